@@ -0,0 +1,57 @@
+// Segment aliases that persist between runs.  They live in aliases.toml in the configuration
+// directory (see `dirs`) as a table of from = "to" pairs:
+//
+//     [aliases]
+//     x86_64 = "amd64"
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dirs;
+
+const FILE_NAME: &str = "aliases.toml";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct AliasFile {
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+}
+
+impl AliasFile {
+    pub(crate) fn path() -> Option<PathBuf> {
+        dirs::dir(dirs::Kind::Config).map(|dir| dir.join(FILE_NAME))
+    }
+    pub(crate) fn load() -> std::io::Result<Self> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{} is not valid: {}", path.display(), e),
+                )
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+    pub(crate) fn save(&self) -> std::io::Result<()> {
+        let path = dirs::create(dirs::Kind::Config)?.join(FILE_NAME);
+        let text = toml::to_string(self).map_err(std::io::Error::other)?;
+        crate::write_atomically(&path, text.as_bytes())
+    }
+    pub(crate) fn add(&mut self, from: &str, to: &str) {
+        self.aliases.insert(from.to_string(), to.to_string());
+    }
+    pub(crate) fn remove(&mut self, from: &str) -> bool {
+        self.aliases.remove(from).is_some()
+    }
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
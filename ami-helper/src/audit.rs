@@ -0,0 +1,199 @@
+// The --audit-log record of every AWS call.  The log wraps the HTTP connector the SDK clients
+// send through, so a service only has to be built by `AwsContext` to be covered.  Only the
+// operation, the credential scope, and a short list of request parameters are recorded; headers,
+// signatures, and response bodies never reach the log.
+
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime};
+
+use aws_smithy_client::erase::DynConnector;
+use aws_smithy_http::body::SdkBody;
+use aws_smithy_http::result::ConnectorError;
+use serde_json::{json, Map, Value};
+use tracing::warn;
+
+/// Request parameters worth recording.  The JSON protocol (SSM) names them in the body; the query
+/// protocol (EC2, STS) numbers list members like `ImageId.1`.
+const JSON_PARAMETERS: [&str; 4] = ["Path", "Name", "Names", "Recursive"];
+const QUERY_PARAMETERS: [&str; 1] = ["ImageId"];
+
+pub(crate) struct AuditLog {
+    file: Mutex<File>,
+    calls: AtomicUsize,
+}
+
+impl AuditLog {
+    /// Entries are appended so one log can cover every step of a release run.
+    pub(crate) fn open(path: &str) -> std::io::Result<Arc<Self>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Arc::new(Self {
+            file: Mutex::new(file),
+            calls: AtomicUsize::new(0),
+        }))
+    }
+    /// Wrap `inner` so every request sent through it is logged.
+    pub(crate) fn connector(self: &Arc<Self>, inner: DynConnector) -> DynConnector {
+        DynConnector::new(AuditConnector {
+            inner,
+            log: self.clone(),
+        })
+    }
+    pub(crate) fn summary(&self, selection_digest: Option<&str>) {
+        self.write(json!({
+            "event": "summary",
+            "time": now(),
+            "calls": self.calls.load(Ordering::SeqCst),
+            "selection_digest": selection_digest,
+        }));
+    }
+    // Each entry is one write followed by a flush so a crash loses at most the entry in flight.
+    fn write(&self, entry: Value) {
+        let mut line = crate::redact::redact(&entry.to_string()).into_owned();
+        line.push('\n');
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(error) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            warn!("unable to write to the audit log: {}", error);
+        }
+    }
+}
+
+// The wall clock rather than `clock::now`; the log records when the calls really happened.
+fn now() -> String {
+    crate::clock::format(
+        &aws_smithy_types::DateTime::from(SystemTime::now()),
+        crate::clock::TimeZone::Utc,
+    )
+}
+
+/// What is known about a call before it is sent.
+struct Call {
+    service: Option<String>,
+    region: Option<String>,
+    operation: Option<String>,
+    parameters: Map<String, Value>,
+}
+
+impl Call {
+    fn from_request(request: &http::Request<SdkBody>) -> Self {
+        // `Credential=<access key id>/<date>/<region>/<service>/aws4_request`.  The access key id
+        // is skipped.
+        let scope: Vec<&str> = request
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split("Credential=").nth(1))
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.split('/').collect())
+            .unwrap_or_default();
+        let mut rv = Self {
+            service: scope.get(3).map(|s| s.to_string()),
+            region: scope.get(2).map(|s| s.to_string()),
+            operation: None,
+            parameters: Map::new(),
+        };
+        let body = request.body().bytes().unwrap_or_default();
+        if let Some(target) = request
+            .headers()
+            .get("x-amz-target")
+            .and_then(|v| v.to_str().ok())
+        {
+            rv.operation = target.rsplit('.').next().map(|o| o.to_string());
+            if let Ok(Value::Object(fields)) = serde_json::from_slice::<Value>(body) {
+                for name in JSON_PARAMETERS {
+                    if let Some(value) = fields.get(name) {
+                        rv.parameters.insert(name.to_string(), value.clone());
+                    }
+                }
+            }
+        } else {
+            for (key, value) in std::str::from_utf8(body)
+                .unwrap_or_default()
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+            {
+                let member = key.split('.').next().unwrap_or_default();
+                if key == "Action" {
+                    rv.operation = Some(value.to_string());
+                } else if QUERY_PARAMETERS.contains(&member) {
+                    if let Value::Array(values) = rv
+                        .parameters
+                        .entry(member.to_string())
+                        .or_insert_with(|| Value::Array(Vec::new()))
+                    {
+                        values.push(Value::from(value));
+                    }
+                }
+            }
+        }
+        rv
+    }
+}
+
+#[derive(Clone)]
+struct AuditConnector {
+    inner: DynConnector,
+    log: Arc<AuditLog>,
+}
+
+impl tower::Service<http::Request<SdkBody>> for AuditConnector {
+    type Response = http::Response<SdkBody>;
+    type Error = ConnectorError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<http::Response<SdkBody>, ConnectorError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<SdkBody>) -> Self::Future {
+        let call = Call::from_request(&request);
+        let log = self.log.clone();
+        let started = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let result = response.await;
+            let mut entry = json!({
+                "event": "call",
+                "time": now(),
+                "service": call.service,
+                "operation": call.operation,
+                "region": call.region,
+                "parameters": call.parameters,
+                "duration_ms": started.elapsed().as_millis() as u64,
+            });
+            match &result {
+                Ok(response) => {
+                    let status = response.status();
+                    entry["status"] = Value::from(status.as_u16());
+                    entry["outcome"] = Value::from(if status.is_success() {
+                        "success"
+                    } else {
+                        "error"
+                    });
+                    entry["request_id"] = ["x-amzn-requestid", "x-amz-request-id"]
+                        .iter()
+                        .find_map(|name| response.headers().get(*name))
+                        .and_then(|v| v.to_str().ok())
+                        .map(Value::from)
+                        .unwrap_or(Value::Null);
+                }
+                Err(error) => {
+                    entry["outcome"] = Value::from("error");
+                    entry["error"] = Value::from(error.to_string());
+                }
+            }
+            log.calls.fetch_add(1, Ordering::SeqCst);
+            log.write(entry);
+            result
+        })
+    }
+}
@@ -0,0 +1,160 @@
+// The one place the time is read and timestamps are turned into text.  Timestamps are RFC 3339 in
+// UTC unless a table was asked for local time, and then the offset is always shown so no
+// timestamp is ever a bare local time.
+//
+// AMI_HELPER_NOW, an RFC 3339 timestamp, pins "now" so the staleness, deprecation, and end of life
+// checks can be reproduced.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use once_cell::sync::Lazy;
+
+static NOW: Lazy<Option<SystemTime>> = Lazy::new(|| {
+    let text = std::env::var("AMI_HELPER_NOW").ok()?;
+    let time = parse(&text)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(u64::try_from(time.secs()).ok()?))
+});
+
+pub(crate) fn now() -> SystemTime {
+    NOW.unwrap_or_else(SystemTime::now)
+}
+
+/// Today's date in UTC as YYYY-MM-DD.
+pub(crate) fn today() -> String {
+    format(&DateTime::from(now()), TimeZone::Utc)
+        .chars()
+        .take(10)
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum TimeZone {
+    Utc,
+    Local,
+}
+
+/// The timestamps EC2 and SSM report, like `2024-03-10T06:00:00.000Z`.
+pub(crate) fn parse(text: &str) -> Option<DateTime> {
+    DateTime::from_str(text, Format::DateTime).ok()
+}
+
+/// `2024-03-10T06:00:00Z` in UTC or `2024-03-10T01:00:00-05:00` in local time.  When the local
+/// offset cannot be determined the UTC form is used.
+pub(crate) fn format(time: &DateTime, zone: TimeZone) -> String {
+    let secs = time.secs();
+    let offset = match zone {
+        TimeZone::Utc => None,
+        TimeZone::Local => local_offset(secs),
+    };
+    let local = secs + offset.unwrap_or(0);
+    let (year, month, day) = civil_from_days(local.div_euclid(86400));
+    let seconds = local.rem_euclid(86400);
+    let mut rv = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    match offset {
+        None => rv.push('Z'),
+        Some(offset) => {
+            let sign = if offset < 0 { '-' } else { '+' };
+            let minutes = offset.abs() / 60;
+            rv.push_str(&format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60));
+        }
+    }
+    rv
+}
+
+// Howard Hinnant's days-to-civil algorithm for the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(windows)]
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Seconds east of UTC for the local time zone at `secs` past the epoch.  The offset is looked up
+/// for that instant so each side of a daylight saving change gets its own offset.
+#[cfg(unix)]
+fn local_offset(secs: i64) -> Option<i64> {
+    // The libc crate does not declare tzset.  localtime_r is not required to call it, so a
+    // changed TZ would otherwise go unnoticed.
+    extern "C" {
+        fn tzset();
+    }
+    let time = libc::time_t::try_from(secs).ok()?;
+    // SAFETY: tzset only reads TZ and the zone files.  localtime_r writes nothing but `tm`.
+    unsafe {
+        tzset();
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return None;
+        }
+        // tm_gmtoff is a c_long, which is only 32 bits on some targets.
+        #[allow(clippy::useless_conversion)]
+        Some(i64::from(tm.tm_gmtoff))
+    }
+}
+
+#[cfg(windows)]
+fn local_offset(secs: i64) -> Option<i64> {
+    use windows_sys::Win32::Foundation::SYSTEMTIME;
+    use windows_sys::Win32::System::Time::SystemTimeToTzSpecificLocalTime;
+
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let seconds = secs.rem_euclid(86400);
+    let universal = SYSTEMTIME {
+        wYear: u16::try_from(year).ok()?,
+        wMonth: month as u16,
+        wDayOfWeek: 0,
+        wDay: day as u16,
+        wHour: (seconds / 3600) as u16,
+        wMinute: (seconds / 60 % 60) as u16,
+        wSecond: (seconds % 60) as u16,
+        wMilliseconds: 0,
+    };
+    // SAFETY: A null time zone means the current one.  Only `local` is written.
+    unsafe {
+        let mut local: SYSTEMTIME = std::mem::zeroed();
+        if SystemTimeToTzSpecificLocalTime(std::ptr::null(), &universal, &mut local) == 0 {
+            return None;
+        }
+        let local_secs = days_from_civil(
+            i64::from(local.wYear),
+            i64::from(local.wMonth),
+            i64::from(local.wDay),
+        ) * 86400
+            + i64::from(local.wHour) * 3600
+            + i64::from(local.wMinute) * 60
+            + i64::from(local.wSecond);
+        Some(local_secs - secs)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn local_offset(_secs: i64) -> Option<i64> {
+    None
+}
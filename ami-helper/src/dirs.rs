@@ -0,0 +1,146 @@
+// Where ami-helper keeps files between runs.  Anything that reads or writes a file of its own asks
+// for one of these directories rather than working out a location itself.
+//
+// Each directory is, in order of precedence, the one given on the command line (--config-dir,
+// --cache-dir, --state-dir), the one in AMI_HELPER_CONFIG_DIR, AMI_HELPER_CACHE_DIR, or
+// AMI_HELPER_STATE_DIR, ami-helper under XDG_CONFIG_HOME, XDG_CACHE_HOME, or XDG_STATE_HOME, and
+// finally the platform's usual place.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const APP: &str = "ami-helper";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Kind {
+    Config,
+    Cache,
+    State,
+}
+
+impl Kind {
+    fn index(self) -> usize {
+        match self {
+            Self::Config => 0,
+            Self::Cache => 1,
+            Self::State => 2,
+        }
+    }
+    fn env_var(self) -> &'static str {
+        match self {
+            Self::Config => "AMI_HELPER_CONFIG_DIR",
+            Self::Cache => "AMI_HELPER_CACHE_DIR",
+            Self::State => "AMI_HELPER_STATE_DIR",
+        }
+    }
+    fn xdg_var(self) -> &'static str {
+        match self {
+            Self::Config => "XDG_CONFIG_HOME",
+            Self::Cache => "XDG_CACHE_HOME",
+            Self::State => "XDG_STATE_HOME",
+        }
+    }
+    fn description(self) -> &'static str {
+        match self {
+            Self::Config => "configuration",
+            Self::Cache => "cache",
+            Self::State => "state",
+        }
+    }
+}
+
+static OVERRIDES: Mutex<[Option<PathBuf>; 3]> = Mutex::new([None, None, None]);
+
+/// The directory from the command line, which wins over everything else.
+pub(crate) fn set_override(kind: Kind, path: PathBuf) {
+    let mut overrides = match OVERRIDES.lock() {
+        Ok(overrides) => overrides,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    overrides[kind.index()] = Some(path);
+}
+
+// The XDG specification says empty and relative values are to be ignored.
+fn absolute_var(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+#[cfg(windows)]
+fn platform_dir(kind: Kind) -> Option<PathBuf> {
+    match kind {
+        Kind::Config => absolute_var("APPDATA").map(|d| d.join(APP)),
+        Kind::Cache => absolute_var("LOCALAPPDATA").map(|d| d.join(APP).join("cache")),
+        Kind::State => absolute_var("LOCALAPPDATA").map(|d| d.join(APP).join("state")),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn platform_dir(kind: Kind) -> Option<PathBuf> {
+    let library = absolute_var("HOME")?.join("Library");
+    Some(match kind {
+        Kind::Config => library.join("Application Support").join(APP),
+        Kind::Cache => library.join("Caches").join(APP),
+        Kind::State => library.join("Application Support").join(APP).join("state"),
+    })
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn platform_dir(kind: Kind) -> Option<PathBuf> {
+    let home = absolute_var("HOME")?;
+    Some(match kind {
+        Kind::Config => home.join(".config").join(APP),
+        Kind::Cache => home.join(".cache").join(APP),
+        Kind::State => home.join(".local").join("state").join(APP),
+    })
+}
+
+/// Where the directory is, whether or not it exists yet.  None when nothing says where it
+/// should be, like when HOME is not set.
+pub(crate) fn dir(kind: Kind) -> Option<PathBuf> {
+    let overridden = match OVERRIDES.lock() {
+        Ok(overrides) => overrides[kind.index()].clone(),
+        Err(poisoned) => poisoned.into_inner()[kind.index()].clone(),
+    };
+    overridden
+        .or_else(|| {
+            std::env::var_os(kind.env_var())
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        })
+        .or_else(|| absolute_var(kind.xdg_var()).map(|d| d.join(APP)))
+        .or_else(|| platform_dir(kind))
+}
+
+/// The directory, created if need be.  Directories made here are private to the user on Unix.
+pub(crate) fn create(kind: Kind) -> std::io::Result<PathBuf> {
+    let path = dir(kind).ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "the {} directory could not be determined; set HOME, {}, or {}",
+                kind.description(),
+                kind.xdg_var(),
+                kind.env_var()
+            ),
+        )
+    })?;
+    create_private(&path)?;
+    Ok(path)
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(path)
+}
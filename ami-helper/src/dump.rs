@@ -0,0 +1,123 @@
+// Streaming writers for the raw and dump subcommands.  Each record is written as soon as it arrives so a dump
+// of the whole public parameter space never has to fit in memory.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+
+/// One SSM parameter.  `path` is the path the dump asked for, not the parameter's parent.
+#[derive(Serialize)]
+pub(crate) struct DumpRecord<'a> {
+    pub(crate) path: &'a str,
+    pub(crate) name: &'a str,
+    pub(crate) value: &'a str,
+    pub(crate) version: i64,
+    pub(crate) last_modified: Option<String>,
+}
+
+/// One parameter as the dump subcommand reports it.  `name` keeps the full SSM path.
+#[derive(Serialize)]
+pub(crate) struct NameValue<'a> {
+    pub(crate) path: &'a str,
+    pub(crate) name: &'a str,
+    pub(crate) value: &'a str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DumpFormat {
+    Json,
+    JsonLines,
+    GzipJsonLines,
+}
+
+impl DumpFormat {
+    /// The format is picked by the file name: .json, .jsonl, or .jsonl.gz.
+    pub(crate) fn from_file_name(name: &str) -> Option<Self> {
+        if name.ends_with(".jsonl.gz") {
+            Some(Self::GzipJsonLines)
+        } else if name.ends_with(".jsonl") {
+            Some(Self::JsonLines)
+        } else if name.ends_with(".json") {
+            Some(Self::Json)
+        } else {
+            None
+        }
+    }
+}
+
+enum Sink {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Sink {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Self::Plain(out) => out,
+            Self::Gzip(out) => out,
+        }
+    }
+}
+
+pub(crate) struct DumpWriter {
+    sink: Sink,
+    format: DumpFormat,
+    records: usize,
+}
+
+impl DumpWriter {
+    pub(crate) fn create(path: &str, format: DumpFormat) -> std::io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let sink = match format {
+            DumpFormat::GzipJsonLines => Sink::Gzip(GzEncoder::new(file, Compression::default())),
+            DumpFormat::Json | DumpFormat::JsonLines => Sink::Plain(Box::new(file)),
+        };
+        Self::new(sink, format)
+    }
+    /// Standard output is never compressed; GzipJsonLines is written as JsonLines.
+    pub(crate) fn stdout(format: DumpFormat) -> std::io::Result<Self> {
+        let format = match format {
+            DumpFormat::GzipJsonLines => DumpFormat::JsonLines,
+            format => format,
+        };
+        Self::new(
+            Sink::Plain(Box::new(BufWriter::new(std::io::stdout()))),
+            format,
+        )
+    }
+    fn new(mut sink: Sink, format: DumpFormat) -> std::io::Result<Self> {
+        if format == DumpFormat::Json {
+            sink.writer().write_all(b"[")?;
+        }
+        Ok(Self {
+            sink,
+            format,
+            records: 0,
+        })
+    }
+    pub(crate) fn write<T: Serialize>(&mut self, record: &T) -> std::io::Result<()> {
+        let out = self.sink.writer();
+        if self.format == DumpFormat::Json {
+            out.write_all(if self.records == 0 { b"\n" } else { b",\n" })?;
+            serde_json::to_writer(&mut *out, record)?;
+        } else {
+            serde_json::to_writer(&mut *out, record)?;
+            out.write_all(b"\n")?;
+        }
+        self.records += 1;
+        Ok(())
+    }
+    /// Close the JSON array or the gzip stream and flush everything to the file.
+    pub(crate) fn finish(mut self) -> std::io::Result<()> {
+        if self.format == DumpFormat::Json {
+            self.sink.writer().write_all(b"\n]\n")?;
+        }
+        match self.sink {
+            Sink::Plain(mut out) => out.flush(),
+            Sink::Gzip(out) => out.finish()?.flush(),
+        }
+    }
+}
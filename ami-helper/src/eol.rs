@@ -0,0 +1,107 @@
+// End of life dates for the releases the SSM catalogs still publish.  Ubuntu uses the end of
+// standard support, Debian the end of LTS, and Windows Server the end of extended support.
+//
+// The versions are spelled the way `parse_version` reports them.  Today's date comes from
+// `clock::today`, which compares correctly with the dates in the table as text.
+
+use crate::OperatingSystem;
+
+const END_OF_LIFE: &[(OperatingSystem, &str, &str)] = &[
+    (OperatingSystem::Amazon, "amzn", "2023-12-31"),
+    (OperatingSystem::Amazon, "amzn2", "2026-06-30"),
+    (OperatingSystem::Debian, "8", "2020-06-30"),
+    (OperatingSystem::Debian, "9", "2022-06-30"),
+    (OperatingSystem::Debian, "10", "2024-06-30"),
+    (OperatingSystem::Debian, "11", "2026-08-31"),
+    (OperatingSystem::Ubuntu, "14.04", "2019-04-25"),
+    (OperatingSystem::Ubuntu, "16.04", "2021-04-30"),
+    (OperatingSystem::Ubuntu, "18.04", "2023-05-31"),
+    (OperatingSystem::Ubuntu, "19.04", "2020-01-23"),
+    (OperatingSystem::Ubuntu, "19.10", "2020-07-17"),
+    (OperatingSystem::Ubuntu, "20.04", "2025-05-29"),
+    (OperatingSystem::Ubuntu, "20.10", "2021-07-22"),
+    (OperatingSystem::Ubuntu, "21.04", "2022-01-20"),
+    (OperatingSystem::Ubuntu, "21.10", "2022-07-14"),
+    (OperatingSystem::Ubuntu, "22.10", "2023-07-20"),
+    (OperatingSystem::Ubuntu, "23.04", "2024-01-25"),
+    (OperatingSystem::Ubuntu, "23.10", "2024-07-11"),
+    (OperatingSystem::Ubuntu, "24.10", "2025-07-10"),
+    (OperatingSystem::Windows, "2012", "2023-10-10"),
+    (OperatingSystem::Windows, "2016", "2027-01-12"),
+];
+
+/// The date `version` reached end of life if that date is on or before `today`.
+pub(crate) fn end_of_life(
+    operating_system: OperatingSystem,
+    version: &str,
+    today: &str,
+) -> Option<&'static str> {
+    END_OF_LIFE
+        .iter()
+        .find(|(o, v, _)| *o == operating_system && *v == version)
+        .map(|(_, _, date)| *date)
+        .filter(|date| *date <= today)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::end_of_life;
+    use crate::OperatingSystem;
+
+    // One row per operating system.  A release is past its end of life on the date in the table and
+    // not the day before.
+    #[test]
+    fn boundaries() {
+        for (operating_system, version, date, day_before) in [
+            (OperatingSystem::Amazon, "amzn2", "2026-06-30", "2026-06-29"),
+            (OperatingSystem::Debian, "10", "2024-06-30", "2024-06-29"),
+            (OperatingSystem::Ubuntu, "20.04", "2025-05-29", "2025-05-28"),
+            (OperatingSystem::Windows, "2016", "2027-01-12", "2027-01-11"),
+        ] {
+            assert_eq!(
+                end_of_life(operating_system, version, date),
+                Some(date),
+                "{:?} {} on {}",
+                operating_system,
+                version,
+                date
+            );
+            assert_eq!(
+                end_of_life(operating_system, version, day_before),
+                None,
+                "{:?} {} on {}",
+                operating_system,
+                version,
+                day_before
+            );
+            assert_eq!(
+                end_of_life(operating_system, version, "2099-01-01"),
+                Some(date)
+            );
+        }
+    }
+
+    // A version the table does not list never reaches end of life, nor does a version listed under
+    // another operating system.
+    #[test]
+    fn versions_missing_from_the_table() {
+        for (operating_system, version) in [
+            (OperatingSystem::Amazon, "al2023"),
+            (OperatingSystem::Debian, "12"),
+            (OperatingSystem::Ubuntu, "24.04"),
+            (OperatingSystem::Windows, "2022"),
+            (OperatingSystem::Ubuntu, "10"),
+            (OperatingSystem::Debian, "20.04"),
+            (OperatingSystem::Windows, "amzn2"),
+            (OperatingSystem::Amazon, ""),
+        ] {
+            assert_eq!(
+                end_of_life(operating_system, version, "2099-01-01"),
+                None,
+                "{:?} {}",
+                operating_system,
+                version
+            );
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// The `export` subcommand's file formats.  Each backend turns the selected AMIs into one file
+// another tool reads; the selection itself is exactly what `select` would have chosen.  Adding a
+// format is a new `ExportBackend` plus an `ExportFormat` variant.
+
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::AmiDetail;
+
+pub(crate) trait ExportBackend {
+    fn export(&self, details: &[AmiDetail], writer: &mut dyn Write) -> std::io::Result<()>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ExportFormat {
+    Tfvars,
+    Ansible,
+    Dotenv,
+    GithubOutput,
+    Packer,
+    Json,
+    Csv,
+}
+
+pub(crate) const EXPORT_FORMATS: &[&str] = &[
+    "tfvars",
+    "ansible",
+    "dotenv",
+    "github-output",
+    "packer",
+    "json",
+    "csv",
+];
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tfvars" => Ok(Self::Tfvars),
+            "ansible" => Ok(Self::Ansible),
+            "dotenv" => Ok(Self::Dotenv),
+            "github-output" => Ok(Self::GithubOutput),
+            "packer" => Ok(Self::Packer),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("{} is not an export format", s)),
+        }
+    }
+}
+
+impl ExportFormat {
+    /// `header` is false for --no-header.  Only csv has a header to leave off.
+    pub(crate) fn backend(&self, header: bool) -> Box<dyn ExportBackend> {
+        match self {
+            Self::Tfvars => Box::new(Hcl {
+                single_name: None,
+                ssh_username: false,
+            }),
+            Self::Packer => Box::new(Hcl {
+                single_name: Some("ami_id"),
+                ssh_username: true,
+            }),
+            Self::Ansible => Box::new(Ansible),
+            Self::Dotenv => Box::new(Dotenv),
+            Self::GithubOutput => Box::new(GithubOutput),
+            Self::Json => Box::new(Json),
+            Self::Csv => Box::new(Csv { header }),
+        }
+    }
+}
+
+/// Terraform and Packer variable files.  `single_name` names the variable when exactly one AMI
+/// was selected, like --export-packer-vars does, so a template can use it without knowing which
+/// operating system was chosen.  Packer also gets the login user for its `ssh_username`, named
+/// like the AMI's variable with `_ssh_username` added.
+struct Hcl {
+    single_name: Option<&'static str>,
+    ssh_username: bool,
+}
+
+// The variable name of each AMI.  Names that differ only in punctuation, like 12.1 and 12-1, become
+// the same variable and one would silently replace the other, so that is an error.
+fn variable_names(details: &[AmiDetail]) -> std::io::Result<Vec<String>> {
+    let names: Vec<String> = details
+        .iter()
+        .map(AmiDetail::packer_variable_name)
+        .collect();
+    for (i, name) in names.iter().enumerate() {
+        if let Some(j) = names[..i].iter().position(|n| n == name) {
+            return Err(crate::custom_error(format!(
+                "{} {} and {} {} would both be the variable {}",
+                details[j].operating_system,
+                details[j].name,
+                details[i].operating_system,
+                details[i].name,
+                name
+            )));
+        }
+    }
+    Ok(names)
+}
+
+impl ExportBackend for Hcl {
+    fn export(&self, details: &[AmiDetail], writer: &mut dyn Write) -> std::io::Result<()> {
+        let names = variable_names(details)?;
+        for (detail, name) in details.iter().zip(names) {
+            let name = match (self.single_name, details.len()) {
+                (Some(single_name), 1) => {
+                    writeln!(writer, "{}", detail.to_packer_variable_named(single_name))?;
+                    single_name.to_string()
+                }
+                _ => {
+                    writeln!(writer, "{}", detail.to_packer_variable())?;
+                    name
+                }
+            };
+            if let (true, Some(username)) = (self.ssh_username, &detail.ssh_username) {
+                let name = match (self.single_name, details.len()) {
+                    (Some(_), 1) => "ssh_username".to_string(),
+                    _ => format!("{}_ssh_username", name),
+                };
+                writeln!(writer, "{} = \"{}\"", name, username)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A YAML variables file for `vars_files` or `include_vars`.
+struct Ansible;
+
+impl ExportBackend for Ansible {
+    fn export(&self, details: &[AmiDetail], writer: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(writer, "---")?;
+        for (detail, name) in details.iter().zip(variable_names(details)?) {
+            writeln!(writer, "# {} {}", detail.operating_system, detail.name)?;
+            writeln!(writer, "{}: \"{}\"", name, detail.ami)?;
+        }
+        Ok(())
+    }
+}
+
+/// `NAME=value` lines with the names in upper case as environment variables usually are.
+struct Dotenv;
+
+impl ExportBackend for Dotenv {
+    fn export(&self, details: &[AmiDetail], writer: &mut dyn Write) -> std::io::Result<()> {
+        for (detail, name) in details.iter().zip(variable_names(details)?) {
+            writeln!(writer, "{}={}", name.to_ascii_uppercase(), detail.ami)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lines for the file named by `$GITHUB_OUTPUT`.  Use `--output "$GITHUB_OUTPUT"` from a
+/// workflow step; the outputs are then `steps.<id>.outputs.<name>`.
+struct GithubOutput;
+
+impl ExportBackend for GithubOutput {
+    fn export(&self, details: &[AmiDetail], writer: &mut dyn Write) -> std::io::Result<()> {
+        for (detail, name) in details.iter().zip(variable_names(details)?) {
+            writeln!(writer, "{}={}", name, detail.ami)?;
+        }
+        Ok(())
+    }
+}
+
+struct Json;
+
+impl ExportBackend for Json {
+    fn export(&self, details: &[AmiDetail], writer: &mut dyn Write) -> std::io::Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, details)?;
+        writeln!(writer)
+    }
+}
+
+/// The same header row as `select --format csv`, unless --no-header, and every row ends with a
+/// newline.
+struct Csv {
+    header: bool,
+}
+
+impl ExportBackend for Csv {
+    fn export(&self, details: &[AmiDetail], writer: &mut dyn Write) -> std::io::Result<()> {
+        if self.header {
+            writeln!(writer, "operating_system,name,ami")?;
+        }
+        for detail in details.iter() {
+            writeln!(
+                writer,
+                "{},{},{}",
+                detail.operating_system,
+                crate::csv_escape(&detail.name, ','),
+                detail.ami
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+// Instance types that are a reasonable starting point for each operating system and architecture.
+// The suggestions are informational; nothing here takes part in choosing an AMI.
+//
+// The family table is also where the smoke test gets its instance type so a family only ever has
+// to be matched to an architecture once.
+
+use crate::{Architecture, OperatingSystem};
+
+/// The instance families ami-helper suggests and the architecture each one runs.
+const FAMILIES: [(&str, Architecture); 7] = [
+    ("t3", Architecture::Amd64),
+    ("t3a", Architecture::Amd64),
+    ("m7i", Architecture::Amd64),
+    ("c7i", Architecture::Amd64),
+    ("t4g", Architecture::Arm64),
+    ("m7g", Architecture::Arm64),
+    ("c7g", Architecture::Arm64),
+];
+
+/// The architecture of an instance type's family, like arm64 for m7g.medium.  None when the
+/// family is not in the table.
+fn family_architecture(instance_type: &str) -> Option<Architecture> {
+    let family = instance_type.split('.').next()?;
+    FAMILIES
+        .iter()
+        .find(|(name, _)| *name == family)
+        .map(|(_, architecture)| *architecture)
+}
+
+/// The burstable family the smoke test launches for each architecture.  None for all and both,
+/// which do not say what to launch.
+pub(crate) fn smoke_test_family(architecture: Architecture) -> Option<&'static str> {
+    match architecture {
+        Architecture::All | Architecture::Both => None,
+        Architecture::Amd64 => Some("t3a"),
+        Architecture::Arm64 => Some("t4g"),
+    }
+}
+
+/// The instance type the smoke test and `--format aws-cli` launch an AMI on.  None when the AMI's
+/// architecture is not known.
+pub(crate) fn smoke_test_instance_type(
+    operating_system: OperatingSystem,
+    architecture: Option<Architecture>,
+) -> Option<String> {
+    let architecture = ami_architecture(operating_system, architecture)?;
+    Some(format!("{}.medium", smoke_test_family(architecture)?))
+}
+
+fn ami_architecture(
+    operating_system: OperatingSystem,
+    architecture: Option<Architecture>,
+) -> Option<Architecture> {
+    match (operating_system, architecture) {
+        (_, Some(architecture)) => Some(architecture),
+        // Windows images carry no architecture in their names and are all amd64.
+        (OperatingSystem::Windows, None) => Some(Architecture::Amd64),
+        (_, None) => None,
+    }
+}
+
+// Every operating system and architecture has an entry so adding a variant to either enum does not
+// compile until its suggestions are decided.
+fn table(operating_system: OperatingSystem, architecture: Architecture) -> &'static [&'static str] {
+    use Architecture::{All, Amd64, Arm64, Both};
+    match (operating_system, architecture) {
+        (OperatingSystem::All, _) | (_, All | Both) => &[],
+        (OperatingSystem::Amazon, Amd64) => &["t3.small", "m7i.large"],
+        (OperatingSystem::Amazon, Arm64) => &["t4g.small", "m7g.medium"],
+        (OperatingSystem::Debian, Amd64) => &["t3.small", "m7i.large"],
+        (OperatingSystem::Debian, Arm64) => &["t4g.small", "m7g.medium"],
+        (OperatingSystem::Ubuntu, Amd64) => &["t3.medium", "m7i.large"],
+        (OperatingSystem::Ubuntu, Arm64) => &["t4g.medium", "m7g.large"],
+        // Desktop sessions over RDP need at least 8 GiB.
+        (OperatingSystem::Windows, Amd64) => &["t3.large", "m7i.large", "m7i.xlarge"],
+        (OperatingSystem::Windows, Arm64) => &[],
+        (OperatingSystem::Custom, Amd64) => &["t3.medium", "m7i.large"],
+        (OperatingSystem::Custom, Arm64) => &["t4g.medium", "m7g.large"],
+    }
+}
+
+/// Two or three instance types for an AMI, smallest first.  A type whose family does not run the
+/// AMI's architecture is never suggested.  Empty when the architecture is not known.
+pub(crate) fn recommend(
+    operating_system: OperatingSystem,
+    architecture: Option<Architecture>,
+) -> Vec<&'static str> {
+    let architecture = match ami_architecture(operating_system, architecture) {
+        Some(architecture) => architecture,
+        None => return Vec::new(),
+    };
+    table(operating_system, architecture)
+        .iter()
+        .copied()
+        .filter(|instance_type| family_architecture(instance_type) == Some(architecture))
+        .collect()
+}
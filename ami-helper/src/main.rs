@@ -1,24 +1,118 @@
+mod aliases;
+mod audit;
+mod clock;
+mod dirs;
+mod dump;
+mod eol;
+mod export;
+mod instance_types;
+mod parameter_path;
+mod patterns;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod redact;
+mod render;
+mod suggest;
+
+use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::{hash_map::HashMap, HashSet};
+use std::collections::{hash_map::HashMap, BTreeMap, HashSet};
 use std::env::{var, VarError};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
 use std::ops::BitOr;
 use std::process::{ExitCode, Termination};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use aws_config::environment::region::EnvironmentVariableRegionProvider;
+use aws_config::imds::region::ImdsRegionProvider;
 use aws_config::meta::region::RegionProviderChain;
+use aws_config::profile::{ProfileFileCredentialsProvider, ProfileFileRegionProvider};
+use aws_config::RetryConfig;
+use aws_sdk_ssm::types::SdkError;
 use aws_sdk_ssm::Client;
+use aws_smithy_types::retry::ProvideErrorKind;
 use aws_types::region::Region;
-use clap::{value_t, App, AppSettings, Arg, ArgMatches};
+use aws_types::SdkConfig;
+use clap::{value_t, App, AppSettings, Arg, ArgMatches, ValueSource};
 use futures_util::stream::StreamExt;
 use once_cell::sync::Lazy;
-use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use tracing_subscriber::EnvFilter;
+
+use aliases::AliasFile;
+use export::{ExportFormat, EXPORT_FORMATS};
+use render::RenderCapabilities;
 
 fn custom_error<E>(error: E) -> std::io::Error
 where
     E: Into<Box<dyn std::error::Error + Send + Sync>>,
 {
-    std::io::Error::new(std::io::ErrorKind::Other, error)
+    std::io::Error::other(error)
+}
+
+#[derive(Debug)]
+struct CredentialsExpired {
+    reading: String,
+}
+
+impl std::fmt::Display for CredentialsExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the AWS credentials expired while reading {}",
+            self.reading
+        )
+    }
+}
+
+impl std::error::Error for CredentialsExpired {}
+
+fn is_expired<E: ProvideErrorKind, R>(error: &SdkError<E, R>) -> bool {
+    match error {
+        SdkError::ServiceError { err, .. } => {
+            matches!(
+                err.code(),
+                Some("ExpiredTokenException") | Some("ExpiredToken")
+            )
+        }
+        _ => false,
+    }
+}
+
+// Every client's errors pass through here so expired credentials from SSM, EC2, or STS all become
+// CredentialsExpired, which is what inner_main starts the run over for.
+fn sdk_error<E>(error: SdkError<E>, reading: &str) -> Box<dyn std::error::Error + Send + Sync>
+where
+    E: ProvideErrorKind + std::error::Error + Send + Sync + 'static,
+{
+    if is_expired(&error) {
+        Box::new(CredentialsExpired {
+            reading: reading.to_string(),
+        })
+    } else {
+        Box::new(error)
+    }
+}
+
+#[derive(Debug)]
+struct RetryableServiceError {
+    path: String,
+    code: String,
 }
 
+impl std::fmt::Display for RetryableServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} while reading {}", self.code, self.path)
+    }
+}
+
+impl std::error::Error for RetryableServiceError {}
+
 pub struct UseDisplay<D>
 where
     D: std::fmt::Display,
@@ -45,6 +139,25 @@ where
     }
 }
 
+impl UseDisplay<String> {
+    /// `UseDisplay::error` for a message, without naming the type parameter.
+    pub fn error_str(message: impl Into<String>) -> Self {
+        Self::error(message.into())
+    }
+}
+
+impl From<&str> for UseDisplay<String> {
+    fn from(message: &str) -> Self {
+        Self::error_str(message)
+    }
+}
+
+impl From<String> for UseDisplay<String> {
+    fn from(message: String) -> Self {
+        Self::error(message)
+    }
+}
+
 impl<D> Termination for UseDisplay<D>
 where
     D: std::fmt::Display,
@@ -52,31 +165,110 @@ where
     fn report(self) -> ExitCode {
         if let Some(message) = self.message {
             let text = format!("{}", message);
-            eprintln!("{}", text);
+            eprintln!("{}", redact::redact(&text));
         }
         self.exit_code
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 enum OperatingSystem {
     All,
     Amazon,
     Debian,
     Ubuntu,
     Windows,
+    Custom,
 }
 
 impl OperatingSystem {
-    fn text_width(&self) -> usize {
-        <&str>::from(self).len()
+    fn default_username(&self) -> Option<&'static str> {
+        match self {
+            Self::All => None,
+            Self::Amazon => Some("ec2-user"),
+            Self::Debian => Some("admin"),
+            Self::Ubuntu => Some("ubuntu"),
+            Self::Windows => Some("Administrator"),
+            Self::Custom => None,
+        }
+    }
+    /// The lowercase name used on the command line.
+    fn key(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Amazon => "amazon",
+            Self::Debian => "debian",
+            Self::Ubuntu => "ubuntu",
+            Self::Windows => "windows",
+            Self::Custom => "custom",
+        }
+    }
+    /// The built-in operating systems a command line value selects.  All is every one of them and
+    /// Custom, which has no built-in paths, is none.
+    fn builtins(&self) -> &'static [Self] {
+        match self {
+            Self::All => &[Self::Amazon, Self::Debian, Self::Ubuntu, Self::Windows],
+            Self::Amazon => &[Self::Amazon],
+            Self::Debian => &[Self::Debian],
+            Self::Ubuntu => &[Self::Ubuntu],
+            Self::Windows => &[Self::Windows],
+            Self::Custom => &[],
+        }
     }
 }
 
 impl std::fmt::Display for OperatingSystem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let text: &str = self.into();
-        f.pad(&text)
+        f.pad(text)
+    }
+}
+
+/// The names accepted on the command line.  Custom is only used for --extra-path images.
+impl std::str::FromStr for OperatingSystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "amazon" => Ok(Self::Amazon),
+            "debian" => Ok(Self::Debian),
+            "ubuntu" => Ok(Self::Ubuntu),
+            "windows" => Ok(Self::Windows),
+            _ => Err(format!(
+                "{} is not an operating system; expected all, amazon, debian, ubuntu, or windows",
+                s
+            )),
+        }
+    }
+}
+
+impl Serialize for OperatingSystem {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.into())
+    }
+}
+
+impl<'de> Deserialize<'de> for OperatingSystem {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        [
+            OperatingSystem::All,
+            OperatingSystem::Amazon,
+            OperatingSystem::Debian,
+            OperatingSystem::Ubuntu,
+            OperatingSystem::Windows,
+            OperatingSystem::Custom,
+        ]
+        .into_iter()
+        .find(|os| <&str>::from(os) == text)
+        .ok_or_else(|| serde::de::Error::custom(format!("{} is not an operating system", text)))
     }
 }
 
@@ -94,6 +286,7 @@ impl From<&OperatingSystem> for &str {
             OperatingSystem::Debian => "Debian",
             OperatingSystem::Ubuntu => "Ubuntu",
             OperatingSystem::Windows => "Windows",
+            OperatingSystem::Custom => "Custom",
         }
     }
 }
@@ -106,6 +299,7 @@ impl From<&OperatingSystem> for usize {
             OperatingSystem::Debian => 3,
             OperatingSystem::Ubuntu => 4,
             OperatingSystem::Windows => 5,
+            OperatingSystem::Custom => 6,
         }
     }
 }
@@ -124,19 +318,40 @@ impl PartialOrd for OperatingSystem {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
 enum Architecture {
     All,
+    Both,
     Amd64,
     Arm64,
 }
 
 impl Architecture {
-    fn instance_group(&self) -> &'static str {
+    /// The name segments the preferred filters build a branch for.
+    fn segments(&self) -> &'static [&'static str] {
         match self {
-            Self::All => panic!(),
-            Self::Amd64 => "t3a",
-            Self::Arm64 => "t4g",
+            Self::All | Self::Both => &["amd64", "arm64"],
+            Self::Amd64 => &["amd64"],
+            Self::Arm64 => &["arm64"],
+        }
+    }
+}
+
+/// graviton is accepted as another name for arm64.
+impl std::str::FromStr for Architecture {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "both" => Ok(Self::Both),
+            "amd64" => Ok(Self::Amd64),
+            "arm64" | "graviton" => Ok(Self::Arm64),
+            _ => Err(format!(
+                "{} is not an architecture; expected all, both, amd64, arm64, or graviton",
+                s
+            )),
         }
     }
 }
@@ -145,255 +360,3571 @@ impl From<Architecture> for &str {
     fn from(value: Architecture) -> &'static str {
         match value {
             Architecture::All => "all",
+            Architecture::Both => "both",
             Architecture::Amd64 => "amd64",
             Architecture::Arm64 => "arm64",
         }
     }
 }
 
-#[derive(Debug)]
-struct SelectOptions {
-    operating_system: OperatingSystem,
-    architecture: Architecture,
-    singleton: bool,
-    just_ami: bool,
-    smoke_test: bool,
-    region: String,
+/// Whether the Amazon Linux selection uses the minimal AMIs.  By default the standard AMI is
+/// used and the minimal AMI only stands in for an architecture without one.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum MinimalPreference {
+    Prefer,
+    Exclude,
+    Require,
 }
 
-impl SelectOptions {
-    fn can_only_be_one(&self) -> bool {
-        self.singleton || self.smoke_test
-    }
-    fn include_amazon(&self) -> bool {
-        match self.operating_system {
-            OperatingSystem::All | OperatingSystem::Amazon => true,
-            _ => false,
-        }
-    }
-    fn include_debian(&self) -> bool {
-        match self.operating_system {
-            OperatingSystem::All | OperatingSystem::Debian => true,
-            _ => false,
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum UbuntuVariant {
+    Server,
+    Minimal,
+}
+
+impl UbuntuVariant {
+    /// Canonical publishes the minimal images in their own parameter tree rather than marking
+    /// them with a segment in the server tree.
+    fn path(&self) -> &'static str {
+        match self {
+            Self::Server => "/aws/service/canonical/ubuntu/server",
+            Self::Minimal => "/aws/service/canonical/ubuntu/server-minimal",
         }
     }
-    fn include_ubuntu(&self) -> bool {
-        match self.operating_system {
-            OperatingSystem::All | OperatingSystem::Ubuntu => true,
-            _ => false,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+struct MatrixCell {
+    operating_system: OperatingSystem,
+    architecture: Architecture,
+}
+
+const FULL_MATRIX: [MatrixCell; 6] = [
+    MatrixCell::new(OperatingSystem::Amazon, Architecture::Amd64),
+    MatrixCell::new(OperatingSystem::Amazon, Architecture::Arm64),
+    MatrixCell::new(OperatingSystem::Debian, Architecture::Amd64),
+    MatrixCell::new(OperatingSystem::Debian, Architecture::Arm64),
+    MatrixCell::new(OperatingSystem::Ubuntu, Architecture::Amd64),
+    MatrixCell::new(OperatingSystem::Ubuntu, Architecture::Arm64),
+];
+
+impl MatrixCell {
+    const fn new(operating_system: OperatingSystem, architecture: Architecture) -> Self {
+        Self {
+            operating_system,
+            architecture,
         }
     }
-    fn include_windows(&self) -> bool {
-        match self.operating_system {
-            OperatingSystem::All | OperatingSystem::Windows => true,
-            _ => false,
-        }
+    fn os_name(&self) -> &'static str {
+        self.operating_system.key()
     }
-    fn instance_group(&self) -> &'static str {
-        self.architecture.instance_group()
+    fn instance_type(&self) -> String {
+        let family = instance_types::smoke_test_family(self.architecture)
+            .expect("The full matrix only includes amd64 and arm64.");
+        format!("{}.medium", family)
     }
 }
 
-#[derive(Debug)]
-enum AmiHelperCommand {
-    Select(SelectOptions),
-    Version,
-}
-
-fn build_architecture_arg<'a>() -> Arg<'a> {
-    Arg::new("architecture")
-        .help("Only list AMIs for the selected architecture")
-        .short('a')
-        .long("architecture")
-        .takes_value(true)
-        .multiple(false)
-        .required(false)
-        .value_parser(["all", "amd64", "arm64"])
+impl std::fmt::Display for MatrixCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let architecture: &str = self.architecture.into();
+        write!(f, "{}:{}", self.os_name(), architecture)
+    }
 }
 
-fn build_just_ami_arg<'a>() -> Arg<'a> {
-    Arg::new("just-ami")
-        .help("Output just the selected AMIs")
-        .short('j')
-        .long("just-ami")
-        .conflicts_with("smoke-test")
-        .takes_value(false)
-        .multiple(false)
-        .required(false)
-}
+impl std::str::FromStr for MatrixCell {
+    type Err = String;
 
-fn build_operating_system_arg<'a>() -> Arg<'a> {
-    Arg::new("operating-system")
-        .help("Only list AMIs for the selected operating system")
-        .short('o')
-        .long("operating-system")
-        .takes_value(true)
-        .multiple(false)
-        .required(false)
-        .value_parser(["all", "amazon", "debian", "ubuntu", "windows"])
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (operating_system, architecture) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected os:arch but found {}", s))?;
+        let operating_system = match operating_system.parse() {
+            Ok(
+                operating_system @ (OperatingSystem::Amazon
+                | OperatingSystem::Debian
+                | OperatingSystem::Ubuntu),
+            ) => operating_system,
+            _ => {
+                return Err(format!(
+                    "{} is not in the matrix; expected amazon, debian, or ubuntu",
+                    operating_system
+                ))
+            }
+        };
+        let architecture = match architecture.parse() {
+            Ok(architecture @ (Architecture::Amd64 | Architecture::Arm64)) => architecture,
+            _ => {
+                return Err(format!(
+                    "{} is not in the matrix; expected amd64 or arm64",
+                    architecture
+                ))
+            }
+        };
+        Ok(Self::new(operating_system, architecture))
+    }
 }
 
-fn build_region_arg<'a>() -> Arg<'a> {
-    Arg::new("region")
-        .help("Use this AWS region")
-        .short('r')
-        .long("region")
-        .takes_value(true)
-        .multiple(false)
-        .required(false)
-        .default_value("us-east-2")
+#[derive(Serialize)]
+struct MatrixEntry<'d> {
+    os: &'static str,
+    arch: &'static str,
+    image_id: &'d str,
+    instance_type: String,
 }
 
-fn build_singleton_arg<'a>() -> Arg<'a> {
-    Arg::new("singleton")
-        .help("Exit with an error if more than one AMI is selected")
-        .short('1')
-        .long("singleton")
-        .takes_value(false)
-        .multiple(false)
-        .required(false)
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputField {
+    Os,
+    Name,
+    Ami,
+    Region,
+    Arch,
+    Deprecated,
+    CreatedAt,
 }
 
-fn build_smoke_test_arg<'a>() -> Arg<'a> {
-    Arg::new("smoke-test")
-        .help("Output arguments used in the smoke tests.  This argument implies --singleton.")
-        .short('s')
-        .long("smoke-test")
-        .conflicts_with("just-ami")
-        .requires("architecture")
-        .takes_value(false)
-        .multiple(false)
-        .required(false)
-}
+const OUTPUT_FIELDS: [OutputField; 7] = [
+    OutputField::Os,
+    OutputField::Name,
+    OutputField::Ami,
+    OutputField::Region,
+    OutputField::Arch,
+    OutputField::Deprecated,
+    OutputField::CreatedAt,
+];
 
-pub fn optional<T>(input: Result<T, clap::Error>) -> Result<Option<T>, clap::Error> {
-    match input {
-        Ok(t) => Ok(Some(t)),
-        Err(e) => match e.kind {
-            clap::ErrorKind::ArgumentNotFound => Ok(None),
-            _ => Err(e),
-        },
+impl OutputField {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Os => "os",
+            Self::Name => "name",
+            Self::Ami => "ami",
+            Self::Region => "region",
+            Self::Arch => "arch",
+            Self::Deprecated => "deprecated",
+            Self::CreatedAt => "created_at",
+        }
     }
-}
-
-fn get_architecture_arg(matches: &ArgMatches) -> Result<Architecture, clap::Error> {
-    if let Some(architecture) = optional(value_t!(matches, "architecture", String))? {
-        Ok(match architecture.as_str() {
-            "all" => Architecture::All,
-            "amd64" => Architecture::Amd64,
-            "arm64" => Architecture::Arm64,
-            _ => panic!("The architecture option has a bug.  This state should be unreachable."),
-        })
-    } else {
-        Ok(Architecture::All)
+    /// The column heading used by --format html.
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Os => "OS",
+            Self::Name => "Name",
+            Self::Ami => "AMI",
+            Self::Region => "Region",
+            Self::Arch => "Arch",
+            Self::Deprecated => "Deprecated",
+            Self::CreatedAt => "Created At",
+        }
+    }
+    fn needs_image(&self) -> bool {
+        matches!(self, Self::Deprecated | Self::CreatedAt)
     }
 }
 
-fn get_just_ami_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
-    Ok(matches.is_present("just-ami"))
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputWidth {
+    Natural,
+    Auto,
+    Fixed(usize),
 }
 
-fn get_operating_system_arg(matches: &ArgMatches) -> Result<OperatingSystem, clap::Error> {
-    if let Some(operating_system) = optional(value_t!(matches, "operating-system", String))? {
-        Ok(match operating_system.as_str() {
-            "all" => OperatingSystem::All,
-            "amazon" => OperatingSystem::Amazon,
-            "debian" => OperatingSystem::Debian,
-            "ubuntu" => OperatingSystem::Ubuntu,
-            "windows" => OperatingSystem::Windows,
-            _ => {
-                panic!("The operating-system option has a bug.  This state should be unreachable.")
+impl OutputWidth {
+    fn resolve(&self) -> Option<usize> {
+        match self {
+            Self::Natural => None,
+            Self::Auto => {
+                let size = terminal_size::terminal_size();
+                if size.is_none() {
+                    debug!("stdout is not a terminal; using the natural column widths");
+                }
+                size.map(|(terminal_size::Width(width), _)| width as usize)
             }
-        })
-    } else {
-        Ok(OperatingSystem::All)
+            Self::Fixed(width) => Some(*width),
+        }
     }
 }
 
-fn get_region_arg(matches: &ArgMatches) -> Result<String, clap::Error> {
-    value_t!(matches, "region", String)
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputFormat {
+    Table,
+    Json,
+    #[serde(rename = "jsonl")]
+    JsonLines,
+    IdOnly,
+    Checksums,
+    Csv,
+    Tsv,
+    Tree,
+    Template,
+    Influx,
+    #[serde(rename = "kv")]
+    KeyValue,
+    Html,
+    AwsCli,
+    Toml,
+    #[cfg(feature = "protobuf")]
+    Protobuf,
 }
 
-fn get_singleton_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
-    Ok(matches.is_present("singleton"))
-}
-
-fn get_smoke_test_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
-    Ok(matches.is_present("smoke-test"))
-}
+const OUTPUT_FORMATS: &[&str] = &[
+    "table",
+    "json",
+    "jsonl",
+    "id-only",
+    "checksums",
+    "csv",
+    "tsv",
+    "tree",
+    "template",
+    "influx",
+    "kv",
+    "html",
+    "aws-cli",
+    "toml",
+    #[cfg(feature = "protobuf")]
+    "protobuf",
+];
 
-mod select {
-    use super::SelectOptions;
-    use clap::{App, AppSettings, ArgMatches, SubCommand};
+/// Other names for the formats, as (alias, format).
+const OUTPUT_FORMAT_ALIASES: [(&str, &str); 3] = [
+    ("ndjson", "jsonl"),
+    ("jsonlines", "jsonl"),
+    ("json-lines", "jsonl"),
+];
 
-    pub(crate) const NAME: &str = "select";
+/// Format names and their aliases are accepted in any case.
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
 
-    pub(crate) fn build_subcommand<'a>() -> App<'a> {
-        SubCommand::with_name(NAME)
-            .setting(AppSettings::NoBinaryName)
-            .about("Select the AMIs that are resonable general purpose choices and match the conditions")
-            .arg(super::build_architecture_arg())
-            .arg(super::build_just_ami_arg())
-            .arg(super::build_operating_system_arg())
-            .arg(super::build_region_arg())
-            .arg(super::build_singleton_arg())
-            .arg(super::build_smoke_test_arg())
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let folded = s.to_ascii_lowercase();
+        let name = OUTPUT_FORMAT_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == folded)
+            .map(|(_, name)| *name)
+            .unwrap_or(&folded);
+        match name {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::JsonLines),
+            "id-only" => Ok(Self::IdOnly),
+            "checksums" => Ok(Self::Checksums),
+            "csv" => Ok(Self::Csv),
+            "tsv" => Ok(Self::Tsv),
+            "tree" => Ok(Self::Tree),
+            "template" => Ok(Self::Template),
+            "influx" => Ok(Self::Influx),
+            "kv" => Ok(Self::KeyValue),
+            "html" => Ok(Self::Html),
+            "aws-cli" => Ok(Self::AwsCli),
+            "toml" => Ok(Self::Toml),
+            #[cfg(feature = "protobuf")]
+            "protobuf" => Ok(Self::Protobuf),
+            _ => Err(format!("{} is not an output format", s)),
+        }
     }
+}
 
-    pub(crate) fn get_options(matches: &ArgMatches) -> Result<SelectOptions, clap::Error> {
-        let operating_system = super::get_operating_system_arg(matches)?;
-        let architecture = super::get_architecture_arg(matches)?;
-        let just_ami = super::get_just_ami_arg(matches)?;
-        let singleton = super::get_singleton_arg(matches)?;
-        let smoke_test = super::get_smoke_test_arg(matches)?;
-        let region = super::get_region_arg(matches)?;
-        Ok(SelectOptions {
-            operating_system,
-            architecture,
-            singleton,
-            just_ami,
-            smoke_test,
-            region,
-        })
+/// How --format kv spells the keys.  The words are the operating system and the architecture.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum KeyStyle {
+    Snake,
+    Kebab,
+    Upper,
+}
+
+impl KeyStyle {
+    fn apply(&self, slug: &str) -> String {
+        match self {
+            Self::Snake => slug.to_string(),
+            Self::Kebab => slug.replace('_', "-"),
+            Self::Upper => slug.to_ascii_uppercase(),
+        }
     }
 }
 
-mod version {
-    use clap::{App, AppSettings, SubCommand};
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct KeyValueOptions {
+    separator: String,
+    prefix: String,
+    style: KeyStyle,
+}
 
-    pub(crate) const NAME: &str = "version";
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Virtualization {
+    Hvm,
+    Paravirtual,
+}
 
-    pub(crate) fn build_subcommand<'a>() -> App<'a> {
-        SubCommand::with_name(NAME)
-            .setting(AppSettings::NoBinaryName)
-            .about("Show version information for this program")
+impl From<Virtualization> for &str {
+    fn from(value: Virtualization) -> &'static str {
+        match value {
+            Virtualization::Hvm => "hvm",
+            Virtualization::Paravirtual => "paravirtual",
+        }
     }
 }
 
-fn get_ami_helper_command(args: &Vec<String>) -> Result<Option<AmiHelperCommand>, clap::Error> {
-    let cli = App::new("ami-helper")
-        .setting(AppSettings::NoBinaryName)
-        .setting(AppSettings::DisableVersion)
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .subcommand(select::build_subcommand())
-        .subcommand(version::build_subcommand());
-
-    match cli.get_matches_from_safe(args) {
-        Ok(matches) => match matches.subcommand() {
-            Some((select::NAME, options)) => Ok(Some(AmiHelperCommand::Select(
-                select::get_options(options)?,
-            ))),
-            Some((version::NAME, _x)) => Ok(Some(AmiHelperCommand::Version)),
-            _ => Ok(None),
-        },
-        Err(error) => Err(error),
-    }
+/// The order of the selected AMIs.  Without --sort they are listed in the order the operating
+/// systems were fetched.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum SortKey {
+    Os,
+    Name,
+    Ami,
+    Date,
 }
 
-type BitmaskT = u128;
+/// When the table is colored.  Auto colors a terminal unless the NO_COLOR environment variable
+/// is set; see https://no-color.org.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
 
-#[derive(Clone, Copy, Debug)]
-struct StringBitmask(BitmaskT);
+/// Where the console links for a selected AMI point: the image in EC2 or the public parameter
+/// in Systems Manager.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum HyperlinkTarget {
+    Parameter,
+    Ami,
+}
+
+impl HyperlinkTarget {
+    fn column(&self) -> Column {
+        match self {
+            Self::Parameter => Column::Name,
+            Self::Ami => Column::Ami,
+        }
+    }
+    fn url(&self, region: &str, detail: &AmiDetail) -> String {
+        match self {
+            // The console expects the parameter name percent-encoded twice; a slash is %252F.
+            Self::Parameter => format!(
+                "https://{0}.console.aws.amazon.com/systems-manager/parameters/{1}/description?region={0}",
+                region,
+                percent_encode(&percent_encode(&detail.parameter))
+            ),
+            Self::Ami => format!(
+                "https://{0}.console.aws.amazon.com/ec2/home?region={0}#ImageDetails:imageId={1}",
+                region, detail.ami
+            ),
+        }
+    }
+}
+
+// Everything except the RFC 3986 unreserved characters is encoded.
+fn percent_encode(text: &str) -> String {
+    let mut rv = String::with_capacity(text.len());
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                rv.push(b as char)
+            }
+            _ => rv.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    rv
+}
+
+/// An SSM parameter path to read and how far below it to look.  `max_depth` is enforced client
+/// side by counting the segments of each returned name relative to `path`.  `pattern`, from a
+/// --path wildcard, is matched against the full names the same way.  `arn` is the parameter ARN
+/// the path was given as, kept so its region can be checked once the region is known.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ParameterPath {
+    label: String,
+    path: String,
+    recursive: bool,
+    max_depth: Option<usize>,
+    pattern: Option<String>,
+    arn: Option<String>,
+}
+
+impl ParameterPath {
+    fn builtin(path: &str) -> Self {
+        Self {
+            label: String::new(),
+            path: path.to_string(),
+            recursive: true,
+            max_depth: None,
+            pattern: None,
+            arn: None,
+        }
+    }
+    /// `/my/prefix/*` or `/my/prefix/*/ami-id`.  Everything below the segments before the first
+    /// wildcard is read and the names are matched against the whole pattern, where `*` matches
+    /// any run of characters, slashes included.  The label is the last segment read.
+    fn from_wildcard(pattern: &str) -> Result<Self, String> {
+        if !pattern.starts_with('/') {
+            return Err(format!("{} does not start with /", pattern));
+        }
+        let pattern = pattern.trim_end_matches('/');
+        let segments: Vec<&str> = pattern.split('/').skip(1).collect();
+        let fixed = segments.iter().take_while(|s| !s.contains('*')).count();
+        if fixed == 0 {
+            return Err(format!(
+                "{} must name at least one segment before the first wildcard",
+                pattern
+            ));
+        }
+        Ok(Self {
+            label: segments[fixed - 1].to_string(),
+            path: format!("/{}", segments[..fixed].join("/")),
+            recursive: true,
+            max_depth: None,
+            pattern: (fixed < segments.len()).then(|| pattern.to_string()),
+            arn: None,
+        })
+    }
+    fn accepts(&self, name: &str) -> bool {
+        self.within_depth(name)
+            && self
+                .pattern
+                .as_deref()
+                .map(|p| wildcard_match(p, name))
+                .unwrap_or(true)
+    }
+    fn within_depth(&self, name: &str) -> bool {
+        match self.max_depth {
+            Some(max_depth) => {
+                let relative = name.strip_prefix(&self.path).unwrap_or(name);
+                relative.split('/').filter(|s| !s.is_empty()).count() <= max_depth
+            }
+            None => true,
+        }
+    }
+}
+
+// `*` matches any run of characters.  When a later literal fails to match, the most recent `*`
+// takes one more character and the match resumes from there.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl std::str::FromStr for ParameterPath {
+    type Err = String;
+
+    /// Parse `label=path[:depth=N][:recursive=false]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, rest) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected label=path but found {}", s))?;
+        // A parameter ARN has colons of its own; the path controls follow its sixth field.
+        let arn_length = if rest.starts_with("arn:") {
+            rest.match_indices(':').nth(4).map_or(0, |(i, _)| i + 1)
+        } else {
+            0
+        };
+        let (arn, rest) = rest.split_at(arn_length);
+        let mut parts = rest.split(':');
+        let path = format!("{}{}", arn, parts.next().unwrap_or_default());
+        if label.is_empty() || path.is_empty() {
+            return Err(format!("expected label=path but found {}", s));
+        }
+        let mut rv = Self {
+            label: label.to_string(),
+            path: path.to_string(),
+            recursive: true,
+            max_depth: None,
+            pattern: None,
+            arn: None,
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("depth", depth)) => {
+                    let depth = depth
+                        .parse::<usize>()
+                        .map_err(|_| format!("depth must be a number but found {}", depth))?;
+                    rv.max_depth = Some(depth);
+                }
+                Some(("recursive", recursive)) => {
+                    rv.recursive = recursive.parse::<bool>().map_err(|_| {
+                        format!("recursive must be true or false but found {}", recursive)
+                    })?;
+                }
+                _ => return Err(format!("unknown path control {}", part)),
+            }
+        }
+        Ok(rv)
+    }
+}
+
+// Fields missing from a recording made by an older version take the command line defaults.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+struct SelectOptions {
+    operating_systems: HashSet<OperatingSystem>,
+    architecture: Architecture,
+    singleton: bool,
+    just_ami: bool,
+    include_eol: bool,
+    include_arm64_only: bool,
+    include_amd64_only: bool,
+    eol_warning: bool,
+    lts_only: bool,
+    smoke_test: bool,
+    preferred_kernel: String,
+    minimal_preference: MinimalPreference,
+    region: Option<String>,
+    region_detect: bool,
+    profile: Option<String>,
+    aws_max_attempts: u32,
+    virtualization: Option<Virtualization>,
+    // Seconds since the epoch.
+    since_date: Option<i64>,
+    columns: Vec<Column>,
+    color: ColorChoice,
+    color_scheme: ColorScheme,
+    format: OutputFormat,
+    json_numbers: bool,
+    pretty_json: bool,
+    html_document: bool,
+    endpoint_url: Option<String>,
+    enforce_account: bool,
+    exclude_marketplace: bool,
+    expected_account: Option<String>,
+    export_packer_vars: Option<String>,
+    ssh_username: Option<String>,
+    ubuntu_variant: UbuntuVariant,
+    extra_paths: Vec<ParameterPath>,
+    max_parameters: usize,
+    aliases: Vec<(String, String)>,
+    all_versions: bool,
+    full_matrix: bool,
+    allow_missing: Vec<MatrixCell>,
+    output_width: OutputWidth,
+    output_width_ratio: Vec<(Column, usize)>,
+    output: Option<String>,
+    stale_after_days: Option<u64>,
+    fail_on_stale: bool,
+    no_strip_prefix: bool,
+    normalize_names: bool,
+    output_fields: Vec<OutputField>,
+    template: Option<String>,
+    trace_name: Option<String>,
+    output_stream: bool,
+    warn_on_multiple_versions: bool,
+    with_previous: bool,
+    resolve_launch_templates: bool,
+    retry_on: Vec<String>,
+    retry_on_attempts: u32,
+    checksum: bool,
+    key_value: KeyValueOptions,
+    hyperlink_target: Option<HyperlinkTarget>,
+    show_links: bool,
+    recommend_instance_types: bool,
+    no_os_column: bool,
+    explicit_columns: bool,
+    with_owner_check: bool,
+    owner_allowlist: Vec<String>,
+    audit_log: Option<String>,
+    sort: Option<SortKey>,
+    reverse: bool,
+    per_os_limit: Option<usize>,
+    assert_ami: Option<String>,
+    local_time: bool,
+    describe_filters: bool,
+    version_offset: usize,
+    no_header: bool,
+    no_sort: bool,
+    export: Option<ExportFormat>,
+    #[serde(skip)]
+    record: Option<String>,
+}
+
+impl Default for SelectOptions {
+    fn default() -> Self {
+        let matches = select::build_subcommand()
+            .try_get_matches_from(Vec::<&str>::new())
+            .expect("The select subcommand has no required arguments.");
+        select::get_options(&matches).expect("The select defaults are valid.")
+    }
+}
+
+impl SelectOptions {
+    // A replayed file skips the argument checks so the ones that compare options run here.
+    fn validate(&self) -> Result<(), clap::Error> {
+        let format = self.format;
+        require_format(self.json_numbers, "output-json-numbers", format, JSON_ONLY)?;
+        require_format(self.pretty_json, "pretty-json", format, JSON_ONLY)?;
+        require_format(self.html_document, "html-document", format, HTML_ONLY)?;
+        require_format(self.full_matrix, "full-matrix", format, FULL_MATRIX_FORMATS)?;
+        require_format(
+            !self.output_fields.is_empty(),
+            "output-fields",
+            format,
+            OUTPUT_FIELDS_FORMATS,
+        )?;
+        require_format(
+            self.output_stream,
+            "output-stream",
+            format,
+            OUTPUT_STREAM_FORMATS,
+        )?;
+        if self.export.is_none() {
+            require_format(self.no_header, "no-header", format, NO_HEADER_FORMATS)?;
+        }
+        check_template(format, self.template.as_deref())?;
+        if self.smoke_test && !self.full_matrix {
+            check_smoke_test_architecture(self.architecture)?;
+        }
+        if let Some(username) = &self.ssh_username {
+            check_ssh_username(username)?;
+        }
+        if self.assert_ami.is_some() {
+            check_assert_ami(self.can_only_be_one(), self.full_matrix)?;
+        }
+        Ok(())
+    }
+
+    fn can_only_be_one(&self) -> bool {
+        self.singleton || (self.smoke_test && !self.full_matrix)
+    }
+    // `create` builds the filter for the newest version when passed 0, the runner-up when passed
+    // 1, and so on.  --version-offset picks which one is preferred.  With --include-eol the
+    // filters for the older releases that have reached end of life are added to the one for the
+    // preferred version.
+    // The preferred version is returned along with the filter so an empty selection can be
+    // explained.
+    fn preferred_filter<F>(
+        &self,
+        operating_system: OperatingSystem,
+        mut create: F,
+    ) -> Result<(Box<dyn StringBitmaskFilter>, Option<String>), std::io::Error>
+    where
+        F: FnMut(usize) -> Option<VersionFilter>,
+    {
+        if self.all_versions {
+            return Ok((Box::new(AlwaysTrueFilter::new()), None));
+        }
+        let (preferred, version) = match create(self.version_offset) {
+            Some(preferred) => (preferred.filter, preferred.version),
+            None => {
+                let available = (0..self.version_offset)
+                    .take_while(|newest| create(*newest).is_some())
+                    .count();
+                if available > 0 {
+                    return Err(custom_error(format!(
+                        "version-offset {} is out of range for {}; {} {} available",
+                        self.version_offset,
+                        operating_system,
+                        available,
+                        if available == 1 {
+                            "version is"
+                        } else {
+                            "versions are"
+                        }
+                    )));
+                }
+                return Ok((Box::new(OrFilter::new()), None));
+            }
+        };
+        if !self.include_eol {
+            return Ok((preferred, Some(version)));
+        }
+        let today = clock::today();
+        let mut rv = OrFilter::new();
+        rv.push_boxed(preferred);
+        for newest in self.version_offset + 1.. {
+            match create(newest) {
+                Some(older) => {
+                    if eol::end_of_life(operating_system, &older.version, &today).is_some() {
+                        rv.push_boxed(older.filter);
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok((Box::new(rv), Some(version)))
+    }
+    // `create` is passed the position of the version just older than the preferred one.
+    fn previous_filter<F>(&self, create: F) -> Option<Box<dyn StringBitmaskFilter>>
+    where
+        F: FnOnce(usize) -> Option<VersionFilter>,
+    {
+        if self.with_previous {
+            create(self.version_offset + 1).map(|previous| previous.filter)
+        } else {
+            None
+        }
+    }
+    // Escape sequences are only useful when they reach a console that interprets them.
+    // --color always and never win over NO_COLOR, which wins over looking at the terminal.  A
+    // file written with --output is not a terminal.
+    fn colors(&self, capabilities: RenderCapabilities) -> ColorScheme {
+        let enabled = match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none()
+                    && self.output.is_none()
+                    && capabilities.ansi
+                    && std::io::stdout().is_terminal()
+            }
+        };
+        if enabled {
+            self.color_scheme.clone()
+        } else {
+            ColorScheme::default()
+        }
+    }
+    // The OS column is dropped on request or, when the columns were not picked on the command
+    // line, when every row is for the same operating system.
+    fn omit_os_column(&self, details: &[AmiDetail]) -> bool {
+        self.no_os_column
+            || (!self.explicit_columns
+                && details
+                    .windows(2)
+                    .all(|pair| pair[0].operating_system == pair[1].operating_system))
+    }
+    fn hyperlinks(&self, capabilities: RenderCapabilities) -> Option<HyperlinkTarget> {
+        if self.output.is_none() && capabilities.ansi {
+            self.hyperlink_target
+        } else {
+            None
+        }
+    }
+    // The full matrix has no Windows cells.
+    fn includes(&self, operating_system: OperatingSystem) -> bool {
+        self.operating_systems.contains(&operating_system)
+            && !(self.full_matrix && operating_system == OperatingSystem::Windows)
+    }
+    fn instance_group(&self) -> Option<&'static str> {
+        instance_types::smoke_test_family(self.architecture)
+    }
+}
+
+#[derive(Debug)]
+struct CheckOptions {
+    region: Option<String>,
+    profile: Option<String>,
+}
+
+#[derive(Debug)]
+struct KarpenterOptions {
+    region: Option<String>,
+    profile: Option<String>,
+    endpoint_url: Option<String>,
+    k8s_version: String,
+    architecture: Architecture,
+    node_class_name: String,
+}
+
+#[derive(Debug)]
+struct RawOptions {
+    region: Option<String>,
+    profile: Option<String>,
+    endpoint_url: Option<String>,
+    paths: Vec<String>,
+    out: Option<(String, dump::DumpFormat)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DumpOutputFormat {
+    Text,
+    Json,
+    JsonLines,
+}
+
+#[derive(Debug)]
+struct DumpOptions {
+    region: Option<String>,
+    profile: Option<String>,
+    endpoint_url: Option<String>,
+    operating_system: OperatingSystem,
+    format: DumpOutputFormat,
+}
+
+/// Every path the operating systems are selected from.
+const BUILTIN_PATHS: [&str; 5] = [
+    "/aws/service/ami-amazon-linux-latest",
+    "/aws/service/debian/release",
+    "/aws/service/canonical/ubuntu/server",
+    "/aws/service/canonical/ubuntu/server-minimal",
+    "/aws/service/ami-windows-latest",
+];
+
+/// The built-in paths for one operating system.  All covers Amazon Linux, Debian, and Ubuntu;
+/// the Windows catalog is large and only dumped when asked for by name.
+fn builtin_paths(operating_system: OperatingSystem) -> Vec<&'static str> {
+    let prefixes: &[&str] = match operating_system {
+        OperatingSystem::All => &[
+            "/aws/service/ami-amazon-linux-latest",
+            "/aws/service/debian/",
+            "/aws/service/canonical/",
+        ],
+        OperatingSystem::Amazon => &["/aws/service/ami-amazon-linux-latest"],
+        OperatingSystem::Debian => &["/aws/service/debian/"],
+        OperatingSystem::Ubuntu => &["/aws/service/canonical/"],
+        OperatingSystem::Windows => &["/aws/service/ami-windows-latest"],
+        OperatingSystem::Custom => &[],
+    };
+    BUILTIN_PATHS
+        .iter()
+        .copied()
+        .filter(|path| prefixes.iter().any(|prefix| path.starts_with(prefix)))
+        .collect()
+}
+
+#[derive(Debug)]
+enum AliasCommand {
+    Add { from: String, to: String },
+    List,
+    Remove { from: String },
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum AmiHelperCommand {
+    Alias(AliasCommand),
+    Check(CheckOptions),
+    Dump(DumpOptions),
+    Export(SelectOptions),
+    Karpenter(KarpenterOptions),
+    Raw(RawOptions),
+    Select(SelectOptions),
+    Version,
+}
+
+const BUILTIN_ALIASES: [(&str, &str); 1] = [("x86_64", "amd64")];
+
+fn build_add_alias_arg<'a>() -> Arg<'a> {
+    Arg::new("add-alias")
+        .help("Treat the name segment <from> as equivalent to <to>.  The syntax is from=to.  The built-in alias x86_64=amd64 is always added unless --no-aliases is used.")
+        .long("add-alias")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .required(false)
+}
+
+fn build_allow_missing_arg<'a>() -> Arg<'a> {
+    Arg::new("allow-missing")
+        .help("With --full-matrix, do not fail when this os:arch cell has no AMI")
+        .long("allow-missing")
+        .requires("full-matrix")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .required(false)
+}
+
+fn build_all_versions_arg<'a>() -> Arg<'a> {
+    Arg::new("all-versions")
+        .help("List every version the catalog offers instead of just the preferred version for each operating system.  The architecture filter still applies.  Every version is already listed so it cannot be used with --include-eol, --version-offset, --singleton, or --smoke-test.  Without --per-os-limit a note warns that the list can be long.")
+        .long("all-versions")
+        .visible_alias("no-preferred-filter")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with_all(&["include-eol", "singleton", "smoke-test", "version-offset"])
+}
+
+fn build_architecture_arg<'a>() -> Arg<'a> {
+    Arg::new("architecture")
+        .help("Only list AMIs for the selected architecture; both means amd64 and arm64 while all includes every architecture")
+        .short('a')
+        .long("architecture")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(["all", "both", "amd64", "arm64", "graviton"])
+        .ignore_case(true)
+}
+
+fn build_assert_ami_arg<'a>() -> Arg<'a> {
+    Arg::new("assert-ami")
+        .help("Exit with an error unless the single selected AMI is this one, to catch a newly published image deliberately.  Requires --singleton or --smoke-test without --full-matrix.")
+        .long("assert-ami")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_audit_log_arg<'a>() -> Arg<'a> {
+    Arg::new("audit-log")
+        .help("Append a JSON Lines entry to this file for every AWS call, then a summary with the digest of the selection")
+        .long("audit-log")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_aws_max_attempts_arg<'a>() -> Arg<'a> {
+    Arg::new("aws-max-attempts")
+        .help("Maximum attempts the AWS SDK makes for each request (AWS-level retries).  1 disables retries.")
+        .long("aws-max-attempts")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("3")
+}
+
+fn build_cache_dir_arg<'a>() -> Arg<'a> {
+    Arg::new("cache-dir")
+        .help("Keep cached files in this directory instead of the one from AMI_HELPER_CACHE_DIR, XDG_CACHE_HOME, or the platform default")
+        .long("cache-dir")
+        .takes_value(true)
+        .allow_invalid_utf8(true)
+        .multiple(false)
+        .required(false)
+        .global(true)
+}
+
+fn build_checksum_arg<'a>() -> Arg<'a> {
+    Arg::new("checksum")
+        .help("Also print a SHA-256 of the selected (os, name, ami) tuples to stderr.  The same selection gives the same checksum in every format.")
+        .long("checksum")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("output-stream")
+}
+
+fn build_color_arg<'a>() -> Arg<'a> {
+    Arg::new("color")
+        .help("When to color each row by operating system.  auto colors a terminal unless the NO_COLOR environment variable is set; always and never override NO_COLOR.  --color alone means always and uses the default color scheme unless --color-scheme picks another.")
+        .long("color")
+        .takes_value(true)
+        .min_values(0)
+        .default_missing_value("always")
+        .multiple(false)
+        .required(false)
+        .value_parser(["auto", "always", "never"])
+        .conflicts_with("no-color")
+}
+
+fn build_no_color_arg<'a>() -> Arg<'a> {
+    Arg::new("no-color")
+        .help("Do not color the output, the same as --color never")
+        .long("no-color")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_color_scheme_arg<'a>() -> Arg<'a> {
+    Arg::new("color-scheme")
+        .help("Color each row by operating system using the selected palette; custom takes the colors from the --os-color-* options")
+        .long("color-scheme")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(COLOR_SCHEMES)
+}
+
+fn build_os_color_arg<'a>(name: &'a str) -> Arg<'a> {
+    Arg::new(name)
+        .help("ANSI SGR parameters, like 32 or 1;38;5;208, for the operating system with --color-scheme custom")
+        .long(name)
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_columns_arg<'a>() -> Arg<'a> {
+    Arg::new("columns")
+        .help("Comma separated list of the columns to output")
+        .long("columns")
+        .takes_value(true)
+        .multiple_values(true)
+        .use_value_delimiter(true)
+        .require_value_delimiter(true)
+        .required(false)
+        .value_parser([
+            "os",
+            "name",
+            "original-name",
+            "ami",
+            "previous",
+            "launch-template",
+            "link",
+            "owner",
+            "created",
+            "username",
+            "instance-types",
+        ])
+        .default_value("os,name,ami")
+}
+
+fn build_exclude_marketplace_arg<'a>() -> Arg<'a> {
+    Arg::new("exclude-marketplace")
+        .help("Drop AWS Marketplace AMIs, which require accepting a subscription before launch (requires ec2:DescribeImages)")
+        .long("exclude-marketplace")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("output-stream")
+}
+
+fn build_config_dir_arg<'a>() -> Arg<'a> {
+    Arg::new("config-dir")
+        .help("Read and write configuration, like the aliases, in this directory instead of the one from AMI_HELPER_CONFIG_DIR, XDG_CONFIG_HOME, or the platform default")
+        .long("config-dir")
+        .takes_value(true)
+        .allow_invalid_utf8(true)
+        .multiple(false)
+        .required(false)
+        .global(true)
+}
+
+fn build_describe_filters_arg<'a>() -> Arg<'a> {
+    Arg::new("describe-filters")
+        .help("Print the filters the selection applies to each operating system, including the version chosen from the catalog, then exit without listing AMIs")
+        .long("describe-filters")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with_all(&["output-stream", "full-matrix"])
+}
+
+fn build_endpoint_url_arg<'a>() -> Arg<'a> {
+    Arg::new("endpoint-url")
+        .help("Send the AWS requests to this URL instead of the regional endpoints, for example a local mock of SSM and EC2")
+        .long("endpoint-url")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_enforce_account_arg<'a>() -> Arg<'a> {
+    Arg::new("enforce-account")
+        .help("Check that the credentials belong to --expected-account before selecting (requires sts:GetCallerIdentity)")
+        .long("enforce-account")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .requires("expected-account")
+}
+
+fn build_expected_account_arg<'a>() -> Arg<'a> {
+    Arg::new("expected-account")
+        .help("The 12 digit AWS account ID the credentials are expected to belong to")
+        .long("expected-account")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_export_packer_vars_arg<'a>() -> Arg<'a> {
+    Arg::new("export-packer-vars")
+        .help("Also write the selected AMIs to this Packer variables file (.pkrvars.hcl)")
+        .long("export-packer-vars")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_export_format_arg<'a>() -> Arg<'a> {
+    Arg::new("format")
+        .help("Write the selected AMIs in this format.  tfvars and packer are HCL variable files, ansible is a YAML variables file, dotenv and github-output are name=value lines, and json and csv list the operating system, name, and AMI of each.")
+        .long("format")
+        .takes_value(true)
+        .multiple(false)
+        .required(true)
+        .possible_values(EXPORT_FORMATS)
+}
+
+fn build_extra_path_arg<'a>() -> Arg<'a> {
+    Arg::new("extra-path")
+        .help("Also list the AMIs under this SSM path.  The syntax is label=path[:depth=N][:recursive=false] where depth limits how many segments below the path are considered.")
+        .long("extra-path")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .required(false)
+}
+
+fn build_path_arg<'a>() -> Arg<'a> {
+    Arg::new("path")
+        .help("List the AMIs under this SSM path instead of the built-in operating systems.  * in the path matches any run of characters, like /my/prefix/* or /my/prefix/*/ami-id.  The names are labeled with the last segment before the first *.")
+        .long("path")
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .required(false)
+        .conflicts_with_all(&["operating-system", "operating-system-list", "full-matrix"])
+}
+
+fn build_full_matrix_arg<'a>() -> Arg<'a> {
+    Arg::new("full-matrix")
+        .help("With --smoke-test, output arguments for every amazon, debian, and ubuntu / amd64 and arm64 combination.  Each combination must select exactly one AMI.")
+        .long("full-matrix")
+        .requires("smoke-test")
+        .conflicts_with_all(&[
+            "architecture",
+            "operating-system",
+            "operating-system-list",
+            "extra-path",
+        ])
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_fail_on_stale_arg<'a>() -> Arg<'a> {
+    Arg::new("fail-on-stale")
+        .help("Exit with an error instead of a warning when a selected AMI parameter is stale")
+        .long("fail-on-stale")
+        .conflicts_with("no-stale-check")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_format_arg<'a>() -> Arg<'a> {
+    Arg::new("format")
+        .help("Output the selected AMIs in this format.  jsonl is one JSON object per line and can also be spelled ndjson, jsonlines, or json-lines.  tsv is csv separated by tabs.  aws-cli is an aws ec2 run-instances command per AMI.  toml is an [[ami]] table per AMI.  Names are accepted in any case.")
+        .long("format")
+        .conflicts_with("just-ami")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(clap::builder::PossibleValuesParser::new(
+            OUTPUT_FORMATS.iter().map(|name| {
+                clap::builder::PossibleValue::new(name).aliases(
+                    OUTPUT_FORMAT_ALIASES
+                        .iter()
+                        .filter(|(_, format)| format == name)
+                        .map(|(alias, _)| *alias),
+                )
+            }),
+        ))
+        .ignore_case(true)
+        .default_value("table")
+}
+
+fn build_dump_format_arg<'a>() -> Arg<'a> {
+    Arg::new("format")
+        .help("Output the parameters as name=value lines, a JSON array of {path, name, value} objects, or one such object per line")
+        .long("format")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(["text", "json", "jsonl"])
+        .default_value("text")
+}
+
+fn build_ignore_case_arg<'a>() -> Arg<'a> {
+    Arg::new("ignore-case")
+        .help("Accept operating system and architecture values in any case (Ubuntu, AMD64)")
+        .long("ignore-case")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_k8s_version_arg<'a>() -> Arg<'a> {
+    Arg::new("k8s-version")
+        .help("Kubernetes version of the EKS optimized AMIs, like 1.30")
+        .long("k8s-version")
+        .takes_value(true)
+        .multiple(false)
+        .required(true)
+}
+
+fn build_lts_only_arg<'a>() -> Arg<'a> {
+    Arg::new("lts-only")
+        .help("Only consider Ubuntu long term support releases (even years, .04).  Other operating systems are not affected.")
+        .long("lts-only")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_html_document_arg<'a>() -> Arg<'a> {
+    Arg::new("html-document")
+        .help("With --format html, output a complete HTML page rather than just the table")
+        .long("html-document")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_hyperlink_target_arg<'a>() -> Arg<'a> {
+    Arg::new("hyperlink-target")
+        .help("In a console table, link the AMI column to the EC2 console or the Name column to the public parameter in the Systems Manager console")
+        .long("hyperlink-target")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(["parameter", "ami"])
+}
+
+fn build_include_amd64_only_arg<'a>() -> Arg<'a> {
+    Arg::new("include-amd64-only")
+        .help("With --architecture all or both, also select the amd64 AMIs that have no arm64 counterpart")
+        .long("include-amd64-only")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with_all(&["singleton", "smoke-test"])
+}
+
+fn build_include_arm64_only_arg<'a>() -> Arg<'a> {
+    Arg::new("include-arm64-only")
+        .help("With --architecture all or both, also select the arm64 AMIs that have no amd64 counterpart, like releases published only for Graviton")
+        .long("include-arm64-only")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with_all(&["singleton", "smoke-test"])
+}
+
+fn build_include_eol_arg<'a>() -> Arg<'a> {
+    Arg::new("include-eol")
+        .help("Also select the older releases that have reached end of life, like Ubuntu 18.04, alongside the newest release")
+        .long("include-eol")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("all-versions")
+}
+
+fn build_just_ami_arg<'a>() -> Arg<'a> {
+    Arg::new("just-ami")
+        .help("Output just the selected AMIs")
+        .short('j')
+        .long("just-ami")
+        .conflicts_with("smoke-test")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_kv_key_style_arg<'a>() -> Arg<'a> {
+    Arg::new("kv-key-style")
+        .help("With --format kv, spell the keys like amazon_amd64 (snake), amazon-amd64 (kebab), or AMAZON_AMD64 (upper)")
+        .long("kv-key-style")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(["snake", "kebab", "upper"])
+        .default_value("snake")
+}
+
+fn build_kv_prefix_arg<'a>() -> Arg<'a> {
+    Arg::new("kv-prefix")
+        .help("With --format kv, put this text in front of every key")
+        .long("kv-prefix")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("")
+}
+
+fn build_kv_sep_arg<'a>() -> Arg<'a> {
+    Arg::new("kv-sep")
+        .help("With --format kv, put this text between each key and AMI")
+        .long("kv-sep")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("=")
+}
+
+fn build_out_arg<'a>() -> Arg<'a> {
+    Arg::new("out")
+        .help("Write the dump to this file instead of standard output.  The name picks the format: .json, .jsonl, or .jsonl.gz.")
+        .long("out")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_output_arg<'a>() -> Arg<'a> {
+    Arg::new("output")
+        .help("Write the selection to this file instead of standard output.  The file is replaced atomically so readers never see a partial write.")
+        .long("output")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_output_stream_arg<'a>() -> Arg<'a> {
+    Arg::new("output-stream")
+        .help("Print each operating system as soon as it has been fetched.  Each one is printed as its own table because the column widths cannot account for rows that have not arrived yet.  Only --format table and id-only are supported.")
+        .long("output-stream")
+        .conflicts_with_all(&[
+            "singleton",
+            "smoke-test",
+            "just-ami",
+            "export-packer-vars",
+            "output",
+            "virtualization",
+        ])
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_output_width_arg<'a>() -> Arg<'a> {
+    Arg::new("output-width")
+        .help("Size the table columns to fill exactly this many characters")
+        .long("output-width")
+        .conflicts_with("output-width-auto")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_output_width_auto_arg<'a>() -> Arg<'a> {
+    Arg::new("output-width-auto")
+        .help("Size the table columns to fill the width of the terminal")
+        .long("output-width-auto")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_output_width_ratio_arg<'a>() -> Arg<'a> {
+    Arg::new("output-width-ratio")
+        .help("Comma separated list of column=weight pairs used to divide the width from --output-width or --output-width-auto.  Columns that are not listed use their default weight (os=15, name=65, original-name=65, ami=20, previous=20, launch-template=20, link=60, owner=15, username=15, instance-types=30).")
+        .long("output-width-ratio")
+        .takes_value(true)
+        .multiple_values(true)
+        .use_value_delimiter(true)
+        .require_value_delimiter(true)
+        .required(false)
+}
+
+fn build_output_fields_arg<'a>() -> Arg<'a> {
+    Arg::new("output-fields")
+        .help("With --format json, jsonl, csv, tsv, or html, comma separated list of the fields to output (os, name, ami, region, arch, deprecated, created_at)")
+        .long("output-fields")
+        .takes_value(true)
+        .multiple_values(true)
+        .use_value_delimiter(true)
+        .require_value_delimiter(true)
+        .required(false)
+}
+
+fn build_output_json_numbers_arg<'a>() -> Arg<'a> {
+    Arg::new("output-json-numbers")
+        .help("With --format json, wrap the AMIs in an object that includes the total count and the count for each operating system")
+        .long("output-json-numbers")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_pretty_json_arg<'a>() -> Arg<'a> {
+    Arg::new("pretty-json")
+        .help(
+            "With --format json, indent the output by two spaces instead of writing it on one line",
+        )
+        .long("pretty-json")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_local_time_arg<'a>() -> Arg<'a> {
+    Arg::new("local-time")
+        .help("Show the times in the table in the local time zone with its UTC offset.  The machine-readable formats are always in UTC.")
+        .long("local-time")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_max_parameters_arg<'a>() -> Arg<'a> {
+    Arg::new("max-parameters")
+        .help("Exit with an error if any SSM path returns more than this many parameters")
+        .long("max-parameters")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("10000")
+}
+
+fn build_node_class_name_arg<'a>() -> Arg<'a> {
+    Arg::new("node-class-name")
+        .help("metadata.name of the EC2NodeClass")
+        .long("node-class-name")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("default")
+}
+
+fn build_no_aliases_arg<'a>() -> Arg<'a> {
+    Arg::new("no-aliases")
+        .help("Do not add the built-in x86_64=amd64 alias")
+        .long("no-aliases")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_no_header_arg<'a>() -> Arg<'a> {
+    Arg::new("no-header")
+        .help("Write only the data rows, without the table's title and rule lines or the csv and tsv header row, so runs can be appended to one file.")
+        .long("no-header")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_no_eol_warning_arg<'a>() -> Arg<'a> {
+    Arg::new("no-eol-warning")
+        .help("Do not note on stderr when a selected release has reached end of life")
+        .long("no-eol-warning")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_minimal_only_arg<'a>() -> Arg<'a> {
+    Arg::new("minimal-only")
+        .help("Select the minimal Amazon Linux AMIs instead of the standard ones")
+        .long("minimal-only")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("no-minimal")
+}
+
+fn build_no_minimal_arg<'a>() -> Arg<'a> {
+    Arg::new("no-minimal")
+        .help("Never select the minimal Amazon Linux AMIs, even for an architecture without a standard AMI")
+        .long("no-minimal")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_no_os_column_arg<'a>() -> Arg<'a> {
+    Arg::new("no-os-column")
+        .help("Leave the OS column out of the table.  Without --columns it is left out whenever a single operating system is selected.")
+        .long("no-os-column")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_no_sort_arg<'a>() -> Arg<'a> {
+    Arg::new("no-sort")
+        .help("List the AMIs of each operating system in the order SSM returns the parameters instead of sorting them, to see the catalog as AWS publishes it")
+        .long("no-sort")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("sort")
+}
+
+fn build_no_stale_check_arg<'a>() -> Arg<'a> {
+    Arg::new("no-stale-check")
+        .help("Do not check when the selected AMI parameters were last modified")
+        .long("no-stale-check")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_no_strip_prefix_arg<'a>() -> Arg<'a> {
+    Arg::new("no-strip-prefix")
+        .help("Output the full parameter name instead of removing the prefix the names have in common")
+        .long("no-strip-prefix")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_normalize_names_arg<'a>() -> Arg<'a> {
+    Arg::new("normalize-names")
+        .help("Replace each name with version/architecture so the names look alike across operating systems.  The original name is kept in the original-name column and field.")
+        .long("normalize-names")
+        .conflicts_with("no-strip-prefix")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_operating_system_arg<'a>() -> Arg<'a> {
+    Arg::new("operating-system")
+        .help("Only list AMIs for the selected operating system")
+        .short('o')
+        .long("operating-system")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(["all", "amazon", "debian", "ubuntu", "windows"])
+        .ignore_case(true)
+}
+
+fn build_operating_system_list_arg<'a>() -> Arg<'a> {
+    Arg::new("operating-system-list")
+        .help("Comma separated list of the operating systems to list AMIs for, like ubuntu,debian")
+        .long("operating-system-list")
+        .takes_value(true)
+        .multiple_values(true)
+        .use_value_delimiter(true)
+        .require_value_delimiter(true)
+        .required(false)
+        .value_parser(["all", "amazon", "debian", "ubuntu", "windows"])
+        .ignore_case(true)
+        .conflicts_with("operating-system")
+}
+
+const DEFAULT_REGION: &str = "us-east-2";
+const NEAREST_REGION: &str = "nearest";
+
+/// The Amazon Linux kernel segments published under /aws/service/ami-amazon-linux-latest when
+/// this list was last reviewed.  The "kernel" segment is combined with the one after it.
+const KNOWN_KERNELS: &[&str] = &[
+    "kernel-default",
+    "kernel-6.12",
+    "kernel-6.1",
+    "kernel-5.15",
+    "kernel-5.10",
+    "kernel-5.4",
+    "kernel-4.14",
+];
+
+fn build_paths_arg<'a>() -> Arg<'a> {
+    Arg::new("paths")
+        .help("Comma separated list of the SSM paths to dump.  all dumps the path of every built-in operating system.")
+        .long("paths")
+        .takes_value(true)
+        .multiple_values(true)
+        .use_value_delimiter(true)
+        .require_value_delimiter(true)
+        .required(true)
+}
+
+fn build_owner_allowlist_arg<'a>() -> Arg<'a> {
+    Arg::new("owner-allowlist")
+        .help("Comma separated list of account IDs, besides the current account, that --with-owner-check accepts as AMI owners")
+        .long("owner-allowlist")
+        .takes_value(true)
+        .multiple_values(true)
+        .use_value_delimiter(true)
+        .require_value_delimiter(true)
+        .required(false)
+        .requires("with-owner-check")
+}
+
+fn build_per_os_limit_arg<'a>() -> Arg<'a> {
+    Arg::new("per-os-limit")
+        .help("List at most this many AMIs for each operating system (and each --extra-path).  The cap is applied to every section as it is selected, before the sections are combined and before --sort.")
+        .long("per-os-limit")
+        .alias("max-results-per-os")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("full-matrix")
+}
+
+fn build_preferred_kernel_arg<'a>() -> Arg<'a> {
+    Arg::new("preferred-kernel")
+        .help("Select the Amazon Linux AMIs with this kernel, like kernel-6.1 or 6.1.  Known kernels are default, 6.12, 6.1, 5.15, 5.10, 5.4, and 4.14; other values are accepted with a warning.")
+        .long("preferred-kernel")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("kernel-default")
+}
+
+fn build_recommend_instance_types_arg<'a>() -> Arg<'a> {
+    Arg::new("recommend-instance-types")
+        .help("Add a column, or an instance_types field to the JSON output, with two or three instance types that suit each AMI.  The suggestions never change which AMIs are selected.")
+        .long("recommend-instance-types")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_record_arg<'a>() -> Arg<'a> {
+    Arg::new("record")
+        .help("Write the options for this selection to a JSON file that --replay can run again")
+        .long("record")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("replay")
+}
+
+fn build_region_arg<'a>() -> Arg<'a> {
+    Arg::new("region")
+        .help("Use this AWS region.  nearest picks the candidate region with the lowest connection latency.  Without it the region comes from AWS_REGION, then the region configured for the profile, then us-east-2.")
+        .short('r')
+        .long("region")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_profile_arg<'a>() -> Arg<'a> {
+    Arg::new("profile")
+        .help("Use the credentials from this profile in the shared AWS configuration.  Without --region the region configured for the profile is used too.")
+        .long("profile")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_region_detect_arg<'a>() -> Arg<'a> {
+    Arg::new("region-detect")
+        .help("Use the region from the EC2 instance metadata when running on EC2.  --region takes precedence.")
+        .long("region-detect")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_replay_arg<'a>() -> Arg<'a> {
+    Arg::new("replay")
+        .help("Run the selection recorded with --record.  The AMIs are fetched again; every other option on the command line is ignored.")
+        .long("replay")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_resolve_launch_templates_arg<'a>() -> Arg<'a> {
+    Arg::new("resolve-launch-templates")
+        .help("Look up the launch template whose default version uses each selected AMI (requires ec2:DescribeLaunchTemplateVersions)")
+        .long("resolve-launch-templates")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("output-stream")
+}
+
+fn build_show_links_arg<'a>() -> Arg<'a> {
+    Arg::new("show-links")
+        .help("Add a column with the console URL for each AMI, or for its parameter with --hyperlink-target parameter")
+        .long("show-links")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_sort_arg<'a>() -> Arg<'a> {
+    Arg::new("sort")
+        .help("Order the selected AMIs by operating system, name, AMI id, or creation date.  date lists the newest first, puts AMIs whose date could not be resolved last, and adds the created column (requires ec2:DescribeImages)")
+        .long("sort")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(["os", "name", "ami", "date"])
+        .conflicts_with_all(&["output-stream", "full-matrix"])
+}
+
+fn build_since_date_arg<'a>() -> Arg<'a> {
+    Arg::new("since-date")
+        .help("Only list AMIs created on or after this date, given as YYYY-MM-DD (requires ec2:DescribeImages)")
+        .long("since-date")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("output-stream")
+}
+
+fn build_reverse_arg<'a>() -> Arg<'a> {
+    Arg::new("reverse")
+        .help("Reverse the order of the selected AMIs, whether it is the default order or the one picked by --sort; --sort date then lists the oldest AMIs first")
+        .long("reverse")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with_all(&["output-stream", "full-matrix"])
+}
+
+fn build_retry_on_arg<'a>() -> Arg<'a> {
+    Arg::new("retry-on")
+        .help("Comma separated list of SSM error code substrings, like Throttling,Timeout, that start the read of a path over.  A path is read at most --retry-on-attempts times; the SDK's own retries from --aws-max-attempts happen within each read.")
+        .long("retry-on")
+        .takes_value(true)
+        .multiple_values(true)
+        .use_value_delimiter(true)
+        .require_value_delimiter(true)
+        .required(false)
+}
+
+fn build_retry_on_attempts_arg<'a>() -> Arg<'a> {
+    Arg::new("retry-on-attempts")
+        .help("Maximum times a path is read when it fails with an error from --retry-on")
+        .long("retry-on-attempts")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("3")
+}
+
+fn build_singleton_arg<'a>() -> Arg<'a> {
+    Arg::new("singleton")
+        .help("Exit with an error if more than one AMI is selected")
+        .short('1')
+        .long("singleton")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_smoke_test_arg<'a>() -> Arg<'a> {
+    Arg::new("smoke-test")
+        .help("Output arguments used in the smoke tests.  This argument implies --singleton.")
+        .short('s')
+        .long("smoke-test")
+        .conflicts_with("just-ami")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_ssh_username_arg<'a>() -> Arg<'a> {
+    Arg::new("ssh-username")
+        .help("Use this login user instead of each operating system's default in the user column, the JSON output, and the Packer variables")
+        .long("ssh-username")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_stale_after_days_arg<'a>() -> Arg<'a> {
+    Arg::new("stale-after-days")
+        .help("Warn when a selected AMI parameter was last modified more than this many days ago")
+        .long("stale-after-days")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("60")
+}
+
+fn build_state_dir_arg<'a>() -> Arg<'a> {
+    Arg::new("state-dir")
+        .help("Keep state carried between runs in this directory instead of the one from AMI_HELPER_STATE_DIR, XDG_STATE_HOME, or the platform default")
+        .long("state-dir")
+        .takes_value(true)
+        .allow_invalid_utf8(true)
+        .multiple(false)
+        .required(false)
+        .global(true)
+}
+
+const TEMPLATE_HELP: &str = "Handlebars template used to render the selection with --format template.  The context has amis (each with os, name, ami, version, architecture and previous), count and region.  \\n and \\t are replaced with a newline and a tab.  Examples:
+    '{{#each amis}}{{os}}={{ami}}\\n{{/each}}'
+    '{{#each amis}}{{#if @index}},{{/if}}{{ami}}{{/each}}\\n'
+    '{{count}} AMIs selected in {{region}}\\n'";
+
+fn build_template_arg<'a>() -> Arg<'a> {
+    Arg::new("template")
+        .help(TEMPLATE_HELP)
+        .long("template")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_trace_name_arg<'a>() -> Arg<'a> {
+    Arg::new("trace-name")
+        .help("Explain on stderr how the filters treated each AMI whose ID is this value or whose name contains it")
+        .long("trace-name")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+}
+
+fn build_ubuntu_variant_arg<'a>() -> Arg<'a> {
+    Arg::new("ubuntu-variant")
+        .help("Select the standard server or the minimal Ubuntu images")
+        .long("ubuntu-variant")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(["server", "minimal"])
+        .default_value("server")
+}
+
+fn build_version_offset_arg<'a>() -> Arg<'a> {
+    Arg::new("version-offset")
+        .help("Prefer the version this many releases behind the newest for each operating system.  0 is the newest, 1 the one before it, and so on.")
+        .long("version-offset")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .default_value("0")
+        .conflicts_with("all-versions")
+}
+
+fn build_virtualization_arg<'a>() -> Arg<'a> {
+    Arg::new("virtualization")
+        .help("Only list AMIs with the selected virtualization type (requires ec2:DescribeImages)")
+        .long("virtualization")
+        .takes_value(true)
+        .multiple(false)
+        .required(false)
+        .value_parser(["hvm", "paravirtual"])
+}
+
+fn build_with_owner_check_arg<'a>() -> Arg<'a> {
+    Arg::new("with-owner-check")
+        .help("Report the account that owns each selected AMI and warn about AMIs shared from other accounts, which the owner can revoke (requires ec2:DescribeImages and sts:GetCallerIdentity)")
+        .long("with-owner-check")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("output-stream")
+}
+
+fn build_with_previous_arg<'a>() -> Arg<'a> {
+    Arg::new("with-previous")
+        .help("Also report the AMI for the previous version of each operating system and architecture")
+        .long("with-previous")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .conflicts_with("all-versions")
+}
+
+fn build_warn_on_multiple_versions_arg<'a>() -> Arg<'a> {
+    Arg::new("warn-on-multiple-versions")
+        .help("Warn when an operating system offers more than one version to choose from")
+        .long("warn-on-multiple-versions")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+}
+
+pub fn optional<T>(input: Result<T, clap::Error>) -> Result<Option<T>, clap::Error> {
+    match input {
+        Ok(t) => Ok(Some(t)),
+        Err(e) => match e.kind {
+            clap::ErrorKind::ArgumentNotFound => Ok(None),
+            _ => Err(e),
+        },
+    }
+}
+
+// The operating system and architecture values are parsed case insensitively so the error can
+// point at --ignore-case.  Without it anything other than lowercase is rejected.
+fn folded_value(matches: &ArgMatches, name: &str) -> Result<Option<String>, clap::Error> {
+    optional(value_t!(matches, name, String))?
+        .map(|value| fold_value(matches, name, &value))
+        .transpose()
+}
+
+// One value of the argument `name` in lower case.  Without --ignore-case it must already be.
+fn fold_value(matches: &ArgMatches, name: &str, value: &str) -> Result<String, clap::Error> {
+    let folded = value.to_ascii_lowercase();
+    if folded != value && !matches.is_present("ignore-case") {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            format!(
+                "\"{}\" isn't a valid value for '--{}'.  Use --ignore-case to accept values in any case.\n",
+                value, name
+            ),
+        ));
+    }
+    Ok(folded)
+}
+
+fn get_aliases_arg(matches: &ArgMatches) -> Result<Vec<(String, String)>, clap::Error> {
+    let mut rv = Vec::new();
+    if !matches.is_present("no-aliases") {
+        for (from, to) in BUILTIN_ALIASES {
+            rv.push((from.to_string(), to.to_string()));
+        }
+    }
+    if let Some(values) = matches.values_of("add-alias") {
+        for value in values {
+            match value.split_once('=') {
+                Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+                    rv.push((from.to_string(), to.to_string()))
+                }
+                _ => {
+                    return Err(clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!(
+                            "Invalid value for 'add-alias': expected from=to but found {}\n",
+                            value
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(rv)
+}
+
+fn get_allow_missing_arg(matches: &ArgMatches) -> Result<Vec<MatrixCell>, clap::Error> {
+    match matches.values_of("allow-missing") {
+        Some(values) => values
+            .map(|v| {
+                let v = match matches.is_present("ignore-case") {
+                    true => v.to_ascii_lowercase(),
+                    false => v.to_string(),
+                };
+                v.parse::<MatrixCell>().map_err(|e| {
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!("Invalid value for 'allow-missing': {}\n", e),
+                    )
+                })
+            })
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn get_all_versions_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("all-versions"))
+}
+
+fn get_architecture_arg(matches: &ArgMatches) -> Result<Architecture, clap::Error> {
+    if let Some(architecture) = folded_value(matches, "architecture")? {
+        architecture.parse().map_err(|e| {
+            clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("Invalid value for 'architecture': {}\n", e),
+            )
+        })
+    } else {
+        Ok(Architecture::All)
+    }
+}
+
+fn get_audit_log_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    optional(value_t!(matches, "audit-log", String))
+}
+
+fn get_aws_max_attempts_arg(matches: &ArgMatches) -> Result<u32, clap::Error> {
+    let aws_max_attempts = value_t!(matches, "aws-max-attempts", u32)?;
+    if aws_max_attempts == 0 {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            "aws-max-attempts must be at least 1\n",
+        ));
+    }
+    Ok(aws_max_attempts)
+}
+
+fn get_color_arg(matches: &ArgMatches) -> Result<ColorChoice, clap::Error> {
+    if matches.is_present("no-color") {
+        return Ok(ColorChoice::Never);
+    }
+    Ok(
+        match optional(value_t!(matches, "color", String))?.as_deref() {
+            None | Some("auto") => ColorChoice::Auto,
+            Some("always") => ColorChoice::Always,
+            Some("never") => ColorChoice::Never,
+            Some(_) => panic!("The color option has a bug.  This state should be unreachable."),
+        },
+    )
+}
+
+fn get_color_scheme_arg(matches: &ArgMatches) -> Result<ColorScheme, clap::Error> {
+    let name = match optional(value_t!(matches, "color-scheme", String))? {
+        Some(name) => name,
+        None if matches.is_present("color") => "default".to_string(),
+        None => "none".to_string(),
+    };
+    if name != "custom" {
+        if let Some(arg) = OS_COLOR_ARGS
+            .iter()
+            .find(|(arg, _)| matches.is_present(arg))
+        {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::ArgumentConflict,
+                format!("{} can only be used with --color-scheme custom\n", arg.0),
+            ));
+        }
+    }
+    match ColorScheme::named(&name) {
+        Some(scheme) => Ok(scheme),
+        None => {
+            let mut scheme = ColorScheme::default();
+            for (arg, operating_system) in OS_COLOR_ARGS.iter() {
+                if let Some(code) = optional(value_t!(matches, arg, String))? {
+                    if code.is_empty() || !code.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                        return Err(clap::Error::raw(
+                            clap::ErrorKind::InvalidValue,
+                            format!(
+                                "{} must be ANSI SGR parameters like 32 or 1;38;5;208, not {}\n",
+                                arg, code
+                            ),
+                        ));
+                    }
+                    scheme.set(*operating_system, code);
+                }
+            }
+            Ok(scheme)
+        }
+    }
+}
+
+// The directories are needed wherever a file is read or written so they are set once, for the
+// whole run, rather than carried in each subcommand's options.
+fn get_dir_args(matches: &ArgMatches) {
+    for (name, kind) in [
+        ("cache-dir", dirs::Kind::Cache),
+        ("config-dir", dirs::Kind::Config),
+        ("state-dir", dirs::Kind::State),
+    ] {
+        if let Some(path) = matches.value_of_os(name) {
+            dirs::set_override(kind, std::path::PathBuf::from(path));
+        }
+    }
+}
+
+fn get_no_header_arg(matches: &ArgMatches, format: OutputFormat) -> Result<bool, clap::Error> {
+    let no_header = matches.is_present("no-header");
+    require_format(no_header, "no-header", format, NO_HEADER_FORMATS)?;
+    Ok(no_header)
+}
+
+fn get_checksum_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("checksum"))
+}
+
+fn get_columns_arg(matches: &ArgMatches) -> Result<Vec<Column>, clap::Error> {
+    Ok(matches
+        .values_of("columns")
+        .map(|values| {
+            values
+                .map(|column| {
+                    Column::from_name(column).unwrap_or_else(|| {
+                        panic!("The columns option has a bug.  This state should be unreachable.")
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn get_explicit_columns_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.value_source("columns") == Some(ValueSource::CommandLine))
+}
+
+fn get_endpoint_url_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    let endpoint_url = optional(value_t!(matches, "endpoint-url", String))?;
+    if let Some(url) = &endpoint_url {
+        if parse_endpoint(url).is_none() {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("--endpoint-url '{}' is not a valid URL\n", url),
+            ));
+        }
+    }
+    Ok(endpoint_url)
+}
+
+fn get_enforce_account_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("enforce-account"))
+}
+
+fn get_assert_ami_arg(
+    matches: &ArgMatches,
+    full_matrix: bool,
+) -> Result<Option<String>, clap::Error> {
+    let assert_ami = optional(value_t!(matches, "assert-ami", String))?;
+    if let Some(ami) = &assert_ami {
+        if !patterns::AMI_ID.is_match(ami) {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!(
+                    "assert-ami must be an AMI id like ami-0123456789abcdef0, not {}\n",
+                    ami
+                ),
+            ));
+        }
+        let can_only_be_one =
+            matches.is_present("singleton") || (matches.is_present("smoke-test") && !full_matrix);
+        check_assert_ami(can_only_be_one, full_matrix)?;
+    }
+    Ok(assert_ami)
+}
+
+// --assert-ami compares against the one AMI selected so the selection must be limited to one.
+fn check_assert_ami(can_only_be_one: bool, full_matrix: bool) -> Result<(), clap::Error> {
+    if full_matrix {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::ArgumentConflict,
+            "assert-ami cannot be used with --full-matrix\n",
+        ));
+    }
+    if !can_only_be_one {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::MissingRequiredArgument,
+            "assert-ami requires --singleton or --smoke-test\n",
+        ));
+    }
+    Ok(())
+}
+
+fn get_expected_account_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    let expected_account = optional(value_t!(matches, "expected-account", String))?;
+    if let Some(account) = &expected_account {
+        if account.len() != 12 || !account.chars().all(|c| c.is_ascii_digit()) {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!(
+                    "expected-account must be a 12 digit AWS account ID, not {}\n",
+                    account
+                ),
+            ));
+        }
+    }
+    Ok(expected_account)
+}
+
+fn get_exclude_marketplace_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("exclude-marketplace"))
+}
+
+fn get_export_packer_vars_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    optional(value_t!(matches, "export-packer-vars", String))
+}
+
+// The path as GetParametersByPath needs it, with a warning for anything that had to be fixed.
+fn normalize_path_arg(
+    name: &str,
+    path: &str,
+    wildcards: bool,
+) -> Result<parameter_path::Normalized, String> {
+    let normalized = parameter_path::normalize(path, wildcards)?;
+    if !normalized.changes.is_empty() {
+        warn!(
+            "--{} {} is read as {}; {}",
+            name,
+            path,
+            normalized.path,
+            normalized.changes.join(", ")
+        );
+    }
+    Ok(normalized)
+}
+
+fn get_extra_path_arg(matches: &ArgMatches) -> Result<Vec<ParameterPath>, clap::Error> {
+    match matches.values_of("extra-path") {
+        Some(values) => values
+            .map(|v| {
+                v.parse::<ParameterPath>()
+                    .and_then(|mut extra_path| {
+                        let normalized = normalize_path_arg("extra-path", &extra_path.path, false)?;
+                        extra_path.path = normalized.path;
+                        extra_path.arn = normalized.arn;
+                        Ok(extra_path)
+                    })
+                    .map_err(|e| {
+                        clap::Error::raw(
+                            clap::ErrorKind::InvalidValue,
+                            format!("Invalid value for 'extra-path': {}\n", e),
+                        )
+                    })
+            })
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn get_path_arg(matches: &ArgMatches) -> Result<Vec<ParameterPath>, clap::Error> {
+    matches
+        .values_of("path")
+        .into_iter()
+        .flatten()
+        .map(|v| {
+            normalize_path_arg("path", v, true)
+                .and_then(|normalized| {
+                    let mut path = ParameterPath::from_wildcard(&normalized.path)?;
+                    path.arn = normalized.arn;
+                    Ok(path)
+                })
+                .map_err(|e| {
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!("Invalid value for 'path': {}\n", e),
+                    )
+                })
+        })
+        .collect()
+}
+
+fn get_full_matrix_arg(matches: &ArgMatches, format: OutputFormat) -> Result<bool, clap::Error> {
+    let full_matrix = matches.is_present("full-matrix");
+    require_format(full_matrix, "full-matrix", format, FULL_MATRIX_FORMATS)?;
+    Ok(full_matrix)
+}
+
+fn get_fail_on_stale_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("fail-on-stale"))
+}
+
+fn get_export_format_arg(matches: &ArgMatches) -> Result<ExportFormat, clap::Error> {
+    let format = value_t!(matches, "format", String)?;
+    format.parse().map_err(|e| {
+        clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            format!("Invalid value for 'format': {}\n", e),
+        )
+    })
+}
+
+fn get_format_arg(matches: &ArgMatches) -> Result<OutputFormat, clap::Error> {
+    let format = value_t!(matches, "format", String)?;
+    format.parse().map_err(|e| {
+        clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            format!("Invalid value for 'format': {}\n", e),
+        )
+    })
+}
+
+const JSON_ONLY: (&[OutputFormat], &str) = (&[OutputFormat::Json], "--format json");
+const HTML_ONLY: (&[OutputFormat], &str) = (&[OutputFormat::Html], "--format html");
+const NO_HEADER_FORMATS: (&[OutputFormat], &str) = (
+    &[OutputFormat::Table, OutputFormat::Csv, OutputFormat::Tsv],
+    "--format table, csv, or tsv",
+);
+const FULL_MATRIX_FORMATS: (&[OutputFormat], &str) = (
+    &[OutputFormat::Table, OutputFormat::Json],
+    "--format table or --format json",
+);
+const OUTPUT_FIELDS_FORMATS: (&[OutputFormat], &str) = (
+    &[
+        OutputFormat::Json,
+        OutputFormat::JsonLines,
+        OutputFormat::Csv,
+        OutputFormat::Tsv,
+        OutputFormat::Html,
+        OutputFormat::Toml,
+    ],
+    "--format json, jsonl, csv, tsv, html, or toml",
+);
+const OUTPUT_STREAM_FORMATS: (&[OutputFormat], &str) = (
+    &[OutputFormat::Table, OutputFormat::IdOnly],
+    "--format table or --format id-only",
+);
+
+/// Options that only change one output format are refused with the others.  The format lists
+/// are shared with the checks for --replay.
+fn require_format(
+    used: bool,
+    name: &str,
+    format: OutputFormat,
+    (formats, description): (&[OutputFormat], &str),
+) -> Result<(), clap::Error> {
+    if used && !formats.contains(&format) {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::ArgumentConflict,
+            format!("{} can only be used with {}\n", name, description),
+        ));
+    }
+    Ok(())
+}
+
+fn get_key_value_args(
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<KeyValueOptions, clap::Error> {
+    if format != OutputFormat::KeyValue {
+        for name in ["kv-key-style", "kv-prefix", "kv-sep"] {
+            if matches.value_source(name) == Some(ValueSource::CommandLine) {
+                return Err(clap::Error::raw(
+                    clap::ErrorKind::ArgumentConflict,
+                    format!("{} can only be used with --format kv\n", name),
+                ));
+            }
+        }
+    }
+    let style = value_t!(matches, "kv-key-style", String)?;
+    Ok(KeyValueOptions {
+        separator: value_t!(matches, "kv-sep", String)?,
+        prefix: value_t!(matches, "kv-prefix", String)?,
+        style: match style.as_str() {
+            "snake" => KeyStyle::Snake,
+            "kebab" => KeyStyle::Kebab,
+            "upper" => KeyStyle::Upper,
+            _ => panic!("The kv-key-style option has a bug.  This state should be unreachable."),
+        },
+    })
+}
+
+fn get_k8s_version_arg(matches: &ArgMatches) -> Result<String, clap::Error> {
+    let k8s_version = value_t!(matches, "k8s-version", String)?;
+    if !patterns::KUBERNETES_VERSION.is_match(&k8s_version) {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            format!(
+                "k8s-version must be a Kubernetes minor version like 1.30, not {}\n",
+                k8s_version
+            ),
+        ));
+    }
+    Ok(k8s_version)
+}
+
+fn get_lts_only_arg(
+    matches: &ArgMatches,
+    operating_systems: &HashSet<OperatingSystem>,
+) -> Result<bool, clap::Error> {
+    let lts_only = matches.is_present("lts-only");
+    if lts_only
+        && operating_systems
+            .iter()
+            .any(|operating_system| *operating_system != OperatingSystem::Ubuntu)
+    {
+        warn!(
+            "--lts-only only applies to Ubuntu; the other operating systems are selected as usual"
+        );
+    }
+    Ok(lts_only)
+}
+
+fn get_html_document_arg(matches: &ArgMatches, format: OutputFormat) -> Result<bool, clap::Error> {
+    let html_document = matches.is_present("html-document");
+    require_format(html_document, "html-document", format, HTML_ONLY)?;
+    Ok(html_document)
+}
+
+fn get_hyperlink_target_arg(matches: &ArgMatches) -> Result<Option<HyperlinkTarget>, clap::Error> {
+    if let Some(target) = optional(value_t!(matches, "hyperlink-target", String))? {
+        Ok(Some(match target.as_str() {
+            "parameter" => HyperlinkTarget::Parameter,
+            "ami" => HyperlinkTarget::Ami,
+            _ => {
+                panic!("The hyperlink-target option has a bug.  This state should be unreachable.")
+            }
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn get_include_amd64_only_arg(
+    matches: &ArgMatches,
+    architecture: Architecture,
+) -> Result<bool, clap::Error> {
+    get_include_unpaired_arg(matches, "include-amd64-only", architecture)
+}
+
+fn check_smoke_test_architecture(architecture: Architecture) -> Result<(), clap::Error> {
+    if matches!(architecture, Architecture::All | Architecture::Both) {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::ArgumentConflict,
+            "smoke-test requires --architecture amd64 or arm64 unless --full-matrix is used\n",
+        ));
+    }
+    Ok(())
+}
+
+fn get_include_arm64_only_arg(
+    matches: &ArgMatches,
+    architecture: Architecture,
+) -> Result<bool, clap::Error> {
+    get_include_unpaired_arg(matches, "include-arm64-only", architecture)
+}
+
+// The unpaired AMIs of one architecture only make sense next to the other architecture's.
+fn get_include_unpaired_arg(
+    matches: &ArgMatches,
+    name: &str,
+    architecture: Architecture,
+) -> Result<bool, clap::Error> {
+    let include = matches.is_present(name);
+    if include && !matches!(architecture, Architecture::All | Architecture::Both) {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::ArgumentConflict,
+            format!(
+                "{} can only be used with --architecture all or both\n",
+                name
+            ),
+        ));
+    }
+    Ok(include)
+}
+
+fn get_include_eol_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("include-eol"))
+}
+
+fn get_just_ami_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("just-ami"))
+}
+
+fn get_describe_filters_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("describe-filters"))
+}
+
+fn get_dump_format_arg(matches: &ArgMatches) -> Result<DumpOutputFormat, clap::Error> {
+    let format = value_t!(matches, "format", String)?;
+    Ok(match format.as_str() {
+        "text" => DumpOutputFormat::Text,
+        "json" => DumpOutputFormat::Json,
+        "jsonl" => DumpOutputFormat::JsonLines,
+        _ => panic!("The format option has a bug.  This state should be unreachable."),
+    })
+}
+
+fn get_out_arg(matches: &ArgMatches) -> Result<Option<(String, dump::DumpFormat)>, clap::Error> {
+    match optional(value_t!(matches, "out", String))? {
+        Some(out) => match dump::DumpFormat::from_file_name(&out) {
+            Some(format) => Ok(Some((out, format))),
+            None => Err(clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("--out {} must end with .json, .jsonl, or .jsonl.gz\n", out),
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+fn get_paths_arg(matches: &ArgMatches) -> Result<Vec<String>, clap::Error> {
+    let mut rv: Vec<String> = Vec::new();
+    for path in matches.values_of("paths").into_iter().flatten() {
+        if path == "all" {
+            rv.extend(BUILTIN_PATHS.iter().map(|p| p.to_string()));
+        } else if path.starts_with('/') {
+            rv.push(path.trim_end_matches('/').to_string());
+        } else {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!(
+                    "--paths {} is not all or an SSM path starting with /\n",
+                    path
+                ),
+            ));
+        }
+    }
+    let mut seen = HashSet::new();
+    rv.retain(|p| seen.insert(p.clone()));
+    Ok(rv)
+}
+
+fn get_output_fields_arg(
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<Vec<OutputField>, clap::Error> {
+    let values = match matches.values_of("output-fields") {
+        Some(values) => values,
+        None => return Ok(Vec::new()),
+    };
+    require_format(true, "output-fields", format, OUTPUT_FIELDS_FORMATS)?;
+    values
+        .map(|value| {
+            OUTPUT_FIELDS
+                .iter()
+                .find(|f| f.name() == value)
+                .copied()
+                .ok_or_else(|| {
+                    let valid: Vec<&str> = OUTPUT_FIELDS.iter().map(|f| f.name()).collect();
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!(
+                            "Invalid value for 'output-fields': {} is not a field.  Valid fields are {}.\n",
+                            value,
+                            valid.join(", ")
+                        ),
+                    )
+                })
+        })
+        .collect()
+}
+
+fn get_output_json_numbers_arg(
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<bool, clap::Error> {
+    let json_numbers = matches.is_present("output-json-numbers");
+    require_format(json_numbers, "output-json-numbers", format, JSON_ONLY)?;
+    Ok(json_numbers)
+}
+
+// JSON Lines is always compact; one record per line is the point of it.
+fn get_pretty_json_arg(matches: &ArgMatches, format: OutputFormat) -> Result<bool, clap::Error> {
+    let pretty_json = matches.is_present("pretty-json");
+    require_format(pretty_json, "pretty-json", format, JSON_ONLY)?;
+    Ok(pretty_json)
+}
+
+fn get_local_time_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("local-time"))
+}
+
+fn get_max_parameters_arg(matches: &ArgMatches) -> Result<usize, clap::Error> {
+    value_t!(matches, "max-parameters", usize)
+}
+
+fn get_per_os_limit_arg(matches: &ArgMatches) -> Result<Option<usize>, clap::Error> {
+    let per_os_limit = optional(value_t!(matches, "per-os-limit", usize))?;
+    if per_os_limit == Some(0) {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            "per-os-limit must be at least 1\n",
+        ));
+    }
+    Ok(per_os_limit)
+}
+
+fn get_output_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    optional(value_t!(matches, "output", String))
+}
+
+fn get_output_stream_arg(matches: &ArgMatches, format: OutputFormat) -> Result<bool, clap::Error> {
+    let output_stream = matches.is_present("output-stream");
+    require_format(
+        output_stream,
+        "output-stream",
+        format,
+        OUTPUT_STREAM_FORMATS,
+    )?;
+    Ok(output_stream)
+}
+
+fn get_output_width_arg(matches: &ArgMatches) -> Result<OutputWidth, clap::Error> {
+    if matches.is_present("output-width-auto") {
+        return Ok(OutputWidth::Auto);
+    }
+    match optional(value_t!(matches, "output-width", usize))? {
+        Some(0) => Err(clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            "output-width must be at least 1\n",
+        )),
+        Some(width) => Ok(OutputWidth::Fixed(width)),
+        None => Ok(OutputWidth::Natural),
+    }
+}
+
+fn get_output_width_ratio_arg(matches: &ArgMatches) -> Result<Vec<(Column, usize)>, clap::Error> {
+    let mut rv = Vec::new();
+    if let Some(values) = matches.values_of("output-width-ratio") {
+        for value in values {
+            let pair = value.split_once('=').and_then(|(column, weight)| {
+                Some((Column::from_name(column)?, weight.parse::<usize>().ok()?))
+            });
+            match pair {
+                Some(pair) => rv.push(pair),
+                None => {
+                    return Err(clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!(
+                            "Invalid value for 'output-width-ratio': expected column=weight but found {}\n",
+                            value
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(rv)
+}
+
+fn get_eol_warning_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(!matches.is_present("no-eol-warning"))
+}
+
+fn get_minimal_preference_arg(matches: &ArgMatches) -> Result<MinimalPreference, clap::Error> {
+    Ok(if matches.is_present("minimal-only") {
+        MinimalPreference::Require
+    } else if matches.is_present("no-minimal") {
+        MinimalPreference::Exclude
+    } else {
+        MinimalPreference::Prefer
+    })
+}
+
+fn get_node_class_name_arg(matches: &ArgMatches) -> Result<String, clap::Error> {
+    value_t!(matches, "node-class-name", String)
+}
+
+fn get_no_os_column_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("no-os-column"))
+}
+
+fn get_no_sort_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("no-sort"))
+}
+
+fn get_no_strip_prefix_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("no-strip-prefix"))
+}
+
+fn get_normalize_names_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("normalize-names"))
+}
+
+fn get_operating_system_arg(matches: &ArgMatches) -> Result<OperatingSystem, clap::Error> {
+    if let Some(operating_system) = folded_value(matches, "operating-system")? {
+        operating_system.parse().map_err(|e| {
+            clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("Invalid value for 'operating-system': {}\n", e),
+            )
+        })
+    } else {
+        Ok(OperatingSystem::All)
+    }
+}
+
+// --operating-system-list or, without it, the operating systems --operating-system selects.
+fn get_operating_systems_arg(
+    matches: &ArgMatches,
+) -> Result<HashSet<OperatingSystem>, clap::Error> {
+    let values = match matches.values_of("operating-system-list") {
+        Some(values) => values,
+        None => {
+            let operating_system = get_operating_system_arg(matches)?;
+            return Ok(operating_system.builtins().iter().copied().collect());
+        }
+    };
+    let mut rv = HashSet::new();
+    for value in values {
+        let folded = fold_value(matches, "operating-system-list", value)?;
+        let operating_system: OperatingSystem = folded.parse().map_err(|e| {
+            clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("Invalid value for 'operating-system-list': {}\n", e),
+            )
+        })?;
+        rv.extend(operating_system.builtins().iter().copied());
+    }
+    Ok(rv)
+}
+
+fn get_profile_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    optional(value_t!(matches, "profile", String))
+}
+
+fn get_owner_allowlist_arg(matches: &ArgMatches) -> Result<Vec<String>, clap::Error> {
+    let values = match matches.values_of("owner-allowlist") {
+        Some(values) => values,
+        None => return Ok(Vec::new()),
+    };
+    values
+        .map(|account| {
+            if account.len() != 12 || !account.chars().all(|c| c.is_ascii_digit()) {
+                Err(clap::Error::raw(
+                    clap::ErrorKind::InvalidValue,
+                    format!(
+                        "owner-allowlist must list 12 digit AWS account IDs, not {}\n",
+                        account
+                    ),
+                ))
+            } else {
+                Ok(account.to_string())
+            }
+        })
+        .collect()
+}
+
+fn get_preferred_kernel_arg(matches: &ArgMatches) -> Result<String, clap::Error> {
+    let kernel = value_t!(matches, "preferred-kernel", String)?;
+    let kernel = if kernel.starts_with("kernel-") {
+        kernel
+    } else {
+        format!("kernel-{}", kernel)
+    };
+    if !KNOWN_KERNELS.contains(&kernel.as_str()) {
+        // Compare the versions alone; the shared "kernel-" prefix would make anything look close.
+        let close: Vec<String> = suggest::closest(
+            &kernel["kernel-".len()..],
+            KNOWN_KERNELS.iter().map(|k| &k["kernel-".len()..]),
+        )
+        .iter()
+        .map(|k| format!("kernel-{}", k))
+        .collect();
+        warn!(
+            "{} is not a known Amazon Linux kernel ({}); using it anyway{}",
+            kernel,
+            KNOWN_KERNELS.join(", "),
+            suggest::did_you_mean(&close)
+        );
+    }
+    Ok(kernel)
+}
+
+fn get_record_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    optional(value_t!(matches, "record", String))
+}
+
+fn get_region_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    optional(value_t!(matches, "region", String))
+}
+
+fn get_region_detect_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("region-detect")
+        && matches.value_source("region") != Some(ValueSource::CommandLine))
+}
+
+fn get_replay_arg(matches: &ArgMatches) -> Result<Option<SelectOptions>, clap::Error> {
+    let path = match optional(value_t!(matches, "replay", String))? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let file = File::open(&path).map_err(|e| {
+        clap::Error::raw(
+            clap::ErrorKind::Io,
+            format!("--replay could not open {}: {}\n", path, e),
+        )
+    })?;
+    let options: SelectOptions =
+        serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| {
+            clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("--replay {} is not a recorded selection: {}\n", path, e),
+            )
+        })?;
+    options.validate()?;
+    Ok(Some(options))
+}
+
+fn get_resolve_launch_templates_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("resolve-launch-templates"))
+}
+
+fn get_singleton_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("singleton"))
+}
+
+// A smoke test launches one instance so it needs one architecture to pick the instance type for.
+fn get_smoke_test_arg(
+    matches: &ArgMatches,
+    architecture: Architecture,
+) -> Result<bool, clap::Error> {
+    let smoke_test = matches.is_present("smoke-test");
+    if smoke_test && !matches.is_present("full-matrix") {
+        if !matches.is_present("architecture") {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::MissingRequiredArgument,
+                "smoke-test requires --architecture unless --full-matrix is used\n",
+            ));
+        }
+        check_smoke_test_architecture(architecture)?;
+        if matches.value_source("format") == Some(ValueSource::CommandLine) {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::ArgumentConflict,
+                "format can only be used with --smoke-test when --full-matrix is used\n",
+            ));
+        }
+    }
+    Ok(smoke_test)
+}
+
+fn get_ssh_username_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    let username = matches.value_of("ssh-username");
+    if let Some(username) = username {
+        check_ssh_username(username)?;
+    }
+    Ok(username.map(str::to_string))
+}
+
+fn check_ssh_username(username: &str) -> Result<(), clap::Error> {
+    if username.is_empty()
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            format!(
+                "ssh-username {:?} is not a user name; use letters, digits, '_', '.', or '-'\n",
+                username
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn get_stale_after_days_arg(matches: &ArgMatches) -> Result<Option<u64>, clap::Error> {
+    if matches.is_present("no-stale-check") {
+        return Ok(None);
+    }
+    Ok(Some(value_t!(matches, "stale-after-days", u64)?))
+}
+
+// The template is compiled here, before anything is fetched, so mistakes are reported with their
+// position without waiting on AWS.
+fn get_template_arg(
+    matches: &ArgMatches,
+    format: OutputFormat,
+) -> Result<Option<String>, clap::Error> {
+    let template = optional(value_t!(matches, "template", String))?
+        .map(|template| unescape_template(&template));
+    check_template(format, template.as_deref())?;
+    Ok(template)
+}
+
+// The template has already been unescaped, so this also checks a replayed one.
+fn check_template(format: OutputFormat, template: Option<&str>) -> Result<(), clap::Error> {
+    match (format, template) {
+        (OutputFormat::Template, Some(template)) => {
+            if let Err(error) = handlebars::Template::compile(template) {
+                return Err(clap::Error::raw(
+                    clap::ErrorKind::InvalidValue,
+                    format!("template is not valid: {}\n", error),
+                ));
+            }
+            Ok(())
+        }
+        (OutputFormat::Template, None) => Err(clap::Error::raw(
+            clap::ErrorKind::MissingRequiredArgument,
+            "--format template requires --template\n",
+        )),
+        (_, Some(_)) => Err(clap::Error::raw(
+            clap::ErrorKind::ArgumentConflict,
+            "template can only be used with --format template\n",
+        )),
+        (_, None) => Ok(()),
+    }
+}
+
+fn get_trace_name_arg(matches: &ArgMatches) -> Result<Option<String>, clap::Error> {
+    optional(value_t!(matches, "trace-name", String))
+}
+
+fn get_since_date_arg(matches: &ArgMatches) -> Result<Option<i64>, clap::Error> {
+    let since_date = match optional(value_t!(matches, "since-date", String))? {
+        Some(since_date) => since_date,
+        None => return Ok(None),
+    };
+    let invalid = || {
+        clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            format!(
+                "--since-date must be a date given as YYYY-MM-DD, not '{}'\n",
+                since_date
+            ),
+        )
+    };
+    if !patterns::CALENDAR_DATE.is_match(&since_date) {
+        return Err(invalid());
+    }
+    aws_smithy_types::DateTime::from_str(
+        &format!("{}T00:00:00Z", since_date),
+        aws_smithy_types::date_time::Format::DateTime,
+    )
+    .map(|since_date| Some(since_date.secs()))
+    .map_err(|_| invalid())
+}
+
+fn get_retry_on_arg(matches: &ArgMatches) -> Result<Vec<String>, clap::Error> {
+    let values = match matches.values_of("retry-on") {
+        Some(values) => values,
+        None => return Ok(Vec::new()),
+    };
+    values
+        .map(|value| {
+            if value.is_empty() {
+                Err(clap::Error::raw(
+                    clap::ErrorKind::InvalidValue,
+                    "retry-on does not accept an empty error code\n",
+                ))
+            } else {
+                Ok(value.to_string())
+            }
+        })
+        .collect()
+}
+
+fn get_recommend_instance_types_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("recommend-instance-types"))
+}
+
+fn get_show_links_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("show-links"))
+}
+
+fn get_ubuntu_variant_arg(matches: &ArgMatches) -> Result<UbuntuVariant, clap::Error> {
+    let ubuntu_variant = value_t!(matches, "ubuntu-variant", String)?;
+    Ok(match ubuntu_variant.as_str() {
+        "server" => UbuntuVariant::Server,
+        "minimal" => UbuntuVariant::Minimal,
+        _ => panic!("The ubuntu-variant option has a bug.  This state should be unreachable."),
+    })
+}
+
+fn get_virtualization_arg(matches: &ArgMatches) -> Result<Option<Virtualization>, clap::Error> {
+    if let Some(virtualization) = optional(value_t!(matches, "virtualization", String))? {
+        Ok(Some(match virtualization.as_str() {
+            "hvm" => Virtualization::Hvm,
+            "paravirtual" => Virtualization::Paravirtual,
+            _ => panic!("The virtualization option has a bug.  This state should be unreachable."),
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn get_retry_on_attempts_arg(matches: &ArgMatches) -> Result<u32, clap::Error> {
+    let retry_on_attempts = value_t!(matches, "retry-on-attempts", u32)?;
+    if retry_on_attempts == 0 {
+        return Err(clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            "retry-on-attempts must be at least 1\n",
+        ));
+    }
+    Ok(retry_on_attempts)
+}
+
+fn get_reverse_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("reverse"))
+}
+
+fn get_sort_arg(matches: &ArgMatches) -> Result<Option<SortKey>, clap::Error> {
+    if let Some(sort) = optional(value_t!(matches, "sort", String))? {
+        Ok(Some(match sort.as_str() {
+            "os" => SortKey::Os,
+            "name" => SortKey::Name,
+            "ami" => SortKey::Ami,
+            "date" => SortKey::Date,
+            _ => panic!("The sort option has a bug.  This state should be unreachable."),
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn get_with_owner_check_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("with-owner-check"))
+}
+
+fn get_with_previous_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("with-previous"))
+}
+
+fn get_version_offset_arg(matches: &ArgMatches) -> Result<usize, clap::Error> {
+    value_t!(matches, "version-offset", usize)
+}
+
+fn get_warn_on_multiple_versions_arg(matches: &ArgMatches) -> Result<bool, clap::Error> {
+    Ok(matches.is_present("warn-on-multiple-versions"))
+}
+
+mod select {
+    use super::{Column, OutputFormat, SelectOptions, SortKey};
+    use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+    use std::collections::HashSet;
+
+    pub(crate) const NAME: &str = "select";
+
+    pub(crate) fn build_subcommand<'a>() -> App<'a> {
+        add_args(
+            SubCommand::with_name(NAME)
+                .setting(AppSettings::NoBinaryName)
+                .about("Select the AMIs that are resonable general purpose choices and match the conditions"),
+            super::build_format_arg(),
+        )
+    }
+
+    /// Every select option with `format` standing in for --format.  `export` shares the options
+    /// but has its own formats.
+    pub(crate) fn add_args<'a>(app: App<'a>, format: Arg<'a>) -> App<'a> {
+        app.arg(super::build_add_alias_arg())
+            .arg(super::build_allow_missing_arg())
+            .arg(super::build_all_versions_arg())
+            .arg(super::build_architecture_arg())
+            .arg(super::build_assert_ami_arg())
+            .arg(super::build_audit_log_arg())
+            .arg(super::build_aws_max_attempts_arg())
+            .arg(super::build_checksum_arg())
+            .arg(super::build_color_arg())
+            .arg(super::build_color_scheme_arg())
+            .arg(super::build_columns_arg())
+            .arg(super::build_describe_filters_arg())
+            .arg(super::build_endpoint_url_arg())
+            .arg(super::build_enforce_account_arg())
+            .arg(super::build_exclude_marketplace_arg())
+            .arg(super::build_expected_account_arg())
+            .arg(super::build_export_packer_vars_arg())
+            .arg(super::build_extra_path_arg())
+            .arg(super::build_fail_on_stale_arg())
+            .arg(format)
+            .arg(super::build_ignore_case_arg())
+            .arg(super::build_full_matrix_arg())
+            .arg(super::build_html_document_arg())
+            .arg(super::build_hyperlink_target_arg())
+            .arg(super::build_include_amd64_only_arg())
+            .arg(super::build_include_arm64_only_arg())
+            .arg(super::build_include_eol_arg())
+            .arg(super::build_just_ami_arg())
+            .arg(super::build_kv_key_style_arg())
+            .arg(super::build_kv_prefix_arg())
+            .arg(super::build_kv_sep_arg())
+            .arg(super::build_local_time_arg())
+            .arg(super::build_lts_only_arg())
+            .arg(super::build_max_parameters_arg())
+            .arg(super::build_minimal_only_arg())
+            .arg(super::build_no_aliases_arg())
+            .arg(super::build_no_color_arg())
+            .arg(super::build_no_eol_warning_arg())
+            .arg(super::build_no_header_arg())
+            .arg(super::build_no_minimal_arg())
+            .arg(super::build_no_os_column_arg())
+            .arg(super::build_no_sort_arg())
+            .arg(super::build_no_stale_check_arg())
+            .arg(super::build_no_strip_prefix_arg())
+            .arg(super::build_normalize_names_arg())
+            .arg(super::build_operating_system_arg())
+            .arg(super::build_operating_system_list_arg())
+            .arg(super::build_os_color_arg("os-color-amazon"))
+            .arg(super::build_os_color_arg("os-color-debian"))
+            .arg(super::build_os_color_arg("os-color-ubuntu"))
+            .arg(super::build_os_color_arg("os-color-windows"))
+            .arg(super::build_output_arg())
+            .arg(super::build_output_fields_arg())
+            .arg(super::build_output_json_numbers_arg())
+            .arg(super::build_output_stream_arg())
+            .arg(super::build_output_width_arg())
+            .arg(super::build_output_width_auto_arg())
+            .arg(super::build_output_width_ratio_arg())
+            .arg(super::build_owner_allowlist_arg())
+            .arg(super::build_path_arg())
+            .arg(super::build_per_os_limit_arg())
+            .arg(super::build_preferred_kernel_arg())
+            .arg(super::build_pretty_json_arg())
+            .arg(super::build_profile_arg())
+            .arg(super::build_recommend_instance_types_arg())
+            .arg(super::build_record_arg())
+            .arg(super::build_region_arg())
+            .arg(super::build_region_detect_arg())
+            .arg(super::build_replay_arg())
+            .arg(super::build_resolve_launch_templates_arg())
+            .arg(super::build_retry_on_arg())
+            .arg(super::build_retry_on_attempts_arg())
+            .arg(super::build_reverse_arg())
+            .arg(super::build_show_links_arg())
+            .arg(super::build_since_date_arg())
+            .arg(super::build_singleton_arg())
+            .arg(super::build_smoke_test_arg())
+            .arg(super::build_sort_arg())
+            .arg(super::build_ssh_username_arg())
+            .arg(super::build_stale_after_days_arg())
+            .arg(super::build_template_arg())
+            .arg(super::build_trace_name_arg())
+            .arg(super::build_ubuntu_variant_arg())
+            .arg(super::build_version_offset_arg())
+            .arg(super::build_virtualization_arg())
+            .arg(super::build_warn_on_multiple_versions_arg())
+            .arg(super::build_with_owner_check_arg())
+            .arg(super::build_with_previous_arg())
+    }
+
+    pub(crate) fn get_options(matches: &ArgMatches) -> Result<SelectOptions, clap::Error> {
+        if let Some(options) = super::get_replay_arg(matches)? {
+            return Ok(options);
+        }
+        let format = super::get_format_arg(matches)?;
+        get_options_with_format(matches, format)
+    }
+
+    pub(crate) fn get_options_with_format(
+        matches: &ArgMatches,
+        format: OutputFormat,
+    ) -> Result<SelectOptions, clap::Error> {
+        let record = super::get_record_arg(matches)?;
+        let operating_systems = super::get_operating_systems_arg(matches)?;
+        let architecture = super::get_architecture_arg(matches)?;
+        let just_ami = super::get_just_ami_arg(matches)?;
+        let include_eol = super::get_include_eol_arg(matches)?;
+        let include_arm64_only = super::get_include_arm64_only_arg(matches, architecture)?;
+        let include_amd64_only = super::get_include_amd64_only_arg(matches, architecture)?;
+        let eol_warning = super::get_eol_warning_arg(matches)?;
+        let lts_only = super::get_lts_only_arg(matches, &operating_systems)?;
+        let singleton = super::get_singleton_arg(matches)?;
+        let smoke_test = super::get_smoke_test_arg(matches, architecture)?;
+        let preferred_kernel = super::get_preferred_kernel_arg(matches)?;
+        let minimal_preference = super::get_minimal_preference_arg(matches)?;
+        let region = super::get_region_arg(matches)?;
+        let region_detect = super::get_region_detect_arg(matches)?;
+        let profile = super::get_profile_arg(matches)?;
+        let aws_max_attempts = super::get_aws_max_attempts_arg(matches)?;
+        let virtualization = super::get_virtualization_arg(matches)?;
+        let since_date = super::get_since_date_arg(matches)?;
+        let mut columns = super::get_columns_arg(matches)?;
+        let explicit_columns = super::get_explicit_columns_arg(matches)?;
+        let no_os_column = super::get_no_os_column_arg(matches)?;
+        let color = super::get_color_arg(matches)?;
+        let color_scheme = super::get_color_scheme_arg(matches)?;
+        let json_numbers = super::get_output_json_numbers_arg(matches, format)?;
+        let pretty_json = super::get_pretty_json_arg(matches, format)?;
+        let no_header = super::get_no_header_arg(matches, format)?;
+        let no_sort = super::get_no_sort_arg(matches)?;
+        let html_document = super::get_html_document_arg(matches, format)?;
+        let endpoint_url = super::get_endpoint_url_arg(matches)?;
+        let enforce_account = super::get_enforce_account_arg(matches)?;
+        let exclude_marketplace = super::get_exclude_marketplace_arg(matches)?;
+        let expected_account = super::get_expected_account_arg(matches)?;
+        let export_packer_vars = super::get_export_packer_vars_arg(matches)?;
+        let ubuntu_variant = super::get_ubuntu_variant_arg(matches)?;
+        let mut extra_paths = super::get_extra_path_arg(matches)?;
+        // --path replaces the built-in operating systems with generic sources.
+        let paths = super::get_path_arg(matches)?;
+        let operating_systems = if paths.is_empty() {
+            operating_systems
+        } else {
+            HashSet::new()
+        };
+        extra_paths.extend(paths);
+        let max_parameters = super::get_max_parameters_arg(matches)?;
+        let aliases = super::get_aliases_arg(matches)?;
+        let all_versions = super::get_all_versions_arg(matches)?;
+        let full_matrix = super::get_full_matrix_arg(matches, format)?;
+        let allow_missing = super::get_allow_missing_arg(matches)?;
+        let output_width = super::get_output_width_arg(matches)?;
+        let output_width_ratio = super::get_output_width_ratio_arg(matches)?;
+        let output = super::get_output_arg(matches)?;
+        let stale_after_days = super::get_stale_after_days_arg(matches)?;
+        let fail_on_stale = super::get_fail_on_stale_arg(matches)?;
+        let no_strip_prefix = super::get_no_strip_prefix_arg(matches)?;
+        let normalize_names = super::get_normalize_names_arg(matches)?;
+        let output_fields = super::get_output_fields_arg(matches, format)?;
+        let template = super::get_template_arg(matches, format)?;
+        let key_value = super::get_key_value_args(matches, format)?;
+        let trace_name = super::get_trace_name_arg(matches)?;
+        let output_stream = super::get_output_stream_arg(matches, format)?;
+        let warn_on_multiple_versions = super::get_warn_on_multiple_versions_arg(matches)?;
+        let with_previous = super::get_with_previous_arg(matches)?;
+        if with_previous && !columns.contains(&Column::Previous) {
+            columns.push(Column::Previous);
+        }
+        let resolve_launch_templates = super::get_resolve_launch_templates_arg(matches)?;
+        let retry_on = super::get_retry_on_arg(matches)?;
+        let retry_on_attempts = super::get_retry_on_attempts_arg(matches)?;
+        let checksum = super::get_checksum_arg(matches)?;
+        if resolve_launch_templates && !columns.contains(&Column::LaunchTemplate) {
+            columns.push(Column::LaunchTemplate);
+        }
+        let hyperlink_target = super::get_hyperlink_target_arg(matches)?;
+        let show_links = super::get_show_links_arg(matches)?;
+        if show_links && !columns.contains(&Column::Link) {
+            columns.push(Column::Link);
+        }
+        let recommend_instance_types = super::get_recommend_instance_types_arg(matches)?;
+        if recommend_instance_types && !columns.contains(&Column::InstanceTypes) {
+            columns.push(Column::InstanceTypes);
+        }
+        let with_owner_check = super::get_with_owner_check_arg(matches)?;
+        let owner_allowlist = super::get_owner_allowlist_arg(matches)?;
+        let audit_log = super::get_audit_log_arg(matches)?;
+        if with_owner_check && !columns.contains(&Column::Owner) {
+            columns.push(Column::Owner);
+        }
+        let sort = super::get_sort_arg(matches)?;
+        let ssh_username = super::get_ssh_username_arg(matches)?;
+        let reverse = super::get_reverse_arg(matches)?;
+        let per_os_limit = super::get_per_os_limit_arg(matches)?;
+        let assert_ami = super::get_assert_ami_arg(matches, full_matrix)?;
+        let local_time = super::get_local_time_arg(matches)?;
+        let describe_filters = super::get_describe_filters_arg(matches)?;
+        let version_offset = super::get_version_offset_arg(matches)?;
+        if sort == Some(SortKey::Date) && !columns.contains(&Column::Created) {
+            columns.push(Column::Created);
+        }
+        Ok(SelectOptions {
+            operating_systems,
+            architecture,
+            singleton,
+            just_ami,
+            include_eol,
+            include_arm64_only,
+            include_amd64_only,
+            eol_warning,
+            lts_only,
+            smoke_test,
+            preferred_kernel,
+            minimal_preference,
+            region,
+            region_detect,
+            profile,
+            aws_max_attempts,
+            virtualization,
+            since_date,
+            columns,
+            color,
+            color_scheme,
+            format,
+            json_numbers,
+            pretty_json,
+            html_document,
+            endpoint_url,
+            enforce_account,
+            exclude_marketplace,
+            expected_account,
+            export_packer_vars,
+            ssh_username,
+            ubuntu_variant,
+            extra_paths,
+            max_parameters,
+            aliases,
+            all_versions,
+            full_matrix,
+            allow_missing,
+            output_width,
+            output_width_ratio,
+            output,
+            stale_after_days,
+            fail_on_stale,
+            no_strip_prefix,
+            normalize_names,
+            output_fields,
+            template,
+            trace_name,
+            output_stream,
+            warn_on_multiple_versions,
+            with_previous,
+            resolve_launch_templates,
+            retry_on,
+            retry_on_attempts,
+            checksum,
+            key_value,
+            hyperlink_target,
+            show_links,
+            recommend_instance_types,
+            no_os_column,
+            explicit_columns,
+            with_owner_check,
+            owner_allowlist,
+            audit_log,
+            sort,
+            reverse,
+            per_os_limit,
+            assert_ami,
+            local_time,
+            describe_filters,
+            version_offset,
+            no_header,
+            no_sort,
+            export: None,
+            record,
+        })
+    }
+}
+
+mod export_subcommand {
+    use super::{ExportFormat, OutputFormat, SelectOptions};
+    use clap::{App, AppSettings, ArgMatches, SubCommand};
+
+    pub(crate) const NAME: &str = "export";
+
+    /// Options that change what is written rather than what is selected.  Export always writes
+    /// the chosen backend so these are refused instead of quietly ignored.
+    const SELECT_ONLY: [&str; 6] = [
+        "describe-filters",
+        "full-matrix",
+        "just-ami",
+        "output-stream",
+        "smoke-test",
+        "template",
+    ];
+
+    pub(crate) fn build_subcommand<'a>() -> App<'a> {
+        super::select::add_args(
+            SubCommand::with_name(NAME)
+                .setting(AppSettings::NoBinaryName)
+                .about(
+                    "Select the AMIs like select does and write them to a file another tool reads",
+                ),
+            super::build_export_format_arg(),
+        )
+    }
+
+    pub(crate) fn get_options(matches: &ArgMatches) -> Result<SelectOptions, clap::Error> {
+        let export = super::get_export_format_arg(matches)?;
+        if let Some(mut options) = super::get_replay_arg(matches)? {
+            options.export = Some(export);
+            return Ok(options);
+        }
+        if let Some(name) = SELECT_ONLY.iter().find(|name| matches.is_present(name)) {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::ArgumentConflict,
+                format!("--{} cannot be used with export\n", name),
+            ));
+        }
+        let mut options = super::select::get_options_with_format(matches, OutputFormat::Table)?;
+        if options.no_header && export != ExportFormat::Csv {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::ArgumentConflict,
+                "no-header can only be used with --format csv\n",
+            ));
+        }
+        options.export = Some(export);
+        Ok(options)
+    }
+}
+
+mod alias {
+    use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+    use super::AliasCommand;
+
+    pub(crate) const NAME: &str = "alias";
+
+    pub(crate) fn build_subcommand<'a>() -> App<'a> {
+        SubCommand::with_name(NAME)
+            .setting(AppSettings::NoBinaryName)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Manage the name segment aliases that are applied to every select")
+            .subcommand(
+                SubCommand::with_name("add")
+                    .about("Treat the name segment <from> as equivalent to <to>")
+                    .arg(Arg::new("from").required(true))
+                    .arg(Arg::new("to").required(true)),
+            )
+            .subcommand(
+                SubCommand::with_name("list").about("Show the built-in and configured aliases"),
+            )
+            .subcommand(
+                SubCommand::with_name("remove")
+                    .about("Remove a configured alias")
+                    .arg(Arg::new("from").required(true)),
+            )
+    }
+
+    pub(crate) fn get_command(matches: &ArgMatches) -> Result<AliasCommand, clap::Error> {
+        match matches.subcommand() {
+            Some(("add", matches)) => Ok(AliasCommand::Add {
+                from: clap::value_t!(matches, "from", String)?,
+                to: clap::value_t!(matches, "to", String)?,
+            }),
+            Some(("list", _)) => Ok(AliasCommand::List),
+            Some(("remove", matches)) => Ok(AliasCommand::Remove {
+                from: clap::value_t!(matches, "from", String)?,
+            }),
+            _ => panic!("The alias subcommand has a bug.  This state should be unreachable."),
+        }
+    }
+}
+
+mod check {
+    use clap::{App, AppSettings, ArgMatches, SubCommand};
+
+    use super::CheckOptions;
+
+    pub(crate) const NAME: &str = "check";
+
+    pub(crate) fn build_subcommand<'a>() -> App<'a> {
+        SubCommand::with_name(NAME)
+            .setting(AppSettings::NoBinaryName)
+            .about("Make one SSM call to check that the credentials and network work")
+            .arg(super::build_profile_arg())
+            .arg(super::build_region_arg())
+    }
+
+    pub(crate) fn get_options(matches: &ArgMatches) -> Result<CheckOptions, clap::Error> {
+        let region = super::get_region_arg(matches)?;
+        let profile = super::get_profile_arg(matches)?;
+        Ok(CheckOptions { region, profile })
+    }
+}
+
+mod karpenter {
+    use clap::{App, AppSettings, ArgMatches, SubCommand};
+
+    use super::KarpenterOptions;
+
+    pub(crate) const NAME: &str = "karpenter";
+
+    pub(crate) fn build_subcommand<'a>() -> App<'a> {
+        SubCommand::with_name(NAME)
+            .setting(AppSettings::NoBinaryName)
+            .about("Output a Karpenter EC2NodeClass with the EKS optimized AMIs for a Kubernetes version")
+            .arg(super::build_architecture_arg())
+            .arg(super::build_endpoint_url_arg())
+            .arg(super::build_ignore_case_arg())
+            .arg(super::build_k8s_version_arg())
+            .arg(super::build_node_class_name_arg())
+            .arg(super::build_profile_arg())
+            .arg(super::build_region_arg())
+    }
+
+    pub(crate) fn get_options(matches: &ArgMatches) -> Result<KarpenterOptions, clap::Error> {
+        let region = super::get_region_arg(matches)?;
+        let profile = super::get_profile_arg(matches)?;
+        let endpoint_url = super::get_endpoint_url_arg(matches)?;
+        let k8s_version = super::get_k8s_version_arg(matches)?;
+        let architecture = super::get_architecture_arg(matches)?;
+        let node_class_name = super::get_node_class_name_arg(matches)?;
+        Ok(KarpenterOptions {
+            region,
+            profile,
+            endpoint_url,
+            k8s_version,
+            architecture,
+            node_class_name,
+        })
+    }
+}
+
+// Named apart from the dump writers module.
+mod dump_subcommand {
+    use clap::{App, AppSettings, ArgMatches, SubCommand};
+
+    use super::DumpOptions;
+
+    pub(crate) const NAME: &str = "dump";
+
+    pub(crate) fn build_subcommand<'a>() -> App<'a> {
+        SubCommand::with_name(NAME)
+            .setting(AppSettings::NoBinaryName)
+            .about("Dump the unprocessed name=value pairs of the built-in AMI paths in one region")
+            .arg(super::build_endpoint_url_arg())
+            .arg(super::build_dump_format_arg())
+            .arg(super::build_ignore_case_arg())
+            .arg(super::build_operating_system_arg())
+            .arg(super::build_profile_arg())
+            .arg(super::build_region_arg())
+    }
+
+    pub(crate) fn get_options(matches: &ArgMatches) -> Result<DumpOptions, clap::Error> {
+        let region = super::get_region_arg(matches)?;
+        let profile = super::get_profile_arg(matches)?;
+        let endpoint_url = super::get_endpoint_url_arg(matches)?;
+        let operating_system = super::get_operating_system_arg(matches)?;
+        let format = super::get_dump_format_arg(matches)?;
+        Ok(DumpOptions {
+            region,
+            profile,
+            endpoint_url,
+            operating_system,
+            format,
+        })
+    }
+}
+
+mod raw {
+    use clap::{App, AppSettings, ArgMatches, SubCommand};
+
+    use super::RawOptions;
+
+    pub(crate) const NAME: &str = "raw";
+
+    pub(crate) fn build_subcommand<'a>() -> App<'a> {
+        SubCommand::with_name(NAME)
+            .setting(AppSettings::NoBinaryName)
+            .about(
+                "Dump every parameter under SSM paths with its value, version, and modified date",
+            )
+            .arg(super::build_endpoint_url_arg())
+            .arg(super::build_out_arg())
+            .arg(super::build_paths_arg())
+            .arg(super::build_profile_arg())
+            .arg(super::build_region_arg())
+    }
+
+    pub(crate) fn get_options(matches: &ArgMatches) -> Result<RawOptions, clap::Error> {
+        let region = super::get_region_arg(matches)?;
+        let profile = super::get_profile_arg(matches)?;
+        let endpoint_url = super::get_endpoint_url_arg(matches)?;
+        let paths = super::get_paths_arg(matches)?;
+        let out = super::get_out_arg(matches)?;
+        Ok(RawOptions {
+            region,
+            profile,
+            endpoint_url,
+            paths,
+            out,
+        })
+    }
+}
+
+mod version {
+    use clap::{App, AppSettings, SubCommand};
+
+    pub(crate) const NAME: &str = "version";
+
+    pub(crate) fn build_subcommand<'a>() -> App<'a> {
+        SubCommand::with_name(NAME)
+            .setting(AppSettings::NoBinaryName)
+            .about("Show version information for this program")
+    }
+}
+
+fn get_ami_helper_command(args: &Vec<String>) -> Result<AmiHelperCommand, clap::Error> {
+    let cli = App::new("ami-helper")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::NoBinaryName)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(build_cache_dir_arg())
+        .arg(build_config_dir_arg())
+        .arg(build_state_dir_arg())
+        .subcommand(alias::build_subcommand())
+        .subcommand(check::build_subcommand())
+        .subcommand(dump_subcommand::build_subcommand())
+        .subcommand(export_subcommand::build_subcommand())
+        .subcommand(karpenter::build_subcommand())
+        .subcommand(raw::build_subcommand())
+        .subcommand(select::build_subcommand())
+        .subcommand(version::build_subcommand());
+
+    match cli.get_matches_from_safe(args) {
+        Ok(matches) => {
+            get_dir_args(&matches);
+            match matches.subcommand() {
+                Some((alias::NAME, options)) => {
+                    Ok(AmiHelperCommand::Alias(alias::get_command(options)?))
+                }
+                Some((check::NAME, options)) => {
+                    Ok(AmiHelperCommand::Check(check::get_options(options)?))
+                }
+                Some((dump_subcommand::NAME, options)) => Ok(AmiHelperCommand::Dump(
+                    dump_subcommand::get_options(options)?,
+                )),
+                Some((export_subcommand::NAME, options)) => Ok(AmiHelperCommand::Export(
+                    export_subcommand::get_options(options)?,
+                )),
+                Some((karpenter::NAME, options)) => Ok(AmiHelperCommand::Karpenter(
+                    karpenter::get_options(options)?,
+                )),
+                Some((raw::NAME, options)) => Ok(AmiHelperCommand::Raw(raw::get_options(options)?)),
+                Some((select::NAME, options)) => {
+                    Ok(AmiHelperCommand::Select(select::get_options(options)?))
+                }
+                Some((version::NAME, _x)) => Ok(AmiHelperCommand::Version),
+                Some((name, _)) => Err(clap::Error::raw(
+                    clap::ErrorKind::UnrecognizedSubcommand,
+                    format!("'{}' is not a recognized subcommand\n", name),
+                )),
+                None => Err(clap::Error::raw(
+                    clap::ErrorKind::MissingSubcommand,
+                    "a subcommand is required\n",
+                )),
+            }
+        }
+        // --version and -V print what the version subcommand does rather than clap's
+        // "ami-helper <version>".
+        Err(error) if error.kind == clap::ErrorKind::DisplayVersion => {
+            Ok(AmiHelperCommand::Version)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+type BitmaskT = u128;
+
+#[derive(Clone, Copy, Debug)]
+struct StringBitmask(BitmaskT);
 
 impl std::fmt::Display for StringBitmask {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -402,789 +3933,3340 @@ impl std::fmt::Display for StringBitmask {
     }
 }
 
-impl BitOr for StringBitmask {
-    type Output = Self;
+impl BitOr for StringBitmask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+trait StringBitmaskFilter {
+    fn filter(&self, string_bitmask: &StringBitmask) -> bool;
+    // How many bits keep string_bitmask from passing; zero when it passes.
+    fn distance(&self, string_bitmask: &StringBitmask) -> u32;
+    fn explain(&self, string_bitmask: &StringBitmask, all_segments: &StringsToBitmask) -> String;
+    // What the filter asks for, independent of any one name.
+    fn describe(&self, all_segments: &StringsToBitmask) -> String;
+    // The descriptions of the filters any one of which lets a name through, nested alternatives
+    // included.
+    fn alternatives(&self, all_segments: &StringsToBitmask) -> Vec<String> {
+        vec![self.describe(all_segments)]
+    }
+}
+
+#[inline(always)]
+fn count_filter_call() {
+    #[cfg(feature = "instrumentation")]
+    FILTER_CALLS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+struct AlwaysTrueFilter {}
+
+impl AlwaysTrueFilter {
+    fn new() -> Self {
+        Self {}
+    }
+}
+
+impl StringBitmaskFilter for AlwaysTrueFilter {
+    fn filter(&self, _: &StringBitmask) -> bool {
+        count_filter_call();
+        true
+    }
+    fn distance(&self, _: &StringBitmask) -> u32 {
+        0
+    }
+    fn explain(&self, _: &StringBitmask, _: &StringsToBitmask) -> String {
+        "passes (nothing to match)".to_string()
+    }
+    fn describe(&self, _: &StringsToBitmask) -> String {
+        "anything".to_string()
+    }
+}
+
+struct MaskEqualsValueFilter {
+    mask: StringBitmask,
+    value: StringBitmask,
+}
+
+impl MaskEqualsValueFilter {
+    fn new(mask: StringBitmask, value: StringBitmask) -> Self {
+        Self { mask, value }
+    }
+}
+
+impl StringBitmaskFilter for MaskEqualsValueFilter {
+    fn filter(&self, string_bitmask: &StringBitmask) -> bool {
+        count_filter_call();
+        (string_bitmask.0 & self.mask.0) == self.value.0
+    }
+    fn distance(&self, string_bitmask: &StringBitmask) -> u32 {
+        ((string_bitmask.0 & self.mask.0) ^ self.value.0).count_ones()
+    }
+    fn explain(&self, string_bitmask: &StringBitmask, all_segments: &StringsToBitmask) -> String {
+        let have = string_bitmask.0 & self.mask.0;
+        if have == self.value.0 {
+            return format!(
+                "passes: has [{}]",
+                all_segments.strings_from(self.value).join(", ")
+            );
+        }
+        let missing = StringBitmask(self.value.0 & !have);
+        let unexpected = StringBitmask(have & !self.value.0);
+        format!(
+            "fails: missing [{}], unexpected [{}]",
+            all_segments.strings_from(missing).join(", "),
+            all_segments.strings_from(unexpected).join(", ")
+        )
+    }
+    fn describe(&self, all_segments: &StringsToBitmask) -> String {
+        let required = all_segments.strings_from(self.value);
+        let excluded = all_segments.strings_from(StringBitmask(self.mask.0 & !self.value.0));
+        match (required.is_empty(), excluded.is_empty()) {
+            (true, true) => "anything".to_string(),
+            (false, true) => format!("has [{}]", required.join(", ")),
+            (true, false) => format!("lacks [{}]", excluded.join(", ")),
+            (false, false) => format!(
+                "has [{}], lacks [{}]",
+                required.join(", "),
+                excluded.join(", ")
+            ),
+        }
+    }
+}
+
+struct OrFilter {
+    filters: Vec<Box<dyn StringBitmaskFilter>>,
+}
+
+impl OrFilter {
+    fn new() -> Self {
+        Self {
+            filters: Vec::new(),
+        }
+    }
+    fn push<F>(&mut self, filter: F)
+    where
+        F: StringBitmaskFilter + 'static,
+    {
+        self.filters.push(Box::new(filter));
+    }
+    fn push_boxed(&mut self, filter: Box<dyn StringBitmaskFilter>) {
+        self.filters.push(filter);
+    }
+}
+
+impl StringBitmaskFilter for OrFilter {
+    fn filter(&self, string_bitmask: &StringBitmask) -> bool {
+        count_filter_call();
+        if !self.filters.is_empty() {
+            for filter in self.filters.iter() {
+                if filter.filter(string_bitmask) {
+                    return true;
+                }
+            }
+            false
+        } else {
+            true
+        }
+    }
+    fn distance(&self, string_bitmask: &StringBitmask) -> u32 {
+        self.filters
+            .iter()
+            .map(|f| f.distance(string_bitmask))
+            .min()
+            .unwrap_or(0)
+    }
+    fn explain(&self, string_bitmask: &StringBitmask, all_segments: &StringsToBitmask) -> String {
+        let closest = self
+            .filters
+            .iter()
+            .min_by_key(|f| f.distance(string_bitmask));
+        match closest {
+            Some(closest) if closest.filter(string_bitmask) => {
+                closest.explain(string_bitmask, all_segments)
+            }
+            Some(closest) => format!(
+                "{} (closest of {} alternatives)",
+                closest.explain(string_bitmask, all_segments),
+                self.filters.len()
+            ),
+            None => "passes (nothing to match)".to_string(),
+        }
+    }
+    fn describe(&self, all_segments: &StringsToBitmask) -> String {
+        let alternatives = self.alternatives(all_segments);
+        if alternatives.len() == 1 {
+            alternatives.into_iter().next().unwrap_or_default()
+        } else {
+            let alternatives: Vec<String> =
+                alternatives.iter().map(|a| format!("({})", a)).collect();
+            format!("any of {}", alternatives.join(" or "))
+        }
+    }
+    fn alternatives(&self, all_segments: &StringsToBitmask) -> Vec<String> {
+        if self.filters.is_empty() {
+            return vec!["anything".to_string()];
+        }
+        self.filters
+            .iter()
+            .flat_map(|f| f.alternatives(all_segments))
+            .collect()
+    }
+}
+
+/// Passes the AMIs of one architecture whose names have no counterpart for the other
+/// architecture, like a release published only for arm64.
+struct UnpairedFilter {
+    this: StringBitmask,
+    other: StringBitmask,
+    others: HashSet<BitmaskT>,
+}
+
+impl UnpairedFilter {
+    fn new(details: &[AmiDetail], this: StringBitmask, other: StringBitmask) -> Self {
+        let others = details
+            .iter()
+            .map(|d| d.bitmask.0)
+            .filter(|b| b & other.0 != 0)
+            .collect();
+        Self {
+            this,
+            other,
+            others,
+        }
+    }
+    fn counterpart(&self, string_bitmask: &StringBitmask) -> BitmaskT {
+        (string_bitmask.0 & !self.this.0) | self.other.0
+    }
+}
+
+impl StringBitmaskFilter for UnpairedFilter {
+    fn filter(&self, string_bitmask: &StringBitmask) -> bool {
+        count_filter_call();
+        string_bitmask.0 & self.this.0 != 0
+            && string_bitmask.0 & self.other.0 == 0
+            && !self.others.contains(&self.counterpart(string_bitmask))
+    }
+    fn distance(&self, string_bitmask: &StringBitmask) -> u32 {
+        u32::from(!self.filter(string_bitmask))
+    }
+    fn explain(&self, string_bitmask: &StringBitmask, all_segments: &StringsToBitmask) -> String {
+        let this = all_segments.strings_from(self.this).join(", ");
+        let other = all_segments.strings_from(self.other).join(", ");
+        if self.filter(string_bitmask) {
+            format!("passes: {} with no {} counterpart", this, other)
+        } else if string_bitmask.0 & self.this.0 == 0 || string_bitmask.0 & self.other.0 != 0 {
+            format!("fails: not {} only", this)
+        } else {
+            format!("fails: has an {} counterpart", other)
+        }
+    }
+    fn describe(&self, all_segments: &StringsToBitmask) -> String {
+        format!(
+            "has [{}] with no [{}] counterpart",
+            all_segments.strings_from(self.this).join(", "),
+            all_segments.strings_from(self.other).join(", ")
+        )
+    }
+}
+
+fn never_ignore(_: &str) -> bool {
+    false
+}
+
+// Filters do not know the StringsToBitmask their masks came from so their calls are counted here,
+// once for the whole process.
+#[cfg(feature = "instrumentation")]
+static FILTER_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Call counts for performance analysis.  Only kept with the instrumentation feature.
+#[cfg(feature = "instrumentation")]
+#[derive(Clone, Copy, Debug, Default)]
+struct StringsToBitmaskStats {
+    insert_calls: usize,
+    insert_hits: usize,
+    bitmask_from_calls: usize,
+    filter_calls: usize,
+}
+
+#[cfg(feature = "instrumentation")]
+impl std::fmt::Display for StringsToBitmaskStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "insert calls: {}, insert hits: {}, bitmask_from calls: {}, filter calls (process): {}",
+            self.insert_calls, self.insert_hits, self.bitmask_from_calls, self.filter_calls
+        )
+    }
+}
+
+struct StringsToBitmask<'a> {
+    string_to_bit: HashMap<String, u8>,
+    next_bit: u8,
+    // The first key that arrived after every bit of BitmaskT was taken.  It and the keys after it
+    // get no bit, so the filters cannot be trusted and check_capacity reports it.
+    overflow: Option<String>,
+    combining: HashSet<String>,
+    // The keys that came from a name's segments as they are and those that came from combining
+    // two of them.  A key in both is ambiguous: one bit stands for two different things.
+    segment_keys: HashSet<String>,
+    combined_keys: HashSet<String>,
+    collisions: HashSet<String>,
+    bit_to_string: Vec<String>,
+    aliases: HashMap<String, HashSet<String>>,
+    ignore_filter: &'a dyn Fn(&str) -> bool,
+    #[cfg(feature = "instrumentation")]
+    stats: StringsToBitmaskStats,
+}
+
+impl<'a> StringsToBitmask<'a> {
+    pub fn new() -> Self {
+        Self {
+            string_to_bit: HashMap::new(),
+            next_bit: 0,
+            overflow: None,
+            combining: HashSet::new(),
+            segment_keys: HashSet::new(),
+            combined_keys: HashSet::new(),
+            collisions: HashSet::new(),
+            bit_to_string: Vec::new(),
+            aliases: HashMap::new(),
+            ignore_filter: &never_ignore,
+            #[cfg(feature = "instrumentation")]
+            stats: StringsToBitmaskStats::default(),
+        }
+    }
+    /// The insert and bitmask_from counts are this instance's.  The filter count is every filter
+    /// call the process has made, whichever instance built the masks.
+    #[cfg(feature = "instrumentation")]
+    fn stats(&self) -> StringsToBitmaskStats {
+        StringsToBitmaskStats {
+            filter_calls: FILTER_CALLS.load(std::sync::atomic::Ordering::Relaxed),
+            ..self.stats
+        }
+    }
+    pub fn alias<K, A>(&mut self, key: K, alias: A)
+    where
+        K: Into<String>,
+        A: Into<String>,
+    {
+        let key = key.into();
+        self.insert_one(&key);
+        let alias = alias.into();
+        self.insert_one(&alias);
+        self.aliases.entry(key).or_default().insert(alias);
+    }
+    pub fn combining<K>(&mut self, key: K)
+    where
+        K: Into<String>,
+    {
+        self.combining.insert(key.into());
+    }
+    pub fn bitmask_from<'b, I>(&mut self, strings: I) -> StringBitmask
+    where
+        I: IntoIterator<Item = &'b str>,
+    {
+        #[cfg(feature = "instrumentation")]
+        {
+            self.stats.bitmask_from_calls += 1;
+        }
+        let mut rv = StringsToBitmaskBuilder::new(self);
+        rv.update(strings);
+        rv.inner()
+    }
+    /// The bitmask for the segments of one name.  Unlike bitmask_from, the keys are remembered
+    /// so a combined key that is also a segment of some other name can be reported.
+    pub fn bitmask_from_segments<'b, I>(&mut self, segments: I) -> StringBitmask
+    where
+        I: IntoIterator<Item = &'b str>,
+    {
+        let mut rv = StringsToBitmaskBuilder::new(self);
+        rv.segments = true;
+        rv.update(segments);
+        rv.inner()
+    }
+    fn note_key(&mut self, key: &str, combined: bool) {
+        let (mine, other) = if combined {
+            (&mut self.combined_keys, &self.segment_keys)
+        } else {
+            (&mut self.segment_keys, &self.combined_keys)
+        };
+        if mine.contains(key) {
+            return;
+        }
+        if other.contains(key) && self.collisions.insert(key.to_string()) {
+            debug!(
+                "{} is both a segment and two combined segments; names with either share a bit",
+                key
+            );
+        }
+        mine.insert(key.to_string());
+    }
+    pub fn clear_combining(&mut self) {
+        self.combining.clear();
+    }
+    pub fn clear_ignore(&mut self) {
+        self.ignore_filter = &never_ignore;
+    }
+    pub fn ignore(&mut self, callme: &'a dyn Fn(&str) -> bool) {
+        self.ignore_filter = callme;
+    }
+    pub fn insert(&mut self, key: &str) -> BitmaskT {
+        let mut rv = self.insert_one(key);
+        if let Some(aliases) = self.aliases.get(key) {
+            // An alias that arrived after the bits ran out has none.
+            for bit in aliases
+                .iter()
+                .filter_map(|alias| self.string_to_bit.get(alias))
+            {
+                rv |= 1 << bit;
+            }
+        }
+        rv
+    }
+    pub fn strings_from(&self, bitmask: StringBitmask) -> Vec<&str> {
+        self.bit_to_string
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| bitmask.0 & (1 << bit) != 0)
+            .map(|(_, string)| string.as_str())
+            .collect()
+    }
+    /// Fails once more keys were inserted than BitmaskT has bits.
+    pub fn check_capacity(&self) -> std::io::Result<()> {
+        match &self.overflow {
+            Some(key) => Err(custom_error(format!(
+                "the parameter names have more than {} different segments, which is all the filters can tell apart; {} was the first that did not fit.  Select from fewer paths.",
+                BitmaskT::BITS,
+                key
+            ))),
+            None => Ok(()),
+        }
+    }
+    fn insert_one(&mut self, key: &str) -> BitmaskT {
+        #[cfg(feature = "instrumentation")]
+        {
+            self.stats.insert_calls += 1;
+        }
+        if (self.ignore_filter)(key) {
+            0
+        } else {
+            let bit = if let Some(value) = self.string_to_bit.get(key) {
+                #[cfg(feature = "instrumentation")]
+                {
+                    self.stats.insert_hits += 1;
+                }
+                *value
+            } else if u32::from(self.next_bit) == BitmaskT::BITS {
+                if self.overflow.is_none() {
+                    self.overflow = Some(key.to_string());
+                }
+                return 0;
+            } else {
+                let rv = self.next_bit;
+                self.next_bit += 1;
+                self.string_to_bit.insert(key.to_string(), rv);
+                self.bit_to_string.push(key.to_string());
+                assert!(self.bit_to_string[rv as usize] == key);
+                rv
+            };
+            1 << bit
+        }
+    }
+}
+
+struct StringsToBitmaskBuilder<'a, 'b, 'c> {
+    strings_to_bitmask: &'a mut StringsToBitmask<'c>,
+    bitmask: StringBitmask,
+    contained: Option<&'b str>,
+    // The strings are the segments of a name rather than what a filter asks for.
+    segments: bool,
+}
+
+impl<'a, 'b, 'c> StringsToBitmaskBuilder<'a, 'b, 'c> {
+    pub fn new(strings_to_bitmask: &'a mut StringsToBitmask<'c>) -> Self {
+        Self {
+            strings_to_bitmask,
+            bitmask: StringBitmask(0),
+            contained: None,
+            segments: false,
+        }
+    }
+    fn finalize(mut self) -> StringBitmask {
+        if let Some(contained) = self.contained.take() {
+            self.update_segment(contained, false);
+        }
+        self.bitmask
+    }
+    pub fn inner(self) -> StringBitmask {
+        self.finalize()
+    }
+    pub fn update<I>(&mut self, strings: I)
+    where
+        I: IntoIterator<Item = &'b str>,
+    {
+        for rover in strings {
+            self.update_one(rover);
+        }
+    }
+    pub fn update_one(&mut self, key: &'b str) {
+        if let Some(contained) = self.contained.take() {
+            let combined = format!("{}-{}", contained, key);
+            self.update_segment(&combined, true);
+        } else {
+            if self.strings_to_bitmask.combining.contains(key) {
+                self.contained = Some(key);
+            } else {
+                self.update_segment(key, false);
+            }
+        }
+    }
+    fn update_segment(&mut self, key: &str, combined: bool) {
+        if self.segments {
+            self.strings_to_bitmask.note_key(key, combined);
+        }
+        self.update_bitmask(key);
+    }
+    fn update_bitmask(&mut self, key: &str) {
+        self.bitmask.0 |= self.strings_to_bitmask.insert(key);
+    }
+}
+
+impl From<StringsToBitmaskBuilder<'_, '_, '_>> for StringBitmask {
+    fn from(value: StringsToBitmaskBuilder<'_, '_, '_>) -> StringBitmask {
+        value.finalize()
+    }
+}
+
+impl From<StringsToBitmaskBuilder<'_, '_, '_>> for BitmaskT {
+    fn from(value: StringsToBitmaskBuilder<'_, '_, '_>) -> BitmaskT {
+        value.finalize().0
+    }
+}
+
+fn common_prefix(list: &[&str], separator: char) -> String {
+    match list {
+        [] => "".to_string(),
+        [just_one] => just_one.chars().collect(),
+        _ => {
+            let first = &list[0];
+            let mut rightmost = usize::MAX;
+            for entry in list.iter() {
+                let mut match_count = 0;
+                let mut last_separator = usize::MAX;
+                for (lft, rgt) in first.chars().zip(entry.chars()) {
+                    if match_count > rightmost {
+                        break;
+                    }
+                    if lft != rgt {
+                        if last_separator == usize::MAX {
+                            if match_count < rightmost {
+                                rightmost = match_count;
+                            }
+                        } else {
+                            if last_separator < rightmost {
+                                rightmost = last_separator;
+                            }
+                        }
+                        break;
+                    }
+                    match_count += 1;
+                    if lft == separator {
+                        last_separator = match_count;
+                    }
+                }
+            }
+            if rightmost == usize::MAX {
+                first.chars().collect()
+            } else {
+                first.chars().take(rightmost).collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AmiDetail {
+    operating_system: OperatingSystem,
+    name: String,
+    ami: String,
+    #[serde(skip)]
+    bitmask: StringBitmask,
+    #[serde(skip)]
+    parameter: String,
+    #[serde(skip)]
+    last_modified: Option<SystemTime>,
+    #[serde(skip)]
+    version: Option<String>,
+    #[serde(skip)]
+    architecture: Option<Architecture>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    launch_template_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created: Option<String>,
+    #[serde(skip)]
+    created_local: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance_types: Option<Vec<&'static str>>,
+    #[serde(skip)]
+    instance_types_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ssh_username: Option<String>,
+}
+
+#[cfg(feature = "protobuf")]
+impl AmiDetail {
+    fn to_protobuf(&self) -> protobuf::AmiDetail {
+        let operating_system: &str = self.operating_system.into();
+        protobuf::AmiDetail {
+            operating_system: operating_system.to_string(),
+            name: self.name.clone(),
+            ami: self.ami.clone(),
+        }
+    }
+}
+
+impl Eq for AmiDetail {}
+
+impl Ord for AmiDetail {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.operating_system.cmp(&other.operating_system) {
+            Ordering::Equal => match self.name.cmp(&other.name) {
+                Ordering::Equal => self.ami.cmp(&other.ami),
+                o => o,
+            },
+            o => o,
+        }
+    }
+}
+
+impl PartialEq for AmiDetail {
+    fn eq(&self, other: &Self) -> bool {
+        self.operating_system == other.operating_system
+            && self.name == other.name
+            && self.ami == other.ami
+    }
+}
+
+impl PartialOrd for AmiDetail {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl AmiDetail {
+    /// The operating system and the architecture joined by an underscore, like amazon_amd64.
+    /// Without an architecture it is just the operating system.
+    fn os_architecture_slug(&self) -> String {
+        match self.architecture {
+            Some(architecture) => {
+                let architecture: &str = architecture.into();
+                format!("{}_{}", self.operating_system.key(), architecture)
+            }
+            None => self.operating_system.key().to_string(),
+        }
+    }
+    /// A valid HCL identifier derived from the operating system and the name.  Anything other
+    /// than an ASCII letter or digit becomes a single underscore.
+    fn packer_variable_name(&self) -> String {
+        let source = format!("{} {}", self.operating_system, self.name);
+        let mut rv = String::with_capacity(source.len());
+        for c in source.chars() {
+            if c.is_ascii_alphanumeric() {
+                rv.push(c.to_ascii_lowercase());
+            } else if !rv.ends_with('_') {
+                rv.push('_');
+            }
+        }
+        rv.trim_end_matches('_').to_string()
+    }
+    /// The AMI as a commented HCL assignment to its `packer_variable_name`.
+    fn to_packer_variable(&self) -> String {
+        self.to_packer_variable_named(&self.packer_variable_name())
+    }
+    /// Like `to_packer_variable` but assigned to `name`, like the lone ami_id of a single AMI.
+    fn to_packer_variable_named(&self, name: &str) -> String {
+        format!(
+            "# {} {}\n{} = \"{}\"",
+            self.operating_system, self.name, name, self.ami
+        )
+    }
+}
+
+fn export_packer_vars(path: &str, details: &[AmiDetail]) -> std::io::Result<()> {
+    let mut text = Vec::new();
+    ExportFormat::Packer
+        .backend(true)
+        .export(details, &mut text)?;
+    std::fs::write(path, text)
+}
+
+// Write contents to a temporary file next to path then rename it into place.  On failure the
+// temporary file is removed and whatever was at path is left untouched.
+fn write_atomically<P>(path: P, contents: &[u8]) -> std::io::Result<()>
+where
+    P: AsRef<std::path::Path>,
+{
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| custom_error(format!("{} does not name a file", path.display())))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+struct AmiDetailsWithFilter {
+    details: Vec<AmiDetail>,
+    filter: Box<dyn StringBitmaskFilter>,
+    previous: Option<Box<dyn StringBitmaskFilter>>,
+    version: Option<String>,
+}
+
+impl AmiDetailsWithFilter {
+    fn new(details: Vec<AmiDetail>, filter: Box<dyn StringBitmaskFilter>) -> Self {
+        Self {
+            details,
+            filter,
+            previous: None,
+            version: None,
+        }
+    }
+    fn with_version(mut self, version: Option<String>) -> Self {
+        self.version = version;
+        self
+    }
+    // When a single architecture was requested and the preferred version only exists for the
+    // other one, say so rather than leaving an empty selection unexplained.
+    fn missing_architecture(
+        &self,
+        architecture: Architecture,
+        architecture_filter: &dyn StringBitmaskFilter,
+        region: &str,
+    ) -> Option<String> {
+        if !matches!(architecture, Architecture::Amd64 | Architecture::Arm64) {
+            return None;
+        }
+        let version = self.version.as_deref()?;
+        if self
+            .details
+            .iter()
+            .any(|d| self.filter.filter(&d.bitmask) && architecture_filter.filter(&d.bitmask))
+        {
+            return None;
+        }
+        let other = self.details.iter().find(|d| {
+            d.version.as_deref() == Some(version)
+                && d.architecture.is_some()
+                && d.architecture != Some(architecture)
+        })?;
+        let operating_system: &str = other.operating_system.into();
+        let architecture: &str = architecture.into();
+        Some(format!(
+            "no {} image exists for {} {} in {}",
+            architecture, operating_system, version, region
+        ))
+    }
+    fn with_previous(mut self, previous: Option<Box<dyn StringBitmaskFilter>>) -> Self {
+        self.previous = previous;
+        self
+    }
+    // The AMI chosen by the runner-up version for each architecture.
+    fn previous_amis(
+        &self,
+        architecture_filter: &dyn StringBitmaskFilter,
+    ) -> HashMap<Option<Architecture>, String> {
+        let mut rv = HashMap::new();
+        if let Some(previous) = &self.previous {
+            for detail in self.details.iter() {
+                if previous.filter(&detail.bitmask) && architecture_filter.filter(&detail.bitmask) {
+                    rv.insert(detail.architecture, detail.ami.clone());
+                }
+            }
+        }
+        rv
+    }
+    fn into_iter(self) -> AmiDetailsWithFilterIteratorOwn {
+        let details = self.details.into_iter().map(Some).collect();
+        AmiDetailsWithFilterIteratorOwn {
+            details,
+            filter: self.filter,
+            rover: 0,
+        }
+    }
+}
+
+struct AmiDetailsWithFilterIteratorOwn {
+    details: Vec<Option<AmiDetail>>,
+    filter: Box<dyn StringBitmaskFilter>,
+    rover: usize,
+}
+
+impl Iterator for AmiDetailsWithFilterIteratorOwn {
+    type Item = AmiDetail;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.rover < self.details.len() {
+            let detail = self.details[self.rover].take().unwrap();
+            self.rover += 1;
+            if self.filter.filter(&detail.bitmask) {
+                return Some(detail);
+            }
+        }
+        None
+    }
+}
+
+struct AwsContext {
+    config: SdkConfig,
+    account: tokio::sync::OnceCell<String>,
+    audit: Option<Arc<audit::AuditLog>>,
+}
+
+impl AwsContext {
+    async fn with_profile(
+        region: Region,
+        max_attempts: u32,
+        profile: Option<&str>,
+        endpoint_url: Option<&str>,
+    ) -> Self {
+        let region_provider = RegionProviderChain::first_try(region);
+        let retry_config = RetryConfig::new().with_max_attempts(max_attempts);
+        let mut loader = aws_config::from_env()
+            .region(region_provider)
+            .retry_config(retry_config);
+        if let Some(endpoint) = endpoint_url.and_then(parse_endpoint) {
+            loader = loader.endpoint_resolver(endpoint);
+        }
+        if let Some(profile) = profile {
+            loader = loader.credentials_provider(
+                ProfileFileCredentialsProvider::builder()
+                    .profile_name(profile)
+                    .build(),
+            );
+        }
+        let config = loader.load().await;
+        Self {
+            config,
+            account: tokio::sync::OnceCell::new(),
+            audit: None,
+        }
+    }
+    fn with_audit_log(mut self, audit: Option<Arc<audit::AuditLog>>) -> Self {
+        self.audit = audit;
+        self
+    }
+    // Every client is created here so the audit log sees the calls of every service.  Without
+    // an audit log the clients use the SDK's own connector.
+    fn connector(&self) -> Option<aws_smithy_client::erase::DynConnector> {
+        let audit = self.audit.as_ref()?;
+        let settings = aws_smithy_client::http_connector::HttpSettings::default();
+        let inner = aws_config::connector::default_connector(&settings, None)?;
+        Some(audit.connector(inner))
+    }
+    fn ssm(&self) -> Client {
+        match self.connector() {
+            Some(connector) => Client::from_conf_conn((&self.config).into(), connector),
+            None => Client::new(&self.config),
+        }
+    }
+    fn ec2(&self) -> aws_sdk_ec2::Client {
+        match self.connector() {
+            Some(connector) => {
+                aws_sdk_ec2::Client::from_conf_conn((&self.config).into(), connector)
+            }
+            None => aws_sdk_ec2::Client::new(&self.config),
+        }
+    }
+    fn sts(&self) -> aws_sdk_sts::Client {
+        match self.connector() {
+            Some(connector) => {
+                aws_sdk_sts::Client::from_conf_conn((&self.config).into(), connector)
+            }
+            None => aws_sdk_sts::Client::new(&self.config),
+        }
+    }
+    // The account the credentials belong to.  STS is asked at most once per context.
+    async fn account(&self) -> Result<&str, Box<dyn std::error::Error>> {
+        let account = self
+            .account
+            .get_or_try_init(|| async {
+                let client = self.sts();
+                let identity = client.get_caller_identity().send().await.map_err(|e| {
+                    sdk_error(e, "the account from STS") as Box<dyn std::error::Error>
+                })?;
+                match identity.account() {
+                    Some(account) => Ok(account.to_string()),
+                    None => Err(Box::new(custom_error(
+                        "GetCallerIdentity did not return an account",
+                    )) as Box<dyn std::error::Error>),
+                }
+            })
+            .await?;
+        Ok(account)
+    }
+    async fn enforce_account(&self, expected: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let account = self.account().await?;
+        if account != expected {
+            return Err(Box::new(custom_error(format!(
+                "credentials resolve to account {} but expected {}",
+                account, expected
+            ))));
+        }
+        Ok(())
+    }
+}
+
+// Every service is sent to the same URL so a single mock can stand in for SSM, EC2, and STS.
+fn parse_endpoint(url: &str) -> Option<aws_sdk_ssm::Endpoint> {
+    url.parse().ok().map(aws_sdk_ssm::Endpoint::immutable)
+}
+
+async fn detect_region() -> Option<Region> {
+    const TIMEOUT: Duration = Duration::from_secs(1);
+    let detect = async {
+        let client = aws_config::imds::Client::builder()
+            .connect_timeout(TIMEOUT)
+            .read_timeout(TIMEOUT)
+            .max_attempts(1)
+            .build()
+            .await
+            .ok()?;
+        ImdsRegionProvider::builder()
+            .imds_client(client)
+            .build()
+            .region()
+            .await
+    };
+    tokio::time::timeout(TIMEOUT, detect).await.ok().flatten()
+}
+
+// Pick the region whose SSM endpoint accepts a TCP connection the fastest.
+async fn nearest_region() -> Option<Region> {
+    const TIMEOUT: Duration = Duration::from_secs(2);
+    const CANDIDATES: [&str; 9] = [
+        "us-east-1",
+        "us-east-2",
+        "us-west-2",
+        "ca-central-1",
+        "sa-east-1",
+        "eu-west-1",
+        "eu-central-1",
+        "ap-south-1",
+        "ap-northeast-1",
+    ];
+    let probes = CANDIDATES.iter().map(|region| async move {
+        let endpoint = format!("ssm.{}.amazonaws.com:443", region);
+        let start = tokio::time::Instant::now();
+        match tokio::time::timeout(TIMEOUT, tokio::net::TcpStream::connect(endpoint)).await {
+            Ok(Ok(_)) => {
+                let elapsed = start.elapsed();
+                debug!("connected to {} in {:?}", region, elapsed);
+                Some((elapsed, *region))
+            }
+            _ => {
+                debug!("could not connect to {}", region);
+                None
+            }
+        }
+    });
+    futures_util::future::join_all(probes)
+        .await
+        .into_iter()
+        .flatten()
+        .min()
+        .map(|(_, region)| Region::new(region))
+}
+
+async fn resolve_region(options: &SelectOptions) -> Region {
+    if options.region_detect {
+        if let Some(region) = detect_region().await {
+            debug!("detected region {} from the instance metadata", region);
+            return region;
+        }
+    }
+    pick_region(options.region.as_deref(), options.profile.as_deref()).await
+}
+
+// --region when given, including nearest, otherwise the same choice the AWS CLI makes.
+async fn pick_region(region: Option<&str>, profile: Option<&str>) -> Region {
+    match region {
+        Some(NEAREST_REGION) => {
+            if let Some(region) = nearest_region().await {
+                debug!("selected {} as the nearest region", region);
+                return region;
+            }
+            debug!("no candidate region responded; using {}", DEFAULT_REGION);
+            Region::new(DEFAULT_REGION)
+        }
+        Some(region) => Region::new(region.to_string()),
+        None => default_region(profile).await,
+    }
+}
+
+// Without --region the region is picked the way the AWS CLI does: the environment, then the
+// region configured for the profile.
+async fn default_region(profile: Option<&str>) -> Region {
+    let mut from_profile = ProfileFileRegionProvider::builder();
+    if let Some(profile) = profile {
+        from_profile = from_profile.profile_name(profile);
+    }
+    RegionProviderChain::first_try(EnvironmentVariableRegionProvider::new())
+        .or_else(from_profile.build())
+        .or_else(DEFAULT_REGION)
+        .region()
+        .await
+        .unwrap_or_else(|| Region::new(DEFAULT_REGION))
+}
+
+struct NameAmiPair {
+    name: String,
+    ami: String,
+    last_modified: Option<SystemTime>,
+    version: i64,
+}
+
+struct NameAmiPairGetter {
+    client: Client,
+    max_parameters: usize,
+    retry_on: Vec<String>,
+    max_attempts: u32,
+}
+
+impl NameAmiPairGetter {
+    fn new(context: &AwsContext, max_parameters: usize) -> Self {
+        let client = context.ssm();
+
+        Self {
+            client,
+            max_parameters,
+            retry_on: Vec::new(),
+            max_attempts: 1,
+        }
+    }
+    fn with_retry_on(mut self, retry_on: Vec<String>, max_attempts: u32) -> Self {
+        self.retry_on = retry_on;
+        self.max_attempts = max_attempts;
+        self
+    }
+    fn is_retryable(&self, code: Option<&str>) -> bool {
+        code.map(|code| self.retry_on.iter().any(|r| code.contains(r.as_str())))
+            .unwrap_or(false)
+    }
+    // The error codes from --retry-on start the path over; the SDK has already retried whatever
+    // it considers transient.
+    async fn get_pairs(
+        &self,
+        path: &ParameterPath,
+    ) -> Result<Vec<NameAmiPair>, Box<dyn std::error::Error>> {
+        let mut attempt = 1;
+        loop {
+            match self.get_pairs_once(path).await {
+                Err(error)
+                    if error.is::<RetryableServiceError>() && attempt < self.max_attempts =>
+                {
+                    warn!("{}; reading it again", error);
+                    tokio::time::sleep(Duration::from_millis(100 << attempt.min(6))).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+    async fn get_pairs_once(
+        &self,
+        path: &ParameterPath,
+    ) -> Result<Vec<NameAmiPair>, Box<dyn std::error::Error>> {
+        // Note: Bear in mind that `into_paginator` suppresses errors.  You'll notice a lack of the
+        // question mark operator or any other error handling.  Instead an empty list is returned.
+        // No doubt some poor sole will curse that decision.  The one exception is expired
+        // credentials which are reported so the caller can start over with fresh credentials.
+        let mut response = self
+            .client
+            .get_parameters_by_path()
+            .path(&path.path)
+            .recursive(path.recursive)
+            .into_paginator()
+            .send();
+        let mut pairs = Vec::new();
+        while let Some(chunk) = response.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    if let Some(parameters) = chunk.parameters {
+                        for parameter in parameters.iter() {
+                            if let (Some(name), Some(value)) = (&parameter.name, &parameter.value) {
+                                if path.accepts(name) {
+                                    pairs.push(NameAmiPair {
+                                        name: name.to_string(),
+                                        ami: value.to_string(),
+                                        last_modified: parameter
+                                            .last_modified_date
+                                            .and_then(|d| SystemTime::try_from(d).ok()),
+                                        version: parameter.version,
+                                    });
+                                }
+                            }
+                        }
+                    }
+                    if pairs.len() > self.max_parameters {
+                        return Err(Box::new(custom_error(format!(
+                            "{} returned more than {} parameters.  Use :depth=N or :recursive=false with --extra-path to narrow it or raise --max-parameters.",
+                            path.path, self.max_parameters
+                        ))));
+                    }
+                }
+                Err(error) if is_expired(&error) => {
+                    return Err(sdk_error(error, &path.path));
+                }
+                Err(SdkError::ServiceError { err, .. }) if self.is_retryable(err.code()) => {
+                    return Err(Box::new(RetryableServiceError {
+                        path: path.path.to_string(),
+                        code: err.code().unwrap_or_default().to_string(),
+                    }));
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(pairs)
+    }
+}
+
+#[derive(Clone)]
+struct ImageDescriber {
+    client: aws_sdk_ec2::Client,
+}
+
+impl ImageDescriber {
+    const CHUNK_SIZE: usize = 100;
+
+    fn new(context: &AwsContext) -> Self {
+        let client = context.ec2();
+
+        Self { client }
+    }
+    async fn describe_images(
+        &self,
+        amis: &[&str],
+    ) -> Result<ImageMap, Box<dyn std::error::Error + Send + Sync>> {
+        let mut rv = HashMap::new();
+        for chunk in amis.chunks(Self::CHUNK_SIZE) {
+            let image_ids = chunk.iter().map(|a| a.to_string()).collect();
+            let response = self
+                .client
+                .describe_images()
+                .set_image_ids(Some(image_ids))
+                .send()
+                .await
+                .map_err(|e| sdk_error(e, "the images"))?;
+            for image in response.images().unwrap_or_default() {
+                if let Some(image_id) = image.image_id() {
+                    rv.insert(image_id.to_string(), image.clone());
+                }
+            }
+        }
+        Ok(rv)
+    }
+    // Map each AMI to the launch template whose default version launches it.  Templates can only
+    // be searched by image through their versions.
+    async fn describe_launch_templates(
+        &self,
+        amis: &[&str],
+    ) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+        let mut rv = HashMap::new();
+        for chunk in amis.chunks(Self::CHUNK_SIZE) {
+            let filter = aws_sdk_ec2::model::Filter::builder()
+                .name("image-id")
+                .set_values(Some(chunk.iter().map(|a| a.to_string()).collect()))
+                .build();
+            let mut next_token = None;
+            loop {
+                let response = self
+                    .client
+                    .describe_launch_template_versions()
+                    .versions("$Default")
+                    .filters(filter.clone())
+                    .set_next_token(next_token)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        sdk_error(e, "the launch templates") as Box<dyn std::error::Error>
+                    })?;
+                for version in response.launch_template_versions().unwrap_or_default() {
+                    let image_id = version.launch_template_data().and_then(|d| d.image_id());
+                    if let (Some(image_id), Some(id)) = (image_id, version.launch_template_id()) {
+                        rv.entry(image_id.to_string())
+                            .or_insert_with(|| id.to_string());
+                    }
+                }
+                next_token = response.next_token().map(|t| t.to_string());
+                if next_token.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(rv)
+    }
+}
+
+type ImageMap = HashMap<String, aws_sdk_ec2::model::Image>;
+
+// DescribeImages is called with up to CHUNK_SIZE AMIs at a time.  Whenever the sections built
+// so far fill a chunk it is sent right away so it runs while the SSM paths that follow are read;
+// what is left over is described with the final selection.  A selection that fits in one chunk
+// takes one call, just as it would without the prefetch.  The preferred filter alone decides what
+// is prefetched; the architecture filter and --per-os-limit only ever remove AMIs so nothing that
+// is selected is missed.  The results are keyed by AMI so the order the calls finish in does not
+// matter.
+struct ImagePrefetch {
+    describer: Option<ImageDescriber>,
+    started: usize,
+    pending: Vec<String>,
+    requested: HashSet<String>,
+    tasks: Vec<tokio::task::JoinHandle<Result<ImageMap, Box<dyn std::error::Error + Send + Sync>>>>,
+}
+
+impl ImagePrefetch {
+    fn new(describer: Option<ImageDescriber>) -> Self {
+        Self {
+            describer,
+            started: 0,
+            pending: Vec::new(),
+            requested: HashSet::new(),
+            tasks: Vec::new(),
+        }
+    }
+    fn start(&mut self, sections: &[AmiDetailsWithFilter]) {
+        let describer = match &self.describer {
+            Some(describer) => describer,
+            None => return,
+        };
+        for section in sections.iter().skip(self.started) {
+            for detail in section.details.iter() {
+                if section.filter.filter(&detail.bitmask)
+                    && self.requested.insert(detail.ami.clone())
+                {
+                    self.pending.push(detail.ami.clone());
+                }
+            }
+        }
+        self.started = sections.len();
+        while self.pending.len() >= ImageDescriber::CHUNK_SIZE {
+            let amis: Vec<String> = self.pending.drain(..ImageDescriber::CHUNK_SIZE).collect();
+            let describer = describer.clone();
+            self.tasks.push(tokio::spawn(async move {
+                let amis: Vec<&str> = amis.iter().map(|a| a.as_str()).collect();
+                describer.describe_images(&amis).await
+            }));
+        }
+    }
+    // The leftover chunk and anything selected that was never prefetched are described now.
+    async fn finish(self, details: &[AmiDetail]) -> Result<ImageMap, Box<dyn std::error::Error>> {
+        let mut rv = HashMap::new();
+        let describer = match &self.describer {
+            Some(describer) => describer,
+            None => return Ok(rv),
+        };
+        let pending: HashSet<&str> = self.pending.iter().map(|a| a.as_str()).collect();
+        let rest: Vec<&str> = details
+            .iter()
+            .map(|d| d.ami.as_str())
+            .filter(|ami| pending.contains(ami) || !self.requested.contains(*ami))
+            .collect();
+        if !rest.is_empty() {
+            rv.extend(
+                describer
+                    .describe_images(&rest)
+                    .await
+                    .map_err(|e| e as Box<dyn std::error::Error>)?,
+            );
+        }
+        for task in self.tasks {
+            rv.extend(task.await?.map_err(|e| e as Box<dyn std::error::Error>)?);
+        }
+        Ok(rv)
+    }
+}
+
+// An AMI shared from another account keeps working only as long as that account keeps sharing it.
+fn check_owners(
+    options: &SelectOptions,
+    account: &str,
+    images: &HashMap<String, aws_sdk_ec2::model::Image>,
+    details: &mut [AmiDetail],
+) {
+    for detail in details.iter_mut() {
+        detail.owner = images
+            .get(&detail.ami)
+            .and_then(|i| i.owner_id())
+            .map(|o| o.to_string());
+        match detail.owner.as_deref() {
+            Some(owner)
+                if owner == account || options.owner_allowlist.iter().any(|a| a == owner) => {}
+            Some(owner) => warn!(
+                "{} ({}) is owned by account {} rather than {}; the owner can revoke access to it",
+                detail.ami, detail.name, owner, account
+            ),
+            None => warn!(
+                "{} ({}) could not be described so its owner is unknown",
+                detail.ami, detail.name
+            ),
+        }
+    }
+}
+
+fn creation_time(image: &aws_sdk_ec2::model::Image) -> Option<aws_smithy_types::DateTime> {
+    image.creation_date().and_then(clock::parse)
+}
+
+// The created column and field.  The serialized value is always UTC; --local-time only changes
+// what the table shows.
+fn add_created(
+    options: &SelectOptions,
+    images: &HashMap<String, aws_sdk_ec2::model::Image>,
+    details: &mut [AmiDetail],
+) {
+    if !options.columns.contains(&Column::Created) {
+        return;
+    }
+    for detail in details.iter_mut() {
+        if let Some(time) = images.get(&detail.ami).and_then(creation_time) {
+            detail.created = Some(clock::format(&time, clock::TimeZone::Utc));
+            if options.local_time {
+                detail.created_local = Some(clock::format(&time, clock::TimeZone::Local));
+            }
+        }
+    }
+}
+
+// --sort orders the selection once it has been filtered.  With --sort date the AMIs whose
+// creation date could not be resolved are listed last whichever way the others run.  Without
+// --sort, --reverse turns the default order around.
+fn sort_details(
+    options: &SelectOptions,
+    images: &HashMap<String, aws_sdk_ec2::model::Image>,
+    details: &mut [AmiDetail],
+) {
+    let sort = match options.sort {
+        Some(sort) => sort,
+        None => {
+            if options.reverse {
+                details.reverse();
+            }
+            return;
+        }
+    };
+    let mut created: HashMap<String, i64> = HashMap::new();
+    if sort == SortKey::Date {
+        for detail in details.iter() {
+            if let Some(time) = images.get(&detail.ami).and_then(creation_time) {
+                created.insert(detail.ami.clone(), time.secs());
+            }
+        }
+        let unresolved = details
+            .iter()
+            .filter(|d| !created.contains_key(&d.ami))
+            .count();
+        if unresolved > 0 {
+            warn!(
+                "the creation date of {} selected AMI(s) could not be resolved; they are listed last",
+                unresolved
+            );
+        }
+    }
+    details.sort_by(|a, b| {
+        let ordering = match sort {
+            SortKey::Os => a.cmp(b),
+            SortKey::Name => a.name.cmp(&b.name).then_with(|| a.ami.cmp(&b.ami)),
+            SortKey::Ami => a.ami.cmp(&b.ami),
+            SortKey::Date => match (created.get(&a.ami), created.get(&b.ami)) {
+                (Some(a), Some(b)) => b.cmp(a),
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                (None, None) => return Ordering::Equal,
+            },
+        };
+        if options.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+fn is_marketplace_image(image: &aws_sdk_ec2::model::Image) -> bool {
+    image.image_owner_alias() == Some("aws-marketplace")
+        || image.product_codes().unwrap_or_default().iter().any(|p| {
+            p.product_code_type() == Some(&aws_sdk_ec2::model::ProductCodeValues::Marketplace)
+        })
+}
+
+fn convert_all(_name: &str, _split: &Vec<&str>) -> bool {
+    false
+}
+
+fn parse_version(operating_system: OperatingSystem, name: &str) -> Option<String> {
+    match operating_system {
+        OperatingSystem::Amazon => patterns::AMAZON_VERSION
+            .captures(name)
+            .and_then(|c| c.get(1))
+            .map(|m| m.as_str().to_string()),
+        OperatingSystem::Debian => patterns::DEBIAN_VERSION
+            .captures(name)
+            .and_then(|c| c.get(1))
+            .map(|m| m.as_str().to_string()),
+        OperatingSystem::Ubuntu => patterns::UBUNTU_VERSION
+            .captures(name)
+            .and_then(|c| Some(format!("{}.{}", c.get(1)?.as_str(), c.get(2)?.as_str()))),
+        OperatingSystem::Windows => patterns::WINDOWS_VERSION
+            .captures(name)
+            .and_then(|c| c.get(1))
+            .map(|m| m.as_str().to_string()),
+        OperatingSystem::All | OperatingSystem::Custom => None,
+    }
+}
+
+fn convert_pairs_to_details<'a>(
+    operating_system: OperatingSystem,
+    extra: Option<StringBitmask>,
+    pairs: Vec<NameAmiPair>,
+    all_segments: &mut StringsToBitmask,
+    segment_separator: char,
+    ignore: &'a dyn Fn(&str, &Vec<&str>) -> bool,
+    sort: bool,
+) -> Vec<AmiDetail> {
+    let as_str: Vec<&str> = pairs.iter().map(|p| p.name.as_str()).collect();
+    let prefix = common_prefix(&as_str, '/');
+    let stripped_names: Vec<&str> = as_str
+        .iter()
+        .map(|n| n.strip_prefix(&prefix).unwrap())
+        .collect();
+    let mut details = Vec::new();
+    let os_bitmask = all_segments.bitmask_from(Some((&operating_system).into()));
+    let amd64 = all_segments.bitmask_from(["amd64"]);
+    let arm64 = all_segments.bitmask_from(["arm64"]);
+    let extra_bitmask = if let Some(extra) = extra {
+        os_bitmask | extra
+    } else {
+        os_bitmask
+    };
+    // Parameters whose names are the same once the prefix is stripped cannot be told apart by the
+    // filters.  Keep the one with the higher SSM version, then the later modification, then the
+    // higher AMI ID, so the winner does not depend on the order SSM returned them in.  The full
+    // names cannot break a tie; they share the prefix so they are the same too.
+    let mut winners: HashMap<&str, usize> = HashMap::new();
+    for (index, (name, pair)) in stripped_names.iter().zip(pairs.iter()).enumerate() {
+        if let Some(&other_index) = winners.get(name) {
+            let other = &pairs[other_index];
+            let keep = (pair.version, pair.last_modified, &pair.ami)
+                > (other.version, other.last_modified, &other.ami);
+            let (kept, dropped) = if keep { (pair, other) } else { (other, pair) };
+            let reason = if kept.version != dropped.version {
+                format!(
+                    "it has the higher SSM version ({} over {})",
+                    kept.version, dropped.version
+                )
+            } else if kept.last_modified != dropped.last_modified {
+                "the versions match and it was modified later".to_string()
+            } else {
+                "the versions and modification times match and its AMI ID sorts higher".to_string()
+            };
+            warn!(
+                "{} and {} are both named {}; keeping {} from {} because {}",
+                other.name, pair.name, name, kept.ami, kept.name, reason
+            );
+            if !keep {
+                continue;
+            }
+        }
+        winners.insert(name, index);
+    }
+    for (index, (name, pair)) in stripped_names.iter().zip(pairs.iter()).enumerate() {
+        if winners.get(name) != Some(&index) {
+            continue;
+        }
+        let split: Vec<&str> = name.split(segment_separator).collect();
+        if ignore(name, &split) {
+            continue;
+        }
+        let bitmask = all_segments.bitmask_from_segments(split) | extra_bitmask;
+        let architecture = if bitmask.0 & amd64.0 != 0 {
+            Some(Architecture::Amd64)
+        } else if bitmask.0 & arm64.0 != 0 {
+            Some(Architecture::Arm64)
+        } else {
+            None
+        };
+        details.push(AmiDetail {
+            operating_system,
+            name: name.to_string(),
+            ami: pair.ami.clone(),
+            bitmask,
+            parameter: pair.name.clone(),
+            last_modified: pair.last_modified,
+            version: parse_version(operating_system, name),
+            architecture,
+            original_name: None,
+            previous: None,
+            launch_template_id: None,
+            link: None,
+            owner: None,
+            created: None,
+            created_local: None,
+            instance_types: None,
+            instance_types_text: None,
+            ssh_username: None,
+        });
+    }
+    if sort {
+        details.sort();
+    }
+    details
+}
+
+/// The filter a preferred filter builder created along with the version it selects.
+struct VersionFilter {
+    version: String,
+    filter: Box<dyn StringBitmaskFilter>,
+}
+
+impl VersionFilter {
+    fn new<F>(version: &str, filter: F) -> Self
+    where
+        F: StringBitmaskFilter + 'static,
+    {
+        Self {
+            version: version.to_string(),
+            filter: Box::new(filter),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct VersionLabel<'a> {
+    version: usize,
+    label: &'a str,
+}
+
+fn warn_if_multiple_versions<T>(operating_system: OperatingSystem, versions: &[T], selected: &str) {
+    let count = versions.len();
+    if count > 1 {
+        let operating_system: &str = operating_system.into();
+        warn!(
+            "{} has {} versions available; selecting {}",
+            operating_system, count, selected
+        );
+    }
+}
+
+/// The Amazon Linux version in an AMI's name: `amzn` is 1, `amzn2` is 2, and `al2023` is 2023.
+/// The number alone ranks versions, so `al2023` is newer than `amzn2` and a future `al2025` newer
+/// still; the prefix (`al` or `amzn`) plays no part.  None for names that carry no version.
+fn amazon_version(detail: &AmiDetail) -> Option<VersionLabel<'_>> {
+    let captures = patterns::AMAZON_VERSION.captures(&detail.name)?;
+    let label = captures.get(1)?.as_str();
+    let version = match captures.get(3)?.as_str() {
+        "" => 1,
+        version => version.parse::<usize>().ok()?,
+    };
+    Some(VersionLabel { version, label })
+}
+
+/// The newest Amazon Linux version among `details`, ranked as `amazon_version` describes.  Ties
+/// between labels with the same number go to the label that sorts last.
+fn extract_latest_amazon_version(details: &[AmiDetail]) -> Option<VersionLabel<'_>> {
+    details.iter().filter_map(amazon_version).max()
+}
+
+fn create_preferred_filter_for_amazon(
+    details: &[AmiDetail],
+    all_segments: &mut StringsToBitmask,
+    kernel: &str,
+    minimal_preference: MinimalPreference,
+    architecture: Architecture,
+    newest: usize,
+    warn_on_multiple_versions: bool,
+) -> Option<VersionFilter> {
+    let mut versions: Vec<VersionLabel> = details.iter().filter_map(amazon_version).collect();
+    versions.sort();
+    versions.dedup();
+
+    let version = match newest {
+        0 => extract_latest_amazon_version(details)?,
+        _ => *versions.iter().rev().nth(newest)?,
+    };
+    if warn_on_multiple_versions {
+        warn_if_multiple_versions(OperatingSystem::Amazon, &versions, version.label);
+    }
+
+    let mut rv = OrFilter::new();
+
+    let mut mask = StringsToBitmaskBuilder::new(all_segments);
+    mask.update_one(version.label);
+    mask.update([kernel, "minimal", "amd64", "arm64"]);
+    let mask = mask.inner();
+
+    for architecture in architecture.segments() {
+        let mut standard = StringsToBitmaskBuilder::new(all_segments);
+        standard.update_one(version.label);
+        standard.update([kernel, architecture]);
+        let standard = standard.inner();
+
+        let mut minimal = StringsToBitmaskBuilder::new(all_segments);
+        minimal.update_one(version.label);
+        minimal.update([kernel, "minimal", architecture]);
+        let minimal = minimal.inner();
+
+        let value = match minimal_preference {
+            MinimalPreference::Exclude => standard,
+            MinimalPreference::Require => minimal,
+            MinimalPreference::Prefer => {
+                let filter = MaskEqualsValueFilter::new(mask, standard);
+                if details.iter().any(|d| filter.filter(&d.bitmask)) {
+                    standard
+                } else {
+                    minimal
+                }
+            }
+        };
+        rv.push(MaskEqualsValueFilter::new(mask, value));
+    }
+    Some(VersionFilter::new(version.label, rv))
+}
+
+fn create_preferred_filter_for_debian<'a, I>(
+    details: I,
+    all_segments: &mut StringsToBitmask,
+    architecture: Architecture,
+    newest: usize,
+    warn_on_multiple_versions: bool,
+) -> Option<VersionFilter>
+where
+    I: IntoIterator<Item = &'a AmiDetail>,
+{
+    let mut versions = Vec::new();
+    for detail in details.into_iter() {
+        if let Some(captures) = patterns::DEBIAN_VERSION.captures(&detail.name) {
+            if let Some(version) = captures.get(1) {
+                let version = version.as_str().parse::<usize>().unwrap();
+                versions.push(version);
+            }
+        }
+    }
+    versions.sort();
+    versions.dedup();
+
+    let version = versions.iter().rev().nth(newest)?.to_string();
+    if warn_on_multiple_versions {
+        warn_if_multiple_versions(OperatingSystem::Debian, &versions, &version);
+    }
+
+    let mut rv = OrFilter::new();
+
+    let mut mask = StringsToBitmaskBuilder::new(all_segments);
+    mask.update_one(&version);
+    mask.update(["latest", "amd64", "arm64"]);
+    let mask = mask.inner();
+
+    for architecture in architecture.segments() {
+        let mut value = StringsToBitmaskBuilder::new(all_segments);
+        value.update_one(&version);
+        value.update(["latest", architecture]);
+        let value = value.inner();
+        rv.push(MaskEqualsValueFilter::new(mask, value));
+    }
+    Some(VersionFilter::new(&version, rv))
+}
+
+fn create_preferred_filter_for_ubuntu<'a, I>(
+    details: I,
+    all_segments: &mut StringsToBitmask,
+    lts_only: bool,
+    architecture: Architecture,
+    newest: usize,
+    warn_on_multiple_versions: bool,
+) -> Option<VersionFilter>
+where
+    I: IntoIterator<Item = &'a AmiDetail>,
+{
+    let mut versions = Vec::new();
+    for detail in details.into_iter() {
+        if let Some(captures) = patterns::UBUNTU_VERSION.captures(&detail.name) {
+            if let (Some(major), Some(minor)) = (captures.get(1), captures.get(2)) {
+                let major = major.as_str().parse::<usize>().unwrap();
+                let minor = minor.as_str().parse::<usize>().unwrap();
+                let version = major * 100 + minor;
+                versions.push(version);
+            }
+        }
+    }
+    if lts_only {
+        // Canonical's long term support releases are the April releases of even years.
+        versions.retain(|v| (v / 100) % 2 == 0 && v % 100 == 4);
+    }
+    versions.sort();
+    versions.dedup();
+
+    let version = versions.iter().rev().nth(newest)?;
+    let version = format!("{}.{:02}", version / 100, version % 100);
+    if warn_on_multiple_versions {
+        warn_if_multiple_versions(OperatingSystem::Ubuntu, &versions, &version);
+    }
+
+    let mut rv = OrFilter::new();
+
+    let mut mask = StringsToBitmaskBuilder::new(all_segments);
+    mask.update_one(&version);
+    mask.update(["stable", "current", "amd64", "arm64"]);
+    let mask = mask.inner();
+
+    for architecture in architecture.segments() {
+        let mut value = StringsToBitmaskBuilder::new(all_segments);
+        value.update_one(&version);
+        value.update(["stable", "current", architecture]);
+        let value = value.inner();
+        rv.push(MaskEqualsValueFilter::new(mask, value));
+    }
+    Some(VersionFilter::new(&version, rv))
+}
+
+fn create_preferred_filter_for_windows<'a, I>(
+    details: I,
+    all_segments: &mut StringsToBitmask,
+    newest: usize,
+    warn_on_multiple_versions: bool,
+) -> Option<VersionFilter>
+where
+    I: IntoIterator<Item = &'a AmiDetail>,
+{
+    let mut versions = Vec::new();
+    for detail in details.into_iter() {
+        if let Some(captures) = patterns::WINDOWS_VERSION.captures(&detail.name) {
+            if let Some(version) = captures.get(1) {
+                versions.push(version.as_str());
+            }
+        }
+    }
+    versions.sort();
+    versions.dedup();
+
+    /*
+        At some point we may add "oldest supported version" to `ami-helper`.  For Windows the
+        correct choice is...
 
-    fn bitor(self, rhs: Self) -> Self::Output {
-        Self(self.0 | rhs.0)
+            Microsoft Windows Server 2012 R2 Base
+            ami-09f1b97927dbacf81
+    */
+    let version = versions.iter().rev().nth(newest)?;
+    if warn_on_multiple_versions {
+        warn_if_multiple_versions(OperatingSystem::Windows, &versions, version);
     }
-}
 
-trait StringBitmaskFilter {
-    fn filter(&self, string_bitmask: &StringBitmask) -> bool;
-}
+    let mut mask = StringsToBitmaskBuilder::new(all_segments);
+    mask.update_one(version);
+    mask.update(["English", "Full", "Base"]);
+    let mask = mask.inner();
 
-struct AlwaysTrueFilter {}
+    let mut value = StringsToBitmaskBuilder::new(all_segments);
+    value.update_one(version);
+    value.update(["English", "Full", "Base"]);
+    let value = value.inner();
 
-impl AlwaysTrueFilter {
-    fn new() -> Self {
-        Self {}
-    }
+    Some(VersionFilter::new(
+        version,
+        MaskEqualsValueFilter::new(mask, value),
+    ))
 }
 
-impl StringBitmaskFilter for AlwaysTrueFilter {
-    fn filter(&self, _: &StringBitmask) -> bool {
-        true
-    }
-}
+const COLOR_SCHEMES: [&str; 6] = [
+    "default",
+    "high-contrast",
+    "solarized",
+    "monokai",
+    "none",
+    "custom",
+];
 
-struct MaskEqualsValueFilter {
-    mask: StringBitmask,
-    value: StringBitmask,
-}
+const OS_COLOR_ARGS: [(&str, OperatingSystem); 4] = [
+    ("os-color-amazon", OperatingSystem::Amazon),
+    ("os-color-debian", OperatingSystem::Debian),
+    ("os-color-ubuntu", OperatingSystem::Ubuntu),
+    ("os-color-windows", OperatingSystem::Windows),
+];
 
-impl MaskEqualsValueFilter {
-    fn new(mask: StringBitmask, value: StringBitmask) -> Self {
-        Self { mask, value }
-    }
+/// The ANSI SGR parameters used to color the rows for each operating system.  Operating systems
+/// without a color, including the extra paths, are written uncolored.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+struct ColorScheme {
+    amazon: Option<String>,
+    debian: Option<String>,
+    ubuntu: Option<String>,
+    windows: Option<String>,
 }
 
-impl StringBitmaskFilter for MaskEqualsValueFilter {
-    fn filter(&self, string_bitmask: &StringBitmask) -> bool {
-        (string_bitmask.0 & self.mask.0) == self.value.0
+impl ColorScheme {
+    fn named(name: &str) -> Option<Self> {
+        let codes = match name {
+            "default" => ["32", "31", "33", "34"],
+            "high-contrast" => ["1;92", "1;91", "1;93", "1;96"],
+            "solarized" => ["38;5;64", "38;5;160", "38;5;136", "38;5;33"],
+            "monokai" => ["38;5;148", "38;5;197", "38;5;208", "38;5;81"],
+            "none" => return Some(Self::default()),
+            _ => return None,
+        };
+        let [amazon, debian, ubuntu, windows] = codes.map(|code| Some(code.to_string()));
+        Some(Self {
+            amazon,
+            debian,
+            ubuntu,
+            windows,
+        })
+    }
+    fn set(&mut self, operating_system: OperatingSystem, code: String) {
+        match operating_system {
+            OperatingSystem::Amazon => self.amazon = Some(code),
+            OperatingSystem::Debian => self.debian = Some(code),
+            OperatingSystem::Ubuntu => self.ubuntu = Some(code),
+            OperatingSystem::Windows => self.windows = Some(code),
+            OperatingSystem::All | OperatingSystem::Custom => {}
+        }
+    }
+    fn code(&self, operating_system: OperatingSystem) -> Option<&str> {
+        match operating_system {
+            OperatingSystem::Amazon => self.amazon.as_deref(),
+            OperatingSystem::Debian => self.debian.as_deref(),
+            OperatingSystem::Ubuntu => self.ubuntu.as_deref(),
+            OperatingSystem::Windows => self.windows.as_deref(),
+            OperatingSystem::All | OperatingSystem::Custom => None,
+        }
     }
 }
 
-struct OrFilter {
-    filters: Vec<Box<dyn StringBitmaskFilter>>,
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Column {
+    OperatingSystem,
+    Name,
+    OriginalName,
+    Ami,
+    Previous,
+    LaunchTemplate,
+    Link,
+    Owner,
+    Created,
+    Username,
+    InstanceTypes,
 }
 
-impl OrFilter {
-    fn new() -> Self {
-        Self {
-            filters: Vec::new(),
+impl Column {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "os" => Some(Self::OperatingSystem),
+            "name" => Some(Self::Name),
+            "original-name" => Some(Self::OriginalName),
+            "ami" => Some(Self::Ami),
+            "previous" => Some(Self::Previous),
+            "launch-template" => Some(Self::LaunchTemplate),
+            "link" => Some(Self::Link),
+            "owner" => Some(Self::Owner),
+            "created" => Some(Self::Created),
+            "username" => Some(Self::Username),
+            "instance-types" => Some(Self::InstanceTypes),
+            _ => None,
         }
     }
-    fn push<F>(&mut self, filter: F)
-    where
-        F: StringBitmaskFilter + 'static,
-    {
-        self.filters.push(Box::new(filter));
+    fn default_ratio(&self) -> usize {
+        match self {
+            Self::OperatingSystem => 15,
+            Self::Name => 65,
+            Self::OriginalName => 65,
+            Self::Ami => 20,
+            Self::Previous => 20,
+            Self::LaunchTemplate => 20,
+            Self::Link => 60,
+            Self::Owner => 15,
+            Self::Created => 20,
+            Self::Username => 15,
+            Self::InstanceTypes => 30,
+        }
     }
-}
-
-impl StringBitmaskFilter for OrFilter {
-    fn filter(&self, string_bitmask: &StringBitmask) -> bool {
-        if self.filters.len() > 0 {
-            for filter in self.filters.iter() {
-                if filter.filter(string_bitmask) {
-                    return true;
-                }
-            }
-            false
-        } else {
-            true
+    fn title(&self) -> &'static str {
+        match self {
+            Self::OperatingSystem => " OS ",
+            Self::Name => " Name ",
+            Self::OriginalName => " Original Name ",
+            Self::Ami => " AMI ",
+            Self::Previous => " Previous AMI ",
+            Self::LaunchTemplate => " Launch Template ",
+            Self::Link => " Link ",
+            Self::Owner => " Owner ",
+            Self::Created => " Created ",
+            Self::Username => " User ",
+            Self::InstanceTypes => " Instance Types ",
+        }
+    }
+    fn minimum_width(&self) -> usize {
+        match self {
+            Self::OperatingSystem => 12,
+            Self::Name => 30,
+            Self::OriginalName => 30,
+            Self::Ami => 21,
+            Self::Previous => 21,
+            Self::LaunchTemplate => 21,
+            Self::Link => 30,
+            Self::Owner => 14,
+            Self::Created => 26,
+            Self::Username => 13,
+            Self::InstanceTypes => 24,
+        }
+    }
+    fn text<'d>(&self, detail: &'d AmiDetail) -> &'d str {
+        match self {
+            Self::OperatingSystem => detail.operating_system.into(),
+            Self::Name => &detail.name,
+            Self::OriginalName => detail.original_name.as_deref().unwrap_or(&detail.name),
+            Self::Ami => &detail.ami,
+            Self::Previous => detail.previous.as_deref().unwrap_or("-"),
+            Self::LaunchTemplate => detail.launch_template_id.as_deref().unwrap_or("N/A"),
+            Self::Link => detail.link.as_deref().unwrap_or("-"),
+            Self::Owner => detail.owner.as_deref().unwrap_or("-"),
+            Self::Created => detail
+                .created_local
+                .as_deref()
+                .or(detail.created.as_deref())
+                .unwrap_or("unresolved"),
+            Self::Username => detail.ssh_username.as_deref().unwrap_or("-"),
+            Self::InstanceTypes => detail.instance_types_text.as_deref().unwrap_or("-"),
         }
     }
 }
 
-fn never_ignore(_: &str) -> bool {
-    false
+impl From<Column> for &str {
+    fn from(value: Column) -> &'static str {
+        match value {
+            Column::OperatingSystem => "os",
+            Column::Name => "name",
+            Column::OriginalName => "original-name",
+            Column::Ami => "ami",
+            Column::Previous => "previous",
+            Column::LaunchTemplate => "launch-template",
+            Column::Link => "link",
+            Column::Owner => "owner",
+            Column::Created => "created",
+            Column::Username => "username",
+            Column::InstanceTypes => "instance-types",
+        }
+    }
 }
 
-struct StringsToBitmask<'a> {
-    string_to_bit: HashMap<String, u8>,
-    next_bit: u8,
-    combining: HashSet<String>,
-    bit_to_string: Vec<String>,
-    aliases: HashMap<String, HashSet<String>>,
-    ignore_filter: &'a dyn Fn(&str) -> bool,
+struct DetailsReporter {
+    columns: Vec<(Column, usize)>,
+    capabilities: RenderCapabilities,
+    colors: ColorScheme,
+    hyperlinks: Option<HyperlinkTarget>,
+    header: bool,
 }
 
-impl<'a> StringsToBitmask<'a> {
-    pub fn new() -> Self {
+impl DetailsReporter {
+    fn new(columns: &[Column], capabilities: RenderCapabilities, colors: ColorScheme) -> Self {
         Self {
-            string_to_bit: HashMap::new(),
-            next_bit: 0,
-            combining: HashSet::new(),
-            bit_to_string: Vec::new(),
-            aliases: HashMap::new(),
-            ignore_filter: &never_ignore,
+            columns: columns.iter().map(|c| (*c, c.minimum_width())).collect(),
+            capabilities,
+            colors,
+            hyperlinks: None,
+            header: true,
         }
     }
-    pub fn alias<K, A>(&mut self, key: K, alias: A)
-    where
-        K: Into<String>,
-        A: Into<String>,
-    {
-        let key = key.into();
-        self.insert_one(&key);
-        let alias = alias.into();
-        self.insert_one(&alias);
-        self.aliases
-            .entry(key)
-            .or_insert(HashSet::new())
-            .insert(alias);
+    // Only the rows; the title line and the closing rule are left off.
+    fn without_header(mut self) -> Self {
+        self.header = false;
+        self
     }
-    pub fn combining<K>(&mut self, key: K)
-    where
-        K: Into<String>,
-    {
-        self.combining.insert(key.into());
+    fn without_column(mut self, column: Column) -> Self {
+        self.columns.retain(|(c, _)| *c != column);
+        self
     }
-    pub fn bitmask_from<'b, I>(&mut self, strings: I) -> StringBitmask
-    where
-        I: IntoIterator<Item = &'b str>,
-    {
-        let mut rv = StringsToBitmaskBuilder::new(self);
-        rv.update(strings);
-        rv.inner()
+    fn with_hyperlinks(mut self, hyperlinks: Option<HyperlinkTarget>) -> Self {
+        self.hyperlinks = hyperlinks;
+        self
     }
-    pub fn clear_combining(&mut self) {
-        self.combining.clear();
+    // The cell padded to width.  The link target column is wrapped in an OSC 8 hyperlink with
+    // the padding left outside of it.
+    fn padded_cell(&self, column: &Column, detail: &AmiDetail, width: usize) -> String {
+        let cell = self.cell(column, detail, width);
+        let padding = " ".repeat(width.saturating_sub(cell.chars().count()));
+        match (self.hyperlinks, &detail.link) {
+            (Some(target), Some(url)) if target.column() == *column => {
+                format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\{}", url, cell, padding)
+            }
+            _ => format!("{}{}", cell, padding),
+        }
     }
-    pub fn clear_ignore(&mut self) {
-        self.ignore_filter = &never_ignore;
+    fn cell<'d>(&self, column: &Column, detail: &'d AmiDetail, width: usize) -> Cow<'d, str> {
+        self.truncate(self.capabilities.render(column.text(detail)), width)
     }
-    pub fn ignore(&mut self, callme: &'a dyn Fn(&str) -> bool) {
-        self.ignore_filter = callme;
+    fn truncate<'d>(&self, text: Cow<'d, str>, width: usize) -> Cow<'d, str> {
+        if text.chars().count() <= width {
+            return text;
+        }
+        let ellipsis = self.capabilities.ellipsis();
+        let ellipsis_width = ellipsis.chars().count();
+        if width <= ellipsis_width {
+            return Cow::Owned(text.chars().take(width).collect());
+        }
+        let mut rv: String = text.chars().take(width - ellipsis_width).collect();
+        rv.push_str(ellipsis);
+        Cow::Owned(rv)
     }
-    pub fn insert(&mut self, key: &str) -> BitmaskT {
-        let mut rv = self.insert_one(key);
-        if let Some(aliases) = self.aliases.get(key) {
-            for alias in aliases {
-                let bit = self.string_to_bit.get(alias).unwrap();
-                rv = rv | (1 << bit);
+    fn output<'a, I>(&self, out: &mut dyn Write, details: I) -> std::io::Result<()>
+    where
+        I: IntoIterator<Item = &'a AmiDetail>,
+    {
+        let header: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(column, width)| {
+                let title = self.truncate(Cow::Borrowed(column.title()), *width);
+                format!("{0:-^1$}", title, width)
+            })
+            .collect();
+        if self.header {
+            writeln!(out, "{}", header.join("  "))?;
+        }
+        for rover in details.into_iter() {
+            let row: Vec<String> = self
+                .columns
+                .iter()
+                .map(|(column, width)| self.padded_cell(column, rover, *width))
+                .collect();
+            match self.colors.code(rover.operating_system) {
+                Some(code) => writeln!(out, "\x1b[{}m{}\x1b[0m", code, row.join("  "))?,
+                None => writeln!(out, "{}", row.join("  "))?,
             }
         }
-        rv
+        let footer: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(_, width)| format!("{0:-^1$}", "", width))
+            .collect();
+        if self.header {
+            writeln!(out, "{}", footer.join("  "))?;
+        }
+        Ok(())
     }
-    fn insert_one(&mut self, key: &str) -> BitmaskT {
-        if (self.ignore_filter)(key) {
-            0
-        } else {
-            let bit = if let Some(value) = self.string_to_bit.get(key) {
-                *value
-            } else {
-                let rv = self.next_bit;
-                self.next_bit += 1;
-                self.string_to_bit.insert(key.to_string(), rv);
-                self.bit_to_string.push(key.to_string());
-                assert!(self.bit_to_string[rv as usize] == key);
-                rv
+    // Replace the column widths so the table, including the two space separators, is exactly
+    // total characters wide.  The space is divided by weight with any remainder going to the
+    // heaviest column.  A column the weights leave narrower than its minimum then takes the
+    // difference from the columns with the most room to spare, provided they have enough.
+    fn fit_to_width(&mut self, total: usize, ratio: &[(Column, usize)]) {
+        if self.columns.is_empty() {
+            return;
+        }
+        let available = total.saturating_sub(2 * (self.columns.len() - 1));
+        let weights: Vec<usize> = self
+            .columns
+            .iter()
+            .map(|(column, _)| {
+                ratio
+                    .iter()
+                    .rev()
+                    .find(|(c, _)| c == column)
+                    .map(|(_, weight)| *weight)
+                    .unwrap_or_else(|| column.default_ratio())
+            })
+            .collect();
+        let sum: usize = weights.iter().sum();
+        let mut assigned = 0;
+        for ((_, width), weight) in self.columns.iter_mut().zip(weights.iter()) {
+            *width = match sum {
+                0 => available / weights.len(),
+                _ => available * weight / sum,
             };
-            1 << bit
+            assigned += *width;
         }
-    }
-}
-
-struct StringsToBitmaskBuilder<'a, 'b, 'c> {
-    strings_to_bitmask: &'a mut StringsToBitmask<'c>,
-    bitmask: StringBitmask,
-    contained: Option<&'b str>,
-}
-
-impl<'a, 'b, 'c> StringsToBitmaskBuilder<'a, 'b, 'c> {
-    pub fn new(strings_to_bitmask: &'a mut StringsToBitmask<'c>) -> Self {
-        Self {
-            strings_to_bitmask,
-            bitmask: StringBitmask(0),
-            contained: None,
+        let heaviest = (0..weights.len())
+            .max_by_key(|i| (weights[*i], std::cmp::Reverse(*i)))
+            .unwrap_or(0);
+        self.columns[heaviest].1 += available - assigned;
+        let spare: usize = self
+            .columns
+            .iter()
+            .map(|(column, width)| width.saturating_sub(column.minimum_width()))
+            .sum();
+        let short: usize = self
+            .columns
+            .iter()
+            .map(|(column, width)| column.minimum_width().saturating_sub(*width))
+            .sum();
+        if short == 0 || short > spare {
+            return;
         }
-    }
-    fn finalize(mut self) -> StringBitmask {
-        if let Some(contained) = self.contained.take() {
-            self.update_bitmask(&contained);
+        for (column, width) in self.columns.iter_mut() {
+            *width = (*width).max(column.minimum_width());
+        }
+        for _ in 0..short {
+            if let Some((_, width)) = self
+                .columns
+                .iter_mut()
+                .max_by_key(|(column, width)| *width - column.minimum_width())
+            {
+                *width -= 1;
+            }
         }
-        self.bitmask
-    }
-    pub fn inner(self) -> StringBitmask {
-        self.finalize()
     }
-    pub fn update<I>(&mut self, strings: I)
+    fn update_column_widths<'a, I>(&mut self, details: I)
     where
-        I: IntoIterator<Item = &'b str>,
+        I: IntoIterator<Item = &'a AmiDetail>,
     {
-        for rover in strings {
-            self.update_one(rover);
-        }
-    }
-    pub fn update_one(&mut self, key: &'b str) {
-        if let Some(contained) = self.contained.take() {
-            let combined = format!("{}-{}", contained, key);
-            self.update_bitmask(&combined);
-        } else {
-            if self.strings_to_bitmask.combining.contains(key) {
-                self.contained = Some(key);
-            } else {
-                self.update_bitmask(key);
+        let capabilities = self.capabilities;
+        for detail in details.into_iter() {
+            for (column, width) in self.columns.iter_mut() {
+                let text_width = capabilities.render(column.text(detail)).chars().count();
+                if text_width > *width {
+                    *width = text_width;
+                }
             }
         }
     }
-    fn update_bitmask(&mut self, key: &str) {
-        self.bitmask.0 = self.bitmask.0 | self.strings_to_bitmask.insert(key);
-    }
 }
 
-impl From<StringsToBitmaskBuilder<'_, '_, '_>> for StringBitmask {
-    fn from(value: StringsToBitmaskBuilder<'_, '_, '_>) -> StringBitmask {
-        value.finalize()
-    }
-}
-
-impl From<StringsToBitmaskBuilder<'_, '_, '_>> for BitmaskT {
-    fn from(value: StringsToBitmaskBuilder<'_, '_, '_>) -> BitmaskT {
-        value.finalize().0
-    }
+#[derive(Serialize)]
+struct JsonNumbers<A> {
+    count: usize,
+    by_os: BTreeMap<&'static str, usize>,
+    amis: A,
 }
 
-fn common_prefix(list: &[&str], separator: char) -> String {
-    match list {
-        [] => "".to_string(),
-        [just_one] => just_one.chars().collect(),
-        _ => {
-            let first = &list[0];
-            let mut rightmost = usize::MAX;
-            for entry in list.iter() {
-                let mut match_count = 0;
-                let mut last_separator = usize::MAX;
-                for (lft, rgt) in first.chars().zip(entry.chars()) {
-                    if match_count > rightmost {
-                        break;
-                    }
-                    if lft != rgt {
-                        if last_separator == usize::MAX {
-                            if match_count < rightmost {
-                                rightmost = match_count;
-                            }
-                        } else {
-                            if last_separator < rightmost {
-                                rightmost = last_separator;
-                            }
-                        }
-                        break;
-                    }
-                    match_count += 1;
-                    if lft == separator {
-                        last_separator = match_count;
-                    }
-                }
-            }
-            if rightmost == usize::MAX {
-                first.chars().collect()
-            } else {
-                first.chars().take(rightmost).collect()
-            }
+impl<A> JsonNumbers<A> {
+    fn new(details: &[AmiDetail], amis: A) -> Self {
+        let mut by_os = BTreeMap::new();
+        for detail in details.iter() {
+            *by_os.entry(detail.operating_system.into()).or_default() += 1;
+        }
+        Self {
+            count: details.len(),
+            by_os,
+            amis,
         }
     }
 }
 
-#[derive(Debug)]
-struct AmiDetail {
-    operating_system: OperatingSystem,
-    name: String,
-    ami: String,
-    bitmask: StringBitmask,
+// Everything --output-fields can ask for.  The image is only described when a field needs it.
+struct FieldSource<'a> {
+    region: &'a str,
+    images: &'a HashMap<String, aws_sdk_ec2::model::Image>,
+    now: aws_smithy_types::DateTime,
 }
-
-impl Eq for AmiDetail {}
-
-impl Ord for AmiDetail {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match self.operating_system.cmp(&other.operating_system) {
-            Ordering::Equal => match self.name.cmp(&other.name) {
-                Ordering::Equal => self.ami.cmp(&other.ami),
-                o @ _ => o,
+
+impl<'a> FieldSource<'a> {
+    fn value(&self, field: OutputField, detail: &AmiDetail) -> serde_json::Value {
+        use serde_json::Value;
+        let image = self.images.get(&detail.ami);
+        match field {
+            OutputField::Os => {
+                let text: &str = detail.operating_system.into();
+                Value::from(text)
+            }
+            OutputField::Name => Value::from(detail.name.as_str()),
+            OutputField::Ami => Value::from(detail.ami.as_str()),
+            OutputField::Region => Value::from(self.region),
+            OutputField::Arch => match detail.architecture {
+                Some(architecture) => Value::from(<&str>::from(architecture)),
+                None => Value::Null,
             },
-            o @ _ => o,
+            OutputField::Deprecated => match image {
+                Some(image) => Value::from(
+                    image
+                        .deprecation_time()
+                        .and_then(clock::parse)
+                        .map(|t| t.secs() <= self.now.secs())
+                        .unwrap_or(false),
+                ),
+                None => Value::Null,
+            },
+            OutputField::CreatedAt => image
+                .and_then(creation_time)
+                .map(|t| Value::from(clock::format(&t, clock::TimeZone::Utc)))
+                .unwrap_or(Value::Null),
         }
     }
-}
-
-impl PartialEq for AmiDetail {
-    fn eq(&self, other: &Self) -> bool {
-        self.operating_system == other.operating_system
-            && self.name == other.name
-            && self.ami == other.ami
+    fn record(&self, fields: &[OutputField], detail: &AmiDetail) -> serde_json::Value {
+        let mut rv = serde_json::Map::new();
+        for field in fields.iter() {
+            rv.insert(field.name().to_string(), self.value(*field, detail));
+        }
+        serde_json::Value::Object(rv)
     }
 }
 
-impl PartialOrd for AmiDetail {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+// Compact and pretty output serialize the same value so only the whitespace differs.
+fn write_json<T: Serialize>(out: &mut dyn Write, value: &T, pretty: bool) -> std::io::Result<()> {
+    if pretty {
+        serde_json::to_writer_pretty(&mut *out, value)?;
+    } else {
+        serde_json::to_writer(&mut *out, value)?;
     }
+    writeln!(out)
 }
 
-struct AmiDetailsWithFilter {
-    details: Vec<AmiDetail>,
-    filter: Box<dyn StringBitmaskFilter>,
+fn output_json(
+    out: &mut dyn Write,
+    details: &[AmiDetail],
+    json_numbers: bool,
+    pretty: bool,
+    fields: &[OutputField],
+    source: &FieldSource,
+) -> std::io::Result<()> {
+    if fields.is_empty() {
+        if json_numbers {
+            write_json(out, &JsonNumbers::new(details, details), pretty)
+        } else {
+            write_json(out, &details, pretty)
+        }
+    } else {
+        let records: Vec<serde_json::Value> =
+            details.iter().map(|d| source.record(fields, d)).collect();
+        if json_numbers {
+            write_json(out, &JsonNumbers::new(details, records), pretty)
+        } else {
+            write_json(out, &records, pretty)
+        }
+    }
 }
 
-impl AmiDetailsWithFilter {
-    fn new(details: Vec<AmiDetail>, filter: Box<dyn StringBitmaskFilter>) -> Self {
-        Self { details, filter }
-    }
-    fn into_iter(self) -> AmiDetailsWithFilterIteratorOwn {
-        let details = self.details.into_iter().map(|d| Some(d)).collect();
-        AmiDetailsWithFilterIteratorOwn {
-            details,
-            filter: self.filter,
-            rover: 0,
+// One record per line, the way output_json writes each element of its array.
+fn output_json_lines(
+    out: &mut dyn Write,
+    details: &[AmiDetail],
+    fields: &[OutputField],
+    source: &FieldSource,
+) -> std::io::Result<()> {
+    for detail in details.iter() {
+        if fields.is_empty() {
+            serde_json::to_writer(&mut *out, detail)?;
+        } else {
+            serde_json::to_writer(&mut *out, &source.record(fields, detail))?;
         }
+        writeln!(out)?;
     }
+    Ok(())
 }
 
-struct AmiDetailsWithFilterIteratorOwn {
-    details: Vec<Option<AmiDetail>>,
-    filter: Box<dyn StringBitmaskFilter>,
-    rover: usize,
+fn csv_escape(text: &str, separator: char) -> Cow<'_, str> {
+    if text.contains([separator, '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", text.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(text)
+    }
 }
 
-impl Iterator for AmiDetailsWithFilterIteratorOwn {
-    type Item = AmiDetail;
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.rover < self.details.len() {
-            let detail = self.details[self.rover].take().unwrap();
-            self.rover += 1;
-            if self.filter.filter(&detail.bitmask) {
-                return Some(detail);
+// A header row naming the fields, like export --format csv, unless --no-header.  With
+// --output-fields ami the output is the same as --just-ami: there is no header and a single AMI is
+// written without a trailing newline.  Every other row ends with a newline.  --format tsv is the
+// same with a tab separator.
+fn output_csv(
+    out: &mut dyn Write,
+    details: &[AmiDetail],
+    fields: &[OutputField],
+    source: &FieldSource,
+    separator: char,
+    header: bool,
+) -> std::io::Result<()> {
+    const DEFAULT_FIELDS: [OutputField; 3] = [OutputField::Os, OutputField::Name, OutputField::Ami];
+    let fields = if fields.is_empty() {
+        &DEFAULT_FIELDS[..]
+    } else {
+        fields
+    };
+    if header && fields != [OutputField::Ami] {
+        let names: Vec<&str> = fields
+            .iter()
+            .map(|field| match field {
+                OutputField::Os => "operating_system",
+                field => field.name(),
+            })
+            .collect();
+        writeln!(out, "{}", names.join(&separator.to_string()))?;
+    }
+    let rows: Vec<String> = details
+        .iter()
+        .map(|detail| {
+            let cells: Vec<String> = fields
+                .iter()
+                .map(|field| match source.value(*field, detail) {
+                    serde_json::Value::Null => String::new(),
+                    serde_json::Value::String(text) => csv_escape(&text, separator).into_owned(),
+                    value => value.to_string(),
+                })
+                .collect();
+            cells.join(&separator.to_string())
+        })
+        .collect();
+    match (fields, rows.as_slice()) {
+        ([OutputField::Ami], [just_one]) => write!(out, "{}", just_one),
+        _ => {
+            for row in rows.iter() {
+                writeln!(out, "{}", row)?;
             }
+            Ok(())
         }
-        None
     }
 }
 
-struct AmiDetailsWithFilterIteratorRef<'d> {
-    target: &'d AmiDetailsWithFilter,
-    rover: usize,
-}
-
-impl<'d> Iterator for AmiDetailsWithFilterIteratorRef<'d> {
-    type Item = &'d AmiDetail;
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.rover < self.target.details.len() {
-            let detail = &self.target.details[self.rover];
-            self.rover += 1;
-            if self.target.filter.filter(&detail.bitmask) {
-                return Some(detail);
-            }
+fn html_escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+    let mut rv = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '&' => rv.push_str("&amp;"),
+            '<' => rv.push_str("&lt;"),
+            '>' => rv.push_str("&gt;"),
+            '"' => rv.push_str("&quot;"),
+            '\'' => rv.push_str("&#39;"),
+            c => rv.push(c),
         }
-        None
     }
+    Cow::Owned(rv)
 }
 
-struct NameAmiPairGetter {
-    client: Client,
+#[derive(Serialize)]
+struct TomlDocument<T> {
+    ami: Vec<T>,
 }
 
-impl NameAmiPairGetter {
-    async fn new(region: Region) -> Self {
-        let region_provider = RegionProviderChain::first_try(region);
-        let config = aws_config::from_env().region(region_provider).load().await;
-        let client = Client::new(&config);
+#[derive(Serialize)]
+struct TomlAmi<'a> {
+    operating_system: &'a str,
+    name: &'a str,
+    ami: &'a str,
+}
 
-        Self { client }
-    }
-    async fn get_pairs(&self, path: &str) -> (Vec<String>, Vec<String>) {
-        // Note: Bear in mind that `into_paginator` suppresses errors.  You'll notice a lack of the
-        // question mark operator or any other error handling.  Instead an empty list is returned.
-        // No doubt some poor sole will curse that decision.
-        let mut response = self
-            .client
-            .get_parameters_by_path()
-            .path(path)
-            .recursive(true)
-            .into_paginator()
-            .send();
-        let mut names = Vec::new();
-        let mut amis = Vec::new();
-        while let Some(chunk) = response.next().await {
-            if let Ok(chunk) = chunk {
-                for parameters in chunk.parameters {
-                    for parameter in parameters.iter() {
-                        if let (Some(name), Some(value)) = (&parameter.name, &parameter.value) {
-                            names.push(name.to_string());
-                            amis.push(value.to_string());
-                        }
-                    }
-                }
-            }
+// The --output-fields of one AMI in the order they were given.  TOML has no null so a field
+// without a value, like the creation time of an image that was not described, is left out.
+struct TomlRecord(Vec<(&'static str, serde_json::Value)>);
+
+impl Serialize for TomlRecord {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        for (name, value) in self.0.iter().filter(|(_, value)| !value.is_null()) {
+            map.serialize_entry(name, value)?;
         }
-        (names, amis)
+        map.end()
     }
 }
 
-fn convert_all(_name: &str, _split: &Vec<&str>) -> bool {
-    false
+// An [[ami]] table per AMI.
+fn output_toml(
+    out: &mut dyn Write,
+    details: &[AmiDetail],
+    fields: &[OutputField],
+    source: &FieldSource,
+) -> std::io::Result<()> {
+    let text = if fields.is_empty() {
+        let ami = details
+            .iter()
+            .map(|detail| TomlAmi {
+                operating_system: detail.operating_system.into(),
+                name: &detail.name,
+                ami: &detail.ami,
+            })
+            .collect();
+        toml::to_string(&TomlDocument { ami })
+    } else {
+        let ami = details
+            .iter()
+            .map(|detail| {
+                TomlRecord(
+                    fields
+                        .iter()
+                        .map(|field| (field.name(), source.value(*field, detail)))
+                        .collect(),
+                )
+            })
+            .collect();
+        toml::to_string(&TomlDocument { ami })
+    };
+    out.write_all(text.map_err(std::io::Error::other)?.as_bytes())
 }
 
-fn convert_pairs_to_details<'a>(
-    operating_system: OperatingSystem,
-    extra: Option<StringBitmask>,
-    names: Vec<String>,
-    amis: Vec<String>,
-    all_segments: &mut StringsToBitmask,
-    segment_separator: char,
-    ignore: &'a dyn Fn(&str, &Vec<&str>) -> bool,
-) -> Vec<AmiDetail> {
-    let as_str: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
-    let prefix = common_prefix(&as_str, '/');
-    let stripped_names: Vec<&str> = as_str
-        .iter()
-        .map(|n| n.strip_prefix(&prefix).unwrap())
-        .collect();
-    let mut details = Vec::new();
-    let os_bitmask = all_segments.bitmask_from(Some((&operating_system).into()));
-    let extra_bitmask = if let Some(extra) = extra {
-        os_bitmask | extra
+// A bare table without any styling so it can be embedded in a page.  --html-document wraps it in
+// a minimal page of its own.
+fn output_html(
+    out: &mut dyn Write,
+    details: &[AmiDetail],
+    fields: &[OutputField],
+    source: &FieldSource,
+    document: bool,
+) -> std::io::Result<()> {
+    const DEFAULT_FIELDS: [OutputField; 3] = [OutputField::Os, OutputField::Name, OutputField::Ami];
+    let fields = if fields.is_empty() {
+        &DEFAULT_FIELDS[..]
     } else {
-        os_bitmask
+        fields
     };
-    for (name, ami) in stripped_names.iter().zip(amis.into_iter()) {
-        let split: Vec<&str> = name.split(segment_separator).collect();
-        if ignore(name, &split) {
-            continue;
+    if document {
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(out, "<html>")?;
+        writeln!(out, "<head>")?;
+        writeln!(out, "<meta charset=\"utf-8\">")?;
+        writeln!(out, "<title>AMIs in {}</title>", html_escape(source.region))?;
+        writeln!(out, "</head>")?;
+        writeln!(out, "<body>")?;
+    }
+    writeln!(out, "<table>")?;
+    writeln!(out, "<thead>")?;
+    write!(out, "<tr>")?;
+    for field in fields.iter() {
+        write!(out, "<th>{}</th>", field.title())?;
+    }
+    writeln!(out, "</tr>")?;
+    writeln!(out, "</thead>")?;
+    writeln!(out, "<tbody>")?;
+    for detail in details.iter() {
+        write!(out, "<tr>")?;
+        for field in fields.iter() {
+            match source.value(*field, detail) {
+                serde_json::Value::Null => write!(out, "<td></td>")?,
+                serde_json::Value::String(text) => write!(out, "<td>{}</td>", html_escape(&text))?,
+                value => write!(out, "<td>{}</td>", value)?,
+            }
         }
-        let bitmask = all_segments.bitmask_from(split.into_iter()) | extra_bitmask;
-        details.push(AmiDetail {
-            operating_system,
-            name: name.to_string(),
-            ami,
-            bitmask,
-        });
+        writeln!(out, "</tr>")?;
     }
-    details.sort();
-    details
+    writeln!(out, "</tbody>")?;
+    writeln!(out, "</table>")?;
+    if document {
+        writeln!(out, "</body>")?;
+        writeln!(out, "</html>")?;
+    }
+    Ok(())
 }
 
-#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
-struct VersionLabel<'a> {
-    version: usize,
-    label: &'a str,
+// Describe each selected AMI whose parameter has not been modified for more than the given number
+// of days.  An upstream publishing problem can leave a "latest" pointer aimed at an old image.
+fn find_stale_parameters(
+    details: &[AmiDetail],
+    stale_after_days: u64,
+    now: SystemTime,
+) -> Vec<String> {
+    const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+    let mut rv = Vec::new();
+    for detail in details.iter() {
+        let age = detail
+            .last_modified
+            .and_then(|m| now.duration_since(m).ok())
+            .map(|d| d.as_secs() / SECONDS_PER_DAY);
+        if let Some(age) = age {
+            if age > stale_after_days {
+                rv.push(format!(
+                    "{} ({}) was last modified {} days ago",
+                    detail.parameter, detail.operating_system, age
+                ));
+            }
+        }
+    }
+    rv
 }
 
-fn create_preferred_filter_for_amazon<'a, I>(
-    details: I,
-    all_segments: &mut StringsToBitmask,
-) -> Box<dyn StringBitmaskFilter>
-where
-    I: IntoIterator<Item = &'a AmiDetail>,
-{
-    let match_version = regex::Regex::new(r"^((al|amzn)([0-9]*))-").unwrap();
-    let mut versions = Vec::new();
-    for detail in details.into_iter() {
-        if let Some(captures) = match_version.captures(&detail.name) {
-            if let (Some(label), Some(version)) = (captures.get(1), captures.get(3)) {
-                let version = version.as_str();
-                let version = if version == "" {
-                    1
-                } else {
-                    version.parse::<usize>().unwrap()
-                };
-                versions.push(VersionLabel {
-                    version,
-                    label: label.as_str(),
-                });
+// Tag keys and values escape commas, equals signs, and spaces.
+fn influx_escape(text: &str) -> Cow<'_, str> {
+    if text.contains([',', '=', ' ']) {
+        let mut rv = String::with_capacity(text.len() + 4);
+        for c in text.chars() {
+            if matches!(c, ',' | '=' | ' ') {
+                rv.push('\\');
             }
+            rv.push(c);
         }
+        Cow::Owned(rv)
+    } else {
+        Cow::Borrowed(text)
     }
-    versions.sort();
+}
 
-    let mut rv = OrFilter::new();
+// One `aws ec2 run-instances` command per AMI on the instance type the smoke test would use.  An
+// AMI whose architecture is not known gets no --instance-type and AWS picks its default.
+fn output_aws_cli(out: &mut dyn Write, details: &[AmiDetail]) -> std::io::Result<()> {
+    for detail in details.iter() {
+        write!(out, "aws ec2 run-instances --image-id \"{}\"", detail.ami)?;
+        if let Some(instance_type) =
+            instance_types::smoke_test_instance_type(detail.operating_system, detail.architecture)
+        {
+            write!(out, " --instance-type \"{}\"", instance_type)?;
+        }
+        writeln!(out, " --count 1")?;
+    }
+    Ok(())
+}
 
-    if versions.len() > 0 {
-        let version = versions.last().unwrap();
+// One prefix + key + separator + AMI line per AMI.  When several AMIs share an operating system and
+// architecture, as with --all-versions, their keys fall back to the longer name based form used
+// for the Packer variables.
+fn output_key_value(
+    out: &mut dyn Write,
+    details: &[AmiDetail],
+    options: &KeyValueOptions,
+) -> std::io::Result<()> {
+    let slugs: Vec<String> = details.iter().map(|d| d.os_architecture_slug()).collect();
+    for (detail, slug) in details.iter().zip(slugs.iter()) {
+        let key = if slugs.iter().filter(|s| *s == slug).count() > 1 {
+            detail.packer_variable_name()
+        } else {
+            slug.clone()
+        };
+        writeln!(
+            out,
+            "{}{}{}{}",
+            options.prefix,
+            options.style.apply(&key),
+            options.separator,
+            detail.ami
+        )?;
+    }
+    Ok(())
+}
 
-        let mut mask = StringsToBitmaskBuilder::new(all_segments);
-        mask.update_one(&version.label);
-        mask.update(["kernel-default", "minimal", "amd64", "arm64"]);
-        let mask = mask.inner();
+// InfluxDB line protocol with one ami_available point per AMI and no timestamp so the receiver
+// supplies the time.
+fn output_influx(out: &mut dyn Write, details: &[AmiDetail]) -> std::io::Result<()> {
+    for detail in details.iter() {
+        let mut line = format!("ami_available,os={}", detail.operating_system.key());
+        if let Some(architecture) = detail.architecture {
+            let architecture: &str = architecture.into();
+            line.push_str(&format!(",arch={}", architecture));
+        }
+        if let Some(version) = &detail.version {
+            line.push_str(&format!(",version={}", influx_escape(version)));
+        }
+        line.push_str(&format!(",ami={} value=1", influx_escape(&detail.ami)));
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
 
-        let mut value = StringsToBitmaskBuilder::new(all_segments);
-        value.update_one(&version.label);
-        value.update(["kernel-default", "amd64"]);
-        let value = value.inner();
-        rv.push(MaskEqualsValueFilter::new(mask.clone(), value));
+// A SHA-256 over the JSON serialization of the sorted (os, name, ami) tuples.  The order the AMIs
+// were selected in does not change the hash; any change to an AMI, name, or operating system does.
+fn selection_checksum(details: &[AmiDetail]) -> std::io::Result<String> {
+    let mut sorted: Vec<&AmiDetail> = details.iter().collect();
+    sorted.sort();
+    let tuples: Vec<(&str, &str, &str)> = sorted
+        .iter()
+        .map(|d| (d.operating_system.into(), d.name.as_str(), d.ami.as_str()))
+        .collect();
+    let canonical = serde_json::to_vec(&tuples)?;
+    Ok(format!("sha256:{:x}", Sha256::digest(&canonical)))
+}
 
-        let mut value = StringsToBitmaskBuilder::new(all_segments);
-        value.update_one(&version.label);
-        value.update(["kernel-default", "arm64"]);
-        let value = value.inner();
-        rv.push(MaskEqualsValueFilter::new(mask.clone(), value));
+// One line per AMI followed by the checksum of the selection.
+fn output_checksums(out: &mut dyn Write, details: &[AmiDetail]) -> std::io::Result<()> {
+    let mut sorted: Vec<&AmiDetail> = details.iter().collect();
+    sorted.sort();
+    for detail in sorted.iter() {
+        writeln!(
+            out,
+            "{}  {}  {}",
+            detail.ami, detail.operating_system, detail.name
+        )?;
     }
-    Box::new(rv)
+    writeln!(out, "{}", selection_checksum(details)?)
 }
 
-fn create_preferred_filter_for_debian<'a, I>(
-    details: I,
-    all_segments: &mut StringsToBitmask,
-) -> Box<dyn StringBitmaskFilter>
-where
-    I: IntoIterator<Item = &'a AmiDetail>,
-{
-    let match_version = regex::Regex::new(r"^([1-9][0-9]*)/").unwrap();
-    let mut versions = Vec::new();
-    for detail in details.into_iter() {
-        if let Some(captures) = match_version.captures(&detail.name) {
-            if let Some(version) = captures.get(1) {
-                let version = version.as_str().parse::<usize>().unwrap();
-                versions.push(version);
+fn trace_name(
+    trace: &str,
+    sections: &[AmiDetailsWithFilter],
+    architecture_filter: &dyn StringBitmaskFilter,
+    all_segments: &StringsToBitmask,
+) {
+    let mut found = false;
+    for section in sections.iter() {
+        for detail in section.details.iter() {
+            if detail.ami != trace && !detail.parameter.contains(trace) {
+                continue;
             }
+            found = true;
+            let preferred = section.filter.filter(&detail.bitmask);
+            let architecture = architecture_filter.filter(&detail.bitmask);
+            eprintln!(
+                "trace: {}  {}  {}",
+                detail.ami, detail.operating_system, detail.name
+            );
+            eprintln!(
+                "  segments: [{}]",
+                all_segments.strings_from(detail.bitmask).join(", ")
+            );
+            eprintln!(
+                "  preferred filter: {}",
+                section.filter.explain(&detail.bitmask, all_segments)
+            );
+            eprintln!(
+                "  architecture filter: {}",
+                architecture_filter.explain(&detail.bitmask, all_segments)
+            );
+            // The preferred filter repeats the architecture so a name for the wrong architecture
+            // fails both.  The architecture is the one worth reporting.
+            let result = match (preferred, architecture) {
+                (true, true) => "selected",
+                (_, false) => "excluded by the architecture filter",
+                (false, true) => "excluded by the preferred filter",
+            };
+            eprintln!("  result: {}", result);
         }
     }
-    versions.sort();
-
-    let mut rv = OrFilter::new();
-
-    if versions.len() > 0 {
-        let version = versions.last().unwrap().to_string();
-
-        let mut mask = StringsToBitmaskBuilder::new(all_segments);
-        mask.update_one(&version);
-        mask.update(["latest", "amd64", "arm64"]);
-        let mask = mask.inner();
+    if !found {
+        let mut candidates: Vec<&str> = Vec::new();
+        for detail in sections.iter().flat_map(|s| s.details.iter()) {
+            candidates.extend(detail.version.as_deref());
+            candidates.push(&detail.name);
+            candidates.push(&detail.ami);
+        }
+        eprintln!(
+            "trace: no AMI matched {}{}",
+            trace,
+            suggest::did_you_mean(&suggest::closest(trace, candidates))
+        );
+    }
+}
 
-        let mut value = StringsToBitmaskBuilder::new(all_segments);
-        value.update_one(&version);
-        value.update(["latest", "amd64"]);
-        let value = value.inner();
-        rv.push(MaskEqualsValueFilter::new(mask.clone(), value));
+fn print_alternatives(
+    label: &str,
+    filter: &dyn StringBitmaskFilter,
+    all_segments: &StringsToBitmask,
+) {
+    let alternatives = filter.alternatives(all_segments);
+    if alternatives.len() == 1 {
+        println!("  {}: {}", label, alternatives[0]);
+    } else {
+        println!("  {}: any of", label);
+        for alternative in alternatives.iter() {
+            println!("    {}", alternative);
+        }
+    }
+}
 
-        let mut value = StringsToBitmaskBuilder::new(all_segments);
-        value.update_one(&version);
-        value.update(["latest", "arm64"]);
-        let value = value.inner();
-        rv.push(MaskEqualsValueFilter::new(mask.clone(), value));
+// --describe-filters prints what trace_name checks each name against, once per section.
+fn describe_filters(
+    sections: &[AmiDetailsWithFilter],
+    architecture_filter: &dyn StringBitmaskFilter,
+    all_segments: &StringsToBitmask,
+) {
+    for section in sections.iter() {
+        let operating_system = match section.details.first() {
+            Some(detail) => detail.operating_system,
+            None => continue,
+        };
+        println!(
+            "{}  version {}",
+            operating_system,
+            section.version.as_deref().unwrap_or("-")
+        );
+        print_alternatives("preferred", section.filter.as_ref(), all_segments);
+        if let Some(previous) = &section.previous {
+            print_alternatives("previous", previous.as_ref(), all_segments);
+        }
     }
-    Box::new(rv)
+    println!(
+        "architecture: {}",
+        architecture_filter.describe(all_segments)
+    );
 }
 
-fn create_preferred_filter_for_ubuntu<'a, I>(
-    details: I,
+fn build_architecture_filter(
+    architecture: Architecture,
     all_segments: &mut StringsToBitmask,
-) -> Box<dyn StringBitmaskFilter>
-where
-    I: IntoIterator<Item = &'a AmiDetail>,
-{
-    let match_version = regex::Regex::new(r"^([1-9][0-9]*)[.]([0-9][0-9])/").unwrap();
-    let mut versions = Vec::new();
-    for detail in details.into_iter() {
-        if let Some(captures) = match_version.captures(&detail.name) {
-            if let (Some(major), Some(minor)) = (captures.get(1), captures.get(2)) {
-                let major = major.as_str().parse::<usize>().unwrap();
-                let minor = minor.as_str().parse::<usize>().unwrap();
-                let version = major * 100 + minor;
-                versions.push(version);
+) -> Box<dyn StringBitmaskFilter> {
+    match architecture {
+        Architecture::All => Box::new(AlwaysTrueFilter::new()),
+        Architecture::Both => {
+            let mask = all_segments.bitmask_from(["amd64", "arm64"]);
+            let mut rv = OrFilter::new();
+            for value in ["amd64", "arm64"] {
+                let value = all_segments.bitmask_from([value]);
+                rv.push(MaskEqualsValueFilter::new(mask, value));
             }
+            Box::new(rv)
+        }
+        Architecture::Amd64 | Architecture::Arm64 => {
+            let mask = all_segments.bitmask_from(["amd64", "arm64"]);
+            let value = all_segments.bitmask_from([architecture.into()]);
+            Box::new(MaskEqualsValueFilter::new(mask, value))
         }
     }
-    versions.sort();
+}
 
-    let mut rv = OrFilter::new();
+// --include-arm64-only and --include-amd64-only add the AMIs published for just one architecture
+// to whatever the preferred filter chose.  A section without both architectures, like Windows,
+// has nothing to pair so it is left alone.
+fn add_unpaired_filters(
+    options: &SelectOptions,
+    sections: &mut [AmiDetailsWithFilter],
+    all_segments: &mut StringsToBitmask,
+) {
+    let mut wanted = Vec::new();
+    if options.include_arm64_only {
+        wanted.push(("arm64", "amd64"));
+    }
+    if options.include_amd64_only {
+        wanted.push(("amd64", "arm64"));
+    }
+    if wanted.is_empty() {
+        return;
+    }
+    for section in sections.iter_mut() {
+        let has = |architecture| {
+            section
+                .details
+                .iter()
+                .any(|d| d.architecture == Some(architecture))
+        };
+        if !has(Architecture::Amd64) || !has(Architecture::Arm64) {
+            continue;
+        }
+        let preferred = std::mem::replace(&mut section.filter, Box::new(AlwaysTrueFilter::new()));
+        let mut filter = OrFilter::new();
+        filter.push_boxed(preferred);
+        for (this, other) in wanted.iter() {
+            filter.push(UnpairedFilter::new(
+                &section.details,
+                all_segments.bitmask_from([*this]),
+                all_segments.bitmask_from([*other]),
+            ));
+        }
+        section.filter = Box::new(filter);
+    }
+}
+
+// Run the preferred and architecture filters over the sections then apply the display choices.
+fn finish_sections(
+    options: &SelectOptions,
+    region: &str,
+    mut sections: Vec<AmiDetailsWithFilter>,
+    all_segments: &mut StringsToBitmask,
+) -> Result<Vec<AmiDetail>, std::io::Error> {
+    add_unpaired_filters(options, &mut sections, all_segments);
+    let architecture_filter = build_architecture_filter(options.architecture, all_segments);
+    all_segments.check_capacity()?;
+    if let Some(trace) = &options.trace_name {
+        trace_name(trace, &sections, architecture_filter.as_ref(), all_segments);
+    }
+    if options.describe_filters {
+        describe_filters(&sections, architecture_filter.as_ref(), all_segments);
+    }
 
-    if versions.len() > 0 {
-        let version = versions.last().unwrap();
-        let version = format!("{}.{:02}", version / 100, version % 100);
+    let missing: Vec<String> = sections
+        .iter()
+        .filter_map(|s| {
+            s.missing_architecture(options.architecture, architecture_filter.as_ref(), region)
+        })
+        .collect();
+    if options.can_only_be_one() && !missing.is_empty() {
+        return Err(custom_error(missing.join("; ")));
+    }
+    for message in missing.iter() {
+        warn!("{}", message);
+    }
 
-        let mut mask = StringsToBitmaskBuilder::new(all_segments);
-        mask.update_one(&version);
-        mask.update(["stable", "current", "amd64", "arm64"]);
-        let mask = mask.inner();
+    let mut details: Vec<AmiDetail> = Vec::new();
+    for section in sections.into_iter() {
+        let previous = section.previous_amis(architecture_filter.as_ref());
+        let limit = options.per_os_limit.unwrap_or(usize::MAX);
+        for mut detail in section
+            .into_iter()
+            .filter(|d| architecture_filter.filter(&d.bitmask))
+            .take(limit)
+        {
+            detail.previous = previous.get(&detail.architecture).cloned();
+            details.push(detail);
+        }
+    }
 
-        let mut value = StringsToBitmaskBuilder::new(all_segments);
-        value.update_one(&version);
-        value.update(["stable", "current", "amd64"]);
-        let value = value.inner();
-        rv.push(MaskEqualsValueFilter::new(mask.clone(), value));
+    if options.eol_warning {
+        note_end_of_life(&details);
+    }
 
-        let mut value = StringsToBitmaskBuilder::new(all_segments);
-        value.update_one(&version);
-        value.update(["stable", "current", "arm64"]);
-        let value = value.inner();
-        rv.push(MaskEqualsValueFilter::new(mask.clone(), value));
+    // The filters above work with the stripped names so the selection does not depend on how the
+    // names are displayed.
+    if options.no_strip_prefix {
+        for detail in details.iter_mut() {
+            detail.name = detail.parameter.clone();
+        }
+    }
+    if options.normalize_names {
+        for detail in details.iter_mut() {
+            if let (Some(version), Some(architecture)) = (&detail.version, detail.architecture) {
+                let architecture: &str = architecture.into();
+                let normalized = format!("{}/{}", version, architecture);
+                detail.original_name = Some(std::mem::replace(&mut detail.name, normalized));
+            }
+        }
     }
-    Box::new(rv)
+    Ok(details)
 }
 
-fn create_preferred_filter_for_windows<'a, I>(
-    details: I,
-    all_segments: &mut StringsToBitmask,
-) -> Box<dyn StringBitmaskFilter>
-where
-    I: IntoIterator<Item = &'a AmiDetail>,
-{
-    let match_version = regex::Regex::new(r"\-(20[0-9][0-9])\-").unwrap();
-    let mut versions = Vec::new();
-    for detail in details.into_iter() {
-        if let Some(captures) = match_version.captures(&detail.name) {
-            if let Some(version) = captures.get(1) {
-                versions.push(version.as_str());
+fn note_end_of_life(details: &[AmiDetail]) {
+    let today = clock::today();
+    let mut noted = HashSet::new();
+    for detail in details.iter() {
+        if let Some(version) = &detail.version {
+            if let Some(date) = eol::end_of_life(detail.operating_system, version, &today) {
+                if noted.insert((detail.operating_system, version)) {
+                    let operating_system: &str = detail.operating_system.into();
+                    eprintln!(
+                        "{} {} reached end of life on {}",
+                        operating_system, version, date
+                    );
+                }
             }
         }
     }
-    versions.sort();
+}
 
-    /*
-        At some point we may add "oldest supported version" to `ami-helper`.  For Windows the
-        correct choice is...
+fn check_stale(options: &SelectOptions, details: &[AmiDetail]) -> std::io::Result<()> {
+    if let Some(stale_after_days) = options.stale_after_days {
+        let stale = find_stale_parameters(details, stale_after_days, clock::now());
+        if options.fail_on_stale && !stale.is_empty() {
+            return Err(custom_error(format!(
+                "stale AMI parameters were selected: {}",
+                stale.join("; ")
+            )));
+        }
+        for message in stale.iter() {
+            warn!("{}", message);
+        }
+    }
+    Ok(())
+}
 
-            Microsoft Windows Server 2012 R2 Base
-            ami-09f1b97927dbacf81
-    */
-    if versions.len() > 0 {
-        let version = versions.last().unwrap();
+fn add_links(options: &SelectOptions, region: &str, details: &mut [AmiDetail]) {
+    if options.hyperlink_target.is_none() && !options.show_links {
+        return;
+    }
+    let target = options.hyperlink_target.unwrap_or(HyperlinkTarget::Ami);
+    for detail in details.iter_mut() {
+        detail.link = Some(target.url(region, detail));
+    }
+}
 
-        let mut mask = StringsToBitmaskBuilder::new(all_segments);
-        mask.update_one(version);
-        mask.update(["English", "Full", "Base"]);
-        let mask = mask.inner();
+fn add_instance_types(options: &SelectOptions, details: &mut [AmiDetail]) {
+    if !options.recommend_instance_types {
+        return;
+    }
+    for detail in details.iter_mut() {
+        let instance_types =
+            instance_types::recommend(detail.operating_system, detail.architecture);
+        if !instance_types.is_empty() {
+            detail.instance_types_text = Some(instance_types.join(","));
+            detail.instance_types = Some(instance_types);
+        }
+    }
+}
 
-        let mut value = StringsToBitmaskBuilder::new(all_segments);
-        value.update_one(&version);
-        value.update(["English", "Full", "Base"]);
-        let value = value.inner();
+// --ssh-username replaces the default login user of every operating system.  Custom paths have no
+// default so they are left without one unless it was given.
+fn add_ssh_usernames(options: &SelectOptions, details: &mut [AmiDetail]) {
+    for detail in details.iter_mut() {
+        detail.ssh_username = options.ssh_username.clone().or_else(|| {
+            detail
+                .operating_system
+                .default_username()
+                .map(str::to_string)
+        });
+    }
+}
 
-        Box::new(MaskEqualsValueFilter::new(mask, value))
-    } else {
-        Box::new(OrFilter::new())
+// With --output-stream each operating system is printed as soon as it has been fetched.  The
+// table can only be sized to the rows at hand so every section is its own table.
+fn stream_sections(
+    options: &SelectOptions,
+    region: &str,
+    sections: &mut Vec<AmiDetailsWithFilter>,
+    all_segments: &mut StringsToBitmask,
+) -> std::io::Result<()> {
+    if !options.output_stream {
+        return Ok(());
+    }
+    let mut details = finish_sections(options, region, std::mem::take(sections), all_segments)?;
+    add_links(options, region, &mut details);
+    add_instance_types(options, &mut details);
+    add_ssh_usernames(options, &mut details);
+    check_stale(options, &details)?;
+    if details.is_empty() {
+        return Ok(());
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    match options.format {
+        OutputFormat::IdOnly => {
+            for detail in details.iter() {
+                writeln!(out, "{}", detail.ami)?;
+            }
+        }
+        _ => {
+            if !options.no_header {
+                writeln!(out)?;
+            }
+            let capabilities = RenderCapabilities::stdout();
+            let mut reporter =
+                DetailsReporter::new(&options.columns, capabilities, options.colors(capabilities))
+                    .with_hyperlinks(options.hyperlinks(capabilities));
+            if options.no_header {
+                reporter = reporter.without_header();
+            }
+            // Every streamed table holds a single operating system so the OS column is what
+            // tells them apart; only --no-os-column removes it.
+            if options.no_os_column {
+                reporter = reporter.without_column(Column::OperatingSystem);
+            }
+            reporter.update_column_widths(details.iter());
+            if let Some(total) = options.output_width.resolve() {
+                reporter.fit_to_width(total, &options.output_width_ratio);
+            }
+            reporter.output(&mut out, details.iter())?;
+        }
     }
+    out.flush()
 }
 
-struct DetailsReporter {
-    os_width: usize,
-    name_width: usize,
-    ami_width: usize,
+// Group the details by operating system, version, then architecture keeping the order they were
+// selected in.
+fn output_tree(
+    out: &mut dyn Write,
+    details: &[AmiDetail],
+    box_drawing: bool,
+) -> std::io::Result<()> {
+    type Architectures<'d> = Vec<(&'d str, Vec<&'d AmiDetail>)>;
+    type Versions<'d> = Vec<(&'d str, Architectures<'d>)>;
+    fn entry<K: PartialEq, V: Default>(list: &mut Vec<(K, V)>, key: K) -> &mut V {
+        let index = match list.iter().position(|(k, _)| *k == key) {
+            Some(index) => index,
+            None => {
+                list.push((key, V::default()));
+                list.len() - 1
+            }
+        };
+        &mut list[index].1
+    }
+    let mut tree: Vec<(&str, Versions)> = Vec::new();
+    for detail in details.iter() {
+        let version = detail.version.as_deref().unwrap_or("(unknown version)");
+        let architecture = detail
+            .architecture
+            .map(|a| a.into())
+            .unwrap_or("(unknown architecture)");
+        let versions = entry(&mut tree, detail.operating_system.into());
+        entry(entry(versions, version), architecture).push(detail);
+    }
+    let (branch, last, pipe) = match box_drawing {
+        true => (
+            "\u{251c}\u{2500}\u{2500} ",
+            "\u{2514}\u{2500}\u{2500} ",
+            "\u{2502}   ",
+        ),
+        false => ("+-- ", "`-- ", "|   "),
+    };
+    let connector = |is_last: bool| if is_last { last } else { branch };
+    let indent = |is_last: bool| if is_last { "    " } else { pipe };
+    for (operating_system, versions) in tree.iter() {
+        writeln!(out, "{}", operating_system)?;
+        for (v, (version, architectures)) in versions.iter().enumerate() {
+            let v_last = v + 1 == versions.len();
+            writeln!(out, "{}{}", connector(v_last), version)?;
+            for (a, (architecture, amis)) in architectures.iter().enumerate() {
+                let a_last = a + 1 == architectures.len();
+                writeln!(
+                    out,
+                    "{}{}{}",
+                    indent(v_last),
+                    connector(a_last),
+                    architecture
+                )?;
+                for (d, detail) in amis.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "{}{}{}{}  {}",
+                        indent(v_last),
+                        indent(a_last),
+                        connector(d + 1 == amis.len()),
+                        detail.ami,
+                        detail.name
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
 }
 
-impl DetailsReporter {
-    fn new() -> Self {
-        Self {
-            os_width: 12,
-            name_width: 30,
-            ami_width: 21,
+fn select_full_matrix<'d>(
+    details: &'d [AmiDetail],
+    all_segments: &mut StringsToBitmask,
+    allow_missing: &[MatrixCell],
+) -> Result<Vec<(MatrixCell, &'d AmiDetail)>, std::io::Error> {
+    let mask = all_segments.bitmask_from(["amd64", "arm64"]);
+    let mut selected = Vec::new();
+    let mut problems = Vec::new();
+    for cell in FULL_MATRIX.iter() {
+        let value = all_segments.bitmask_from([cell.architecture.into()]);
+        let filter = MaskEqualsValueFilter::new(mask, value);
+        let candidates: Vec<&AmiDetail> = details
+            .iter()
+            .filter(|d| d.operating_system == cell.operating_system && filter.filter(&d.bitmask))
+            .collect();
+        match candidates.len() {
+            1 => selected.push((*cell, candidates[0])),
+            0 if allow_missing.contains(cell) => {
+                debug!(
+                    "no AMI was selected for {} but it is allowed to be missing",
+                    cell
+                )
+            }
+            n => problems.push(format!("{} selected {} AMIs", cell, n)),
         }
     }
-    fn output<'a, I>(&self, details: I)
-    where
-        I: IntoIterator<Item = &'a AmiDetail>,
-    {
-        println!(
-            "{0:-^1$}  {2:-^3$}  {4:-^5$}",
-            " OS ", self.os_width, " Name ", self.name_width, " AMI ", self.ami_width
-        );
-        for rover in details.into_iter() {
-            println!(
-                "{0:<1$}  {2:<3$}  {4:<5$}",
-                rover.operating_system,
-                self.os_width,
-                rover.name,
-                self.name_width,
-                rover.ami,
-                self.ami_width
-            );
+    if !problems.is_empty() {
+        return Err(custom_error(format!(
+            "full-matrix requires exactly one AMI for each combination but {}",
+            problems.join(", ")
+        )));
+    }
+    Ok(selected)
+}
+
+fn output_full_matrix(
+    out: &mut dyn Write,
+    selected: &[(MatrixCell, &AmiDetail)],
+    format: OutputFormat,
+    pretty: bool,
+) -> std::io::Result<()> {
+    if format == OutputFormat::Json {
+        let entries: Vec<MatrixEntry> = selected
+            .iter()
+            .map(|(cell, detail)| MatrixEntry {
+                os: cell.os_name(),
+                arch: cell.architecture.into(),
+                image_id: &detail.ami,
+                instance_type: cell.instance_type(),
+            })
+            .collect();
+        write_json(out, &entries, pretty)?;
+    } else {
+        for (cell, detail) in selected.iter() {
+            writeln!(
+                out,
+                "{} --image-id \"{}\" --instance-type \"{}\"",
+                cell,
+                detail.ami,
+                cell.instance_type()
+            )?;
         }
-        println!(
-            "{0:-^1$}  {2:-^3$}  {4:-^5$}",
-            "", self.os_width, "", self.name_width, "", self.ami_width
-        );
     }
-    fn update_column_widths<'a, I>(&mut self, details: I)
-    where
-        I: IntoIterator<Item = &'a AmiDetail>,
-    {
-        let mut os_width = self.os_width;
-        let mut name_width = self.name_width;
-        let mut ami_width = self.ami_width;
+    Ok(())
+}
 
-        for detail in details.into_iter() {
-            if detail.operating_system.text_width() > os_width {
-                os_width = detail.operating_system.text_width();
-            }
-            if detail.name.len() > name_width {
-                name_width = detail.name.len();
-            }
-            if detail.ami.len() > ami_width {
-                ami_width = detail.ami.len();
+fn unescape_template(template: &str) -> String {
+    let mut rv = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            rv.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => rv.push('\n'),
+            Some('t') => rv.push('\t'),
+            Some('\\') => rv.push('\\'),
+            Some(other) => {
+                rv.push('\\');
+                rv.push(other);
             }
+            None => rv.push('\\'),
         }
-        self.os_width = os_width;
-        self.name_width = name_width;
-        self.ami_width = ami_width;
     }
+    rv
+}
+
+#[derive(Serialize)]
+struct TemplateRecord<'a> {
+    os: &'a str,
+    name: &'a str,
+    ami: &'a str,
+    version: Option<&'a str>,
+    architecture: Option<&'a str>,
+    previous: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct TemplateContext<'a> {
+    amis: Vec<TemplateRecord<'a>>,
+    count: usize,
+    region: &'a str,
+}
+
+fn output_template(
+    out: &mut dyn Write,
+    template: &str,
+    details: &[AmiDetail],
+    region: &str,
+) -> std::io::Result<()> {
+    let amis: Vec<TemplateRecord> = details
+        .iter()
+        .map(|detail| TemplateRecord {
+            os: detail.operating_system.into(),
+            name: &detail.name,
+            ami: &detail.ami,
+            version: detail.version.as_deref(),
+            architecture: detail.architecture.map(|a| a.into()),
+            previous: detail.previous.as_deref(),
+        })
+        .collect();
+    let context = TemplateContext {
+        count: amis.len(),
+        amis,
+        region,
+    };
+    let mut registry = handlebars::Handlebars::new();
+    registry.register_escape_fn(handlebars::no_escape);
+    registry
+        .register_template_string("output", template)
+        .map_err(custom_error)?;
+    let rendered = registry.render("output", &context).map_err(custom_error)?;
+    out.write_all(rendered.as_bytes())
 }
 
+// The bitmask statistics cover every way out of the selection, --output-stream and
+// --describe-filters and errors included.
 async fn do_select(options: SelectOptions) -> Result<(), Box<dyn std::error::Error>> {
-    let getter = NameAmiPairGetter::new(Region::new(options.region.clone())).await;
     let mut all_segments = StringsToBitmask::new();
-    all_segments.alias("x86_64", "amd64");
+    let result = select_and_write(&options, &mut all_segments).await;
+    #[cfg(feature = "instrumentation")]
+    eprintln!("{}", all_segments.stats());
+    result
+}
+
+async fn select_and_write(
+    options: &SelectOptions,
+    all_segments: &mut StringsToBitmask<'_>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = &options.record {
+        serde_json::to_writer_pretty(File::create(path)?, options)?;
+    }
+    if options.all_versions && options.per_os_limit.is_none() {
+        warn!("--all-versions selects every AMI under each path; the list can be long, --per-os-limit shortens it");
+    }
+    let region = resolve_region(options).await;
+    let region_name = region.to_string();
+    for arn in options
+        .extra_paths
+        .iter()
+        .filter_map(|path| path.arn.as_deref())
+    {
+        parameter_path::check_region(arn, &region_name).map_err(custom_error)?;
+    }
+    let audit = match &options.audit_log {
+        Some(path) => Some(audit::AuditLog::open(path)?),
+        None => None,
+    };
+    let context = AwsContext::with_profile(
+        region,
+        options.aws_max_attempts,
+        options.profile.as_deref(),
+        options.endpoint_url.as_deref(),
+    )
+    .await
+    .with_audit_log(audit.clone());
+    if options.enforce_account {
+        if let Some(expected) = &options.expected_account {
+            context.enforce_account(expected).await?;
+        }
+    }
+    let getter = NameAmiPairGetter::new(&context, options.max_parameters)
+        .with_retry_on(options.retry_on.clone(), options.retry_on_attempts);
+    for (from, to) in options.aliases.iter() {
+        all_segments.alias(from.as_str(), to.as_str());
+    }
+    for (from, to) in AliasFile::load()?.iter() {
+        all_segments.alias(from, to);
+    }
     let mut operating_systems: Vec<AmiDetailsWithFilter> = Vec::new();
+    let needs_images = options.virtualization.is_some()
+        || options.since_date.is_some()
+        || options.exclude_marketplace
+        || options.with_owner_check
+        || options.sort == Some(SortKey::Date)
+        || options.columns.contains(&Column::Created)
+        || options.output_fields.iter().any(|f| f.needs_image());
+    let mut prefetch = ImagePrefetch::new(
+        (needs_images && !options.output_stream && !options.describe_filters)
+            .then(|| ImageDescriber::new(&context)),
+    );
 
-    if options.include_amazon() {
-        let (names, amis) = getter
-            .get_pairs("/aws/service/ami-amazon-linux-latest")
-            .await;
+    if options.includes(OperatingSystem::Amazon) {
+        let pairs = getter
+            .get_pairs(&ParameterPath::builtin(
+                "/aws/service/ami-amazon-linux-latest",
+            ))
+            .await?;
         all_segments.combining("kernel");
         all_segments.clear_ignore();
         let details = convert_pairs_to_details(
             OperatingSystem::Amazon,
             None,
-            names,
-            amis,
-            &mut all_segments,
+            pairs,
+            all_segments,
             '-',
             &convert_all,
+            !options.no_sort,
         );
-        let preferred = create_preferred_filter_for_amazon(&details, &mut all_segments);
-        let amazon = AmiDetailsWithFilter::new(details, preferred);
+        let (preferred, version) = options.preferred_filter(OperatingSystem::Amazon, |newest| {
+            create_preferred_filter_for_amazon(
+                &details,
+                all_segments,
+                &options.preferred_kernel,
+                options.minimal_preference,
+                options.architecture,
+                newest,
+                options.warn_on_multiple_versions && newest == options.version_offset,
+            )
+        })?;
+        let previous = options.previous_filter(|newest| {
+            create_preferred_filter_for_amazon(
+                &details,
+                all_segments,
+                &options.preferred_kernel,
+                options.minimal_preference,
+                options.architecture,
+                newest,
+                false,
+            )
+        });
+        let amazon = AmiDetailsWithFilter::new(details, preferred)
+            .with_version(version)
+            .with_previous(previous);
         operating_systems.push(amazon);
+        prefetch.start(&operating_systems);
+        stream_sections(options, &region_name, &mut operating_systems, all_segments)?;
     }
 
-    if options.include_debian() {
-        let (names, amis) = getter.get_pairs("/aws/service/debian/release").await;
+    if options.includes(OperatingSystem::Debian) {
+        let pairs = getter
+            .get_pairs(&ParameterPath::builtin("/aws/service/debian/release"))
+            .await?;
         all_segments.clear_combining();
-        all_segments.ignore(&|s| {
-            static DATE_SERIAL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{8}-\d+$").unwrap());
-            DATE_SERIAL.is_match(s)
-        });
+        all_segments.ignore(&|s| patterns::DATE_SERIAL.is_match(s));
         let details = convert_pairs_to_details(
             OperatingSystem::Debian,
             None,
-            names,
-            amis,
-            &mut all_segments,
+            pairs,
+            all_segments,
             '/',
             &convert_all,
+            !options.no_sort,
         );
-        let preferred = create_preferred_filter_for_debian(&details, &mut all_segments);
-        let debian = AmiDetailsWithFilter::new(details, preferred);
+        let (preferred, version) = options.preferred_filter(OperatingSystem::Debian, |newest| {
+            create_preferred_filter_for_debian(
+                &details,
+                all_segments,
+                options.architecture,
+                newest,
+                options.warn_on_multiple_versions && newest == options.version_offset,
+            )
+        })?;
+        let previous = options.previous_filter(|newest| {
+            create_preferred_filter_for_debian(
+                &details,
+                all_segments,
+                options.architecture,
+                newest,
+                false,
+            )
+        });
+        let debian = AmiDetailsWithFilter::new(details, preferred)
+            .with_version(version)
+            .with_previous(previous);
         operating_systems.push(debian);
+        prefetch.start(&operating_systems);
+        stream_sections(options, &region_name, &mut operating_systems, all_segments)?;
     }
 
-    if options.include_ubuntu() {
-        let (names, amis) = getter
-            .get_pairs("/aws/service/canonical/ubuntu/server")
-            .await;
+    if options.includes(OperatingSystem::Ubuntu) {
+        let pairs = getter
+            .get_pairs(&ParameterPath::builtin(options.ubuntu_variant.path()))
+            .await?;
         all_segments.clear_combining();
-        all_segments.ignore(&|s| {
-            static DATE_REVISION: Lazy<Regex> =
-                Lazy::new(|| Regex::new(r"^\d{8}(?:[.]\d+)?$").unwrap());
-            DATE_REVISION.is_match(s)
-        });
+        all_segments.ignore(&|s| patterns::DATE_REVISION.is_match(s));
         let details = convert_pairs_to_details(
             OperatingSystem::Ubuntu,
             None,
-            names,
-            amis,
-            &mut all_segments,
+            pairs,
+            all_segments,
             '/',
             &convert_all,
+            !options.no_sort,
         );
-        let preferred = create_preferred_filter_for_ubuntu(&details, &mut all_segments);
-        let ubuntu = AmiDetailsWithFilter::new(details, preferred);
+        let (preferred, version) = options.preferred_filter(OperatingSystem::Ubuntu, |newest| {
+            create_preferred_filter_for_ubuntu(
+                &details,
+                all_segments,
+                options.lts_only,
+                options.architecture,
+                newest,
+                options.warn_on_multiple_versions && newest == options.version_offset,
+            )
+        })?;
+        let previous = options.previous_filter(|newest| {
+            create_preferred_filter_for_ubuntu(
+                &details,
+                all_segments,
+                options.lts_only,
+                options.architecture,
+                newest,
+                false,
+            )
+        });
+        let ubuntu = AmiDetailsWithFilter::new(details, preferred)
+            .with_version(version)
+            .with_previous(previous);
         operating_systems.push(ubuntu);
+        prefetch.start(&operating_systems);
+        stream_sections(options, &region_name, &mut operating_systems, all_segments)?;
     }
 
-    if options.include_windows() {
-        let (names, amis) = getter.get_pairs("/aws/service/ami-windows-latest").await;
+    if options.includes(OperatingSystem::Windows) {
+        let pairs = getter
+            .get_pairs(&ParameterPath::builtin("/aws/service/ami-windows-latest"))
+            .await?;
         all_segments.clear_combining();
         all_segments.clear_ignore();
         let ab = all_segments.bitmask_from(["amd64"]);
         let details = convert_pairs_to_details(
             OperatingSystem::Windows,
             Some(ab),
-            names,
-            amis,
-            &mut all_segments,
+            pairs,
+            all_segments,
             '-',
             &|n, s| {
                 if !n.starts_with("Windows_Server") {
@@ -1228,29 +7310,117 @@ async fn do_select(options: SelectOptions) -> Result<(), Box<dyn std::error::Err
                 }
                 false
             },
+            !options.no_sort,
         );
-        let preferred = create_preferred_filter_for_windows(&details, &mut all_segments);
-        let windows = AmiDetailsWithFilter::new(details, preferred);
+        let (preferred, version) =
+            options.preferred_filter(OperatingSystem::Windows, |newest| {
+                create_preferred_filter_for_windows(
+                    &details,
+                    all_segments,
+                    newest,
+                    options.warn_on_multiple_versions && newest == options.version_offset,
+                )
+            })?;
+        let previous = options.previous_filter(|newest| {
+            create_preferred_filter_for_windows(&details, all_segments, newest, false)
+        });
+        let windows = AmiDetailsWithFilter::new(details, preferred)
+            .with_version(version)
+            .with_previous(previous);
         operating_systems.push(windows);
+        prefetch.start(&operating_systems);
+        stream_sections(options, &region_name, &mut operating_systems, all_segments)?;
     }
 
-    let architecture_filter: Box<dyn StringBitmaskFilter> =
-        if options.architecture != Architecture::All {
-            let mask = all_segments.bitmask_from(["amd64", "arm64"]);
-            let value = all_segments.bitmask_from([options.architecture.into()]);
-            Box::new(MaskEqualsValueFilter::new(mask, value))
-        } else {
-            Box::new(AlwaysTrueFilter::new())
-        };
-    let mut details: Vec<AmiDetail> = Vec::new();
-    for section in operating_systems.into_iter() {
-        for detail in section.into_iter() {
-            if architecture_filter.filter(&detail.bitmask) {
-                details.push(detail);
-            }
+    for extra_path in options.extra_paths.iter() {
+        let pairs = getter.get_pairs(extra_path).await?;
+        all_segments.clear_combining();
+        all_segments.clear_ignore();
+        let mut details = convert_pairs_to_details(
+            OperatingSystem::Custom,
+            None,
+            pairs,
+            all_segments,
+            '/',
+            &convert_all,
+            !options.no_sort,
+        );
+        for detail in details.iter_mut() {
+            detail.name = format!("{}/{}", extra_path.label, detail.name);
+        }
+        let custom = AmiDetailsWithFilter::new(details, Box::new(AlwaysTrueFilter::new()));
+        operating_systems.push(custom);
+        prefetch.start(&operating_systems);
+        stream_sections(options, &region_name, &mut operating_systems, all_segments)?;
+    }
+
+    if options.output_stream {
+        if let Some(audit) = &audit {
+            audit.summary(None);
+        }
+        return Ok(());
+    }
+    let mut details = finish_sections(options, &region_name, operating_systems, all_segments)?;
+    if options.describe_filters {
+        if let Some(audit) = &audit {
+            audit.summary(None);
+        }
+        return Ok(());
+    }
+
+    let images = prefetch.finish(&details).await?;
+
+    if let Some(virtualization) = options.virtualization {
+        let wanted: &str = virtualization.into();
+        details.retain(|d| {
+            images
+                .get(&d.ami)
+                .and_then(|i| i.virtualization_type())
+                .map(|v| v.as_str() == wanted)
+                .unwrap_or(false)
+        });
+    }
+
+    if let Some(since_date) = &options.since_date {
+        details.retain(|d| {
+            images
+                .get(&d.ami)
+                .and_then(creation_time)
+                .map(|t| t.secs() >= *since_date)
+                .unwrap_or(false)
+        });
+    }
+
+    if options.exclude_marketplace {
+        if let Some(detail) = details.iter().find(|d| !images.contains_key(&d.ami)) {
+            return Err(Box::new(custom_error(format!(
+                "{} could not be described so there is no telling whether it is a Marketplace AMI",
+                detail.ami
+            ))));
+        }
+        details.retain(|d| !images.get(&d.ami).map(is_marketplace_image).unwrap_or(true));
+    }
+
+    if options.with_owner_check {
+        check_owners(options, context.account().await?, &images, &mut details);
+    }
+
+    if options.resolve_launch_templates {
+        let describer = ImageDescriber::new(&context);
+        let amis: Vec<&str> = details.iter().map(|d| d.ami.as_str()).collect();
+        let templates = describer.describe_launch_templates(&amis).await?;
+        for detail in details.iter_mut() {
+            detail.launch_template_id = templates.get(&detail.ami).cloned();
         }
     }
 
+    add_created(options, &images, &mut details);
+    sort_details(options, &images, &mut details);
+
+    add_links(options, &region_name, &mut details);
+    add_instance_types(options, &mut details);
+    add_ssh_usernames(options, &mut details);
+
     if options.can_only_be_one() && details.len() != 1 {
         return Err(Box::new(custom_error(format!(
             "singleton or smoke-test was specified but {} AMIs were selected",
@@ -1258,65 +7428,498 @@ async fn do_select(options: SelectOptions) -> Result<(), Box<dyn std::error::Err
         ))));
     }
 
-    if options.smoke_test {
-        print!(
+    if let Some(expected) = &options.assert_ami {
+        if details.len() != 1 {
+            return Err(Box::new(custom_error(format!(
+                "assert-ami requires exactly one AMI but {} were selected",
+                details.len()
+            ))));
+        }
+        if details[0].ami != *expected {
+            return Err(Box::new(custom_error(format!(
+                "assert-ami failed for {}\n- {}\n+ {}",
+                details[0].name, expected, details[0].ami
+            ))));
+        }
+    }
+
+    check_stale(options, &details)?;
+
+    if let Some(path) = &options.export_packer_vars {
+        export_packer_vars(path, &details)?;
+    }
+
+    let field_source = FieldSource {
+        region: &region_name,
+        images: &images,
+        now: aws_smithy_types::DateTime::from(clock::now()),
+    };
+    let mut out: Vec<u8> = Vec::new();
+    if let Some(export) = options.export {
+        export
+            .backend(!options.no_header)
+            .export(&details, &mut out)?;
+    } else if options.full_matrix {
+        let selected = select_full_matrix(&details, all_segments, &options.allow_missing)?;
+        output_full_matrix(&mut out, &selected, options.format, options.pretty_json)?;
+    } else if options.smoke_test {
+        let instance_group = options
+            .instance_group()
+            .ok_or_else(|| custom_error("smoke-test requires --architecture amd64 or arm64"))?;
+        write!(
+            out,
             "--image-id \"{}\" --instance-type \"{}.medium\"",
-            details[0].ami,
-            options.instance_group()
-        );
+            details[0].ami, instance_group
+        )?;
     } else if options.just_ami {
         if details.len() == 1 {
-            print!("{}", details[0].ami);
+            write!(out, "{}", details[0].ami)?;
         } else {
             for detail in details.iter() {
-                println!("{}", detail.ami);
+                writeln!(out, "{}", detail.ami)?;
+            }
+        }
+    } else {
+        match options.format {
+            OutputFormat::Table => {
+                if !options.no_header {
+                    writeln!(out)?;
+                }
+                let capabilities = RenderCapabilities::stdout();
+                let mut reporter = DetailsReporter::new(
+                    &options.columns,
+                    capabilities,
+                    options.colors(capabilities),
+                )
+                .with_hyperlinks(options.hyperlinks(capabilities));
+                if options.omit_os_column(&details) {
+                    reporter = reporter.without_column(Column::OperatingSystem);
+                }
+                if options.no_header {
+                    reporter = reporter.without_header();
+                }
+                reporter.update_column_widths(details.iter());
+                if let Some(total) = options.output_width.resolve() {
+                    reporter.fit_to_width(total, &options.output_width_ratio);
+                }
+                reporter.output(&mut out, details.iter())?;
+                if !options.no_header {
+                    writeln!(out)?;
+                }
+            }
+            OutputFormat::Json => output_json(
+                &mut out,
+                &details,
+                options.json_numbers,
+                options.pretty_json,
+                &options.output_fields,
+                &field_source,
+            )?,
+            OutputFormat::JsonLines => {
+                output_json_lines(&mut out, &details, &options.output_fields, &field_source)?
+            }
+            OutputFormat::Csv => output_csv(
+                &mut out,
+                &details,
+                &options.output_fields,
+                &field_source,
+                ',',
+                !options.no_header,
+            )?,
+            OutputFormat::Tsv => output_csv(
+                &mut out,
+                &details,
+                &options.output_fields,
+                &field_source,
+                '\t',
+                !options.no_header,
+            )?,
+            OutputFormat::Html => output_html(
+                &mut out,
+                &details,
+                &options.output_fields,
+                &field_source,
+                options.html_document,
+            )?,
+            OutputFormat::IdOnly => {
+                for detail in details.iter() {
+                    writeln!(out, "{}", detail.ami)?;
+                }
+            }
+            OutputFormat::Checksums => output_checksums(&mut out, &details)?,
+            OutputFormat::Influx => output_influx(&mut out, &details)?,
+            OutputFormat::AwsCli => output_aws_cli(&mut out, &details)?,
+            OutputFormat::Toml => {
+                output_toml(&mut out, &details, &options.output_fields, &field_source)?
+            }
+            OutputFormat::KeyValue => output_key_value(&mut out, &details, &options.key_value)?,
+            OutputFormat::Template => output_template(
+                &mut out,
+                options.template.as_deref().unwrap_or_default(),
+                &details,
+                &region_name,
+            )?,
+            OutputFormat::Tree => {
+                let box_drawing = options.output.is_none()
+                    && std::io::stdout().is_terminal()
+                    && RenderCapabilities::stdout().unicode;
+                output_tree(&mut out, &details, box_drawing)?
+            }
+            #[cfg(feature = "protobuf")]
+            OutputFormat::Protobuf => protobuf::output_protobuf(
+                &mut out,
+                details.iter().map(|detail| detail.to_protobuf()),
+            )?,
+        }
+    }
+
+    match &options.output {
+        Some(path) => write_atomically(path, &out)?,
+        None => {
+            let stdout = std::io::stdout();
+            let mut lock = stdout.lock();
+            lock.write_all(&out)?;
+            lock.flush()?;
+        }
+    }
+
+    if options.checksum {
+        eprintln!("{}", selection_checksum(&details)?);
+    }
+
+    if let Some(audit) = &audit {
+        audit.summary(Some(&selection_checksum(&details)?));
+    }
+
+    Ok(())
+}
+
+// The EKS optimized Amazon Linux 2023 AMI recommended for a Kubernetes version.
+fn eks_parameter_name(k8s_version: &str, architecture: Architecture) -> String {
+    let architecture = match architecture {
+        Architecture::Arm64 => "arm64",
+        _ => "x86_64",
+    };
+    format!(
+        "/aws/service/eks/optimized-ami/{}/amazon-linux-2023/{}/standard/recommended/image_id",
+        k8s_version, architecture
+    )
+}
+
+async fn do_karpenter(options: KarpenterOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let region = pick_region(options.region.as_deref(), options.profile.as_deref()).await;
+    let region_name = region.to_string();
+    let context = AwsContext::with_profile(
+        region,
+        3,
+        options.profile.as_deref(),
+        options.endpoint_url.as_deref(),
+    )
+    .await;
+    let architectures = match options.architecture {
+        Architecture::All | Architecture::Both => vec![Architecture::Amd64, Architecture::Arm64],
+        architecture => vec![architecture],
+    };
+    let names: Vec<String> = architectures
+        .iter()
+        .map(|a| eks_parameter_name(&options.k8s_version, *a))
+        .collect();
+    let client = context.ssm();
+    let response = client
+        .get_parameters()
+        .set_names(Some(names.clone()))
+        .send()
+        .await?;
+    if !response.invalid_parameters().unwrap_or_default().is_empty() {
+        return Err(Box::new(custom_error(format!(
+            "EKS does not publish optimized AMIs for Kubernetes {} in {}",
+            options.k8s_version, region_name
+        ))));
+    }
+    let parameters = response.parameters().unwrap_or_default();
+    let mut out: Vec<u8> = Vec::new();
+    writeln!(out, "apiVersion: karpenter.k8s.aws/v1")?;
+    writeln!(out, "kind: EC2NodeClass")?;
+    writeln!(out, "metadata:")?;
+    writeln!(out, "  name: {}", options.node_class_name)?;
+    writeln!(out, "spec:")?;
+    writeln!(out, "  amiFamily: AL2023")?;
+    writeln!(out, "  amiSelectorTerms:")?;
+    for (architecture, name) in architectures.iter().zip(names.iter()) {
+        let ami = parameters
+            .iter()
+            .find(|p| p.name() == Some(name.as_str()))
+            .and_then(|p| p.value())
+            .ok_or_else(|| custom_error(format!("{} has no value", name)))?;
+        let architecture: &str = (*architecture).into();
+        writeln!(out, "    - id: {}  # {}", ami, architecture)?;
+    }
+    std::io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+// Hand every parameter under path that has a name and a value to f, one page at a time, and
+// return how many there were.  Unlike select, a failed page is an error; a dump with silent gaps
+// would be misleading.
+async fn for_each_parameter<F>(
+    client: &Client,
+    path: &str,
+    mut f: F,
+) -> Result<usize, Box<dyn std::error::Error>>
+where
+    F: FnMut(&aws_sdk_ssm::model::Parameter, &str, &str) -> std::io::Result<()>,
+{
+    let mut response = client
+        .get_parameters_by_path()
+        .path(path)
+        .recursive(true)
+        .into_paginator()
+        .send();
+    let mut count = 0;
+    while let Some(chunk) = response.next().await {
+        for parameter in chunk?.parameters().unwrap_or_default() {
+            if let (Some(name), Some(value)) = (parameter.name(), parameter.value()) {
+                f(parameter, name, value)?;
+                count += 1;
             }
         }
+    }
+    Ok(count)
+}
+
+// The names are written as SSM returns them, prefix and all, so the output can be compared with
+// the catalog or turned into fixtures as is.
+async fn do_dump(options: DumpOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let region = pick_region(options.region.as_deref(), options.profile.as_deref()).await;
+    let context = AwsContext::with_profile(
+        region,
+        3,
+        options.profile.as_deref(),
+        options.endpoint_url.as_deref(),
+    )
+    .await;
+    let client = context.ssm();
+    let paths = builtin_paths(options.operating_system);
+    if options.format == DumpOutputFormat::Text {
+        let stdout = std::io::stdout();
+        let mut out = std::io::BufWriter::new(stdout.lock());
+        for path in paths {
+            for_each_parameter(&client, path, |_, name, value| {
+                writeln!(out, "{}={}", name, value)
+            })
+            .await?;
+        }
+        out.flush()?;
     } else {
-        println!();
-        let mut reporter = DetailsReporter::new();
-        reporter.update_column_widths(details.iter());
-        reporter.output(details.iter());
-        println!();
+        let format = match options.format {
+            DumpOutputFormat::Json => dump::DumpFormat::Json,
+            _ => dump::DumpFormat::JsonLines,
+        };
+        let mut writer = dump::DumpWriter::stdout(format)?;
+        for path in paths {
+            for_each_parameter(&client, path, |_, name, value| {
+                writer.write(&dump::NameValue { path, name, value })
+            })
+            .await?;
+        }
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+async fn do_raw(options: RawOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let region = pick_region(options.region.as_deref(), options.profile.as_deref()).await;
+    let context = AwsContext::with_profile(
+        region,
+        3,
+        options.profile.as_deref(),
+        options.endpoint_url.as_deref(),
+    )
+    .await;
+    let client = context.ssm();
+    let mut writer = match &options.out {
+        Some((path, format)) => dump::DumpWriter::create(path, *format)?,
+        None => dump::DumpWriter::stdout(dump::DumpFormat::JsonLines)?,
+    };
+    let mut counts = Vec::new();
+    for path in options.paths.iter() {
+        let count = for_each_parameter(&client, path, |parameter, name, value| {
+            writer.write(&dump::DumpRecord {
+                path,
+                name,
+                value,
+                version: parameter.version(),
+                last_modified: parameter
+                    .last_modified_date()
+                    .and_then(|d| d.fmt(aws_smithy_types::date_time::Format::DateTime).ok()),
+            })
+        })
+        .await?;
+        counts.push((path, count));
+    }
+    writer.finish()?;
+    for (path, count) in counts.iter() {
+        eprintln!("{}  {}", count, path);
+    }
+    Ok(())
+}
+
+async fn do_check(options: CheckOptions) -> Result<(), Box<dyn std::error::Error>> {
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const PARAMETER: &str = "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-x86_64";
+    let check = async {
+        let region = pick_region(options.region.as_deref(), options.profile.as_deref()).await;
+        let context = AwsContext::with_profile(region, 1, options.profile.as_deref(), None).await;
+        let client = context.ssm();
+        client.get_parameter().name(PARAMETER).send().await
+    };
+    let output = match tokio::time::timeout(TIMEOUT, check).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(SdkError::ServiceError { err, .. })) => {
+            return Err(Box::new(custom_error(format!(
+                "check failed: {}: {}",
+                err.code().unwrap_or("unknown error"),
+                err.message().unwrap_or("no message")
+            ))))
+        }
+        Ok(Err(error)) => return Err(Box::new(custom_error(format!("check failed: {}", error)))),
+        Err(_) => {
+            return Err(Box::new(custom_error(format!(
+                "check failed: no response within {} seconds",
+                TIMEOUT.as_secs()
+            ))))
+        }
+    };
+    match output.parameter().and_then(|p| p.value()) {
+        Some(ami) => {
+            println!("OK {}", ami);
+            Ok(())
+        }
+        None => Err(Box::new(custom_error(format!(
+            "check failed: {} has no value",
+            PARAMETER
+        )))),
     }
+}
 
+fn do_alias(command: AliasCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = AliasFile::load()?;
+    match command {
+        AliasCommand::Add { from, to } => {
+            file.add(&from, &to);
+            file.save()?;
+        }
+        AliasCommand::List => {
+            for (from, to) in BUILTIN_ALIASES {
+                println!("{}={}  built-in", from, to);
+            }
+            for (from, to) in file.iter() {
+                println!("{}={}", from, to);
+            }
+        }
+        AliasCommand::Remove { from } => {
+            if !file.remove(&from) {
+                let message = if BUILTIN_ALIASES.iter().any(|(f, _)| *f == from) {
+                    format!("{} is a built-in alias and cannot be removed", from)
+                } else {
+                    format!("there is no configured alias for {}", from)
+                };
+                return Err(Box::new(custom_error(message)));
+            }
+            file.save()?;
+        }
+    }
     Ok(())
 }
 
+/// Replace each `@<filename>` argument with the arguments listed one per line in that file.
+/// Blank lines and lines starting with `#` are ignored.  Response files cannot reference
+/// other response files.
+fn expand_response_files(args: Vec<String>) -> Result<Vec<String>, std::io::Error> {
+    let mut expanded = Vec::with_capacity(args.len());
+    for arg in args {
+        let Some(filename) = arg.strip_prefix('@') else {
+            expanded.push(arg);
+            continue;
+        };
+        let contents = std::fs::read_to_string(filename).map_err(|error| {
+            custom_error(format!(
+                "Unable to read response file {}: {}",
+                filename, error
+            ))
+        })?;
+        for line in contents.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('@') {
+                return Err(custom_error(format!(
+                    "Response file {} references {}; response files cannot be nested.",
+                    filename, line
+                )));
+            }
+            expanded.push(line.to_string());
+        }
+    }
+    Ok(expanded)
+}
+
 async fn inner_main() -> Result<(), Box<dyn std::error::Error>> {
-    let raw_args = std::env::args().skip(1).collect::<Vec<String>>();
+    let raw_args = expand_response_files(std::env::args().skip(1).collect::<Vec<String>>())?;
     let t = get_ami_helper_command(&raw_args);
     match t {
-        Ok(Some(command)) => match command {
-            AmiHelperCommand::Select(options) => {
+        Ok(command) => match command {
+            AmiHelperCommand::Alias(command) => do_alias(command),
+            AmiHelperCommand::Select(options) | AmiHelperCommand::Export(options) => {
                 let mut errors = Vec::new();
+                // With --profile the credentials come from the shared configuration instead.
                 match var("AWS_ACCESS_KEY_ID") {
+                    _ if options.profile.is_some() => {}
                     Err(VarError::NotPresent) => errors.push("AWS_ACCESS_KEY_ID is not set.  It must be set to a valid AWS access key ID."),
                     Err(VarError::NotUnicode(_)) => errors.push("While AWS_ACCESS_KEY_ID is set it is not valid Unicode.  It must be set to a valid AWS access key ID."),
                     Ok(_) => {}
                 }
                 match var("AWS_SECRET_ACCESS_KEY") {
+                    _ if options.profile.is_some() => {}
                     Err(VarError::NotPresent) => errors.push("AWS_SECRET_ACCESS_KEY is not set.  It must be set to a valid AWS access key ID."),
                     Err(VarError::NotUnicode(_)) => errors.push("While AWS_SECRET_ACCESS_KEY is set it is not valid Unicode.  It must be set to a valid AWS access key ID."),
                     Ok(_) => {}
                 }
-                if errors.len() == 0 {
-                    do_select(options).await
+                if errors.is_empty() {
+                    // Short STS sessions can expire part way through a run.  Each call to
+                    // `do_select` loads the AWS configuration anew which picks up refreshed
+                    // credentials so start over from scratch exactly once.  --output-stream may
+                    // already have printed some operating systems so it cannot start over.
+                    let output_stream = options.output_stream;
+                    match do_select(options.clone()).await {
+                        Err(error) if error.is::<CredentialsExpired>() && !output_stream => {
+                            do_select(options).await
+                        }
+                        result => result,
+                    }
                 } else {
                     Err(Box::new(custom_error(errors.join("  "))).into())
                 }
             }
+            AmiHelperCommand::Check(options) => do_check(options).await,
+            AmiHelperCommand::Dump(options) => do_dump(options).await,
+            AmiHelperCommand::Karpenter(options) => do_karpenter(options).await,
+            AmiHelperCommand::Raw(options) => do_raw(options).await,
             AmiHelperCommand::Version => {
                 const VERSION: &str = env!("CARGO_PKG_VERSION");
                 println!("{}", VERSION);
                 Ok(())
             }
         },
-        Ok(None) => panic!("get_ami_helper_command has a bug.  This state should be unreachable."),
         Err(e) => {
             if e.kind == clap::ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand {
                 eprintln!("{}", e);
                 Ok(())
+            } else if e.kind == clap::ErrorKind::DisplayHelp {
+                // Asked for, so it goes to standard output and is not a failure.  A closed pipe,
+                // as with `| head`, is not worth reporting.
+                let _ = e.print();
+                Ok(())
             } else {
                 Err(Box::new(custom_error(e)).into())
             }
@@ -1326,8 +7929,40 @@ async fn inner_main() -> Result<(), Box<dyn std::error::Error>> {
 
 #[tokio::main]
 async fn main() -> UseDisplay<Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_writer(|| redact::RedactingStderr)
+        .with_ansi(RenderCapabilities::stderr().ansi)
+        .with_env_filter(
+            EnvFilter::try_from_env("AMI_HELPER_LOG").unwrap_or_else(|_| EnvFilter::new("warn")),
+        )
+        .init();
     match inner_main().await {
         Ok(()) => UseDisplay::success(),
         Err(error) => UseDisplay::error(error),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::OperatingSystem;
+
+    // All and Custom stand for no one operating system so neither has a login user.
+    #[test]
+    fn default_username() {
+        for (operating_system, username) in [
+            (OperatingSystem::All, None),
+            (OperatingSystem::Amazon, Some("ec2-user")),
+            (OperatingSystem::Debian, Some("admin")),
+            (OperatingSystem::Ubuntu, Some("ubuntu")),
+            (OperatingSystem::Windows, Some("Administrator")),
+            (OperatingSystem::Custom, None),
+        ] {
+            assert_eq!(
+                operating_system.default_username(),
+                username,
+                "{:?}",
+                operating_system
+            );
+        }
+    }
+}
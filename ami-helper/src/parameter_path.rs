@@ -0,0 +1,89 @@
+// Cleans up the SSM paths typed or pasted into --path and --extra-path.  Paths copied from the
+// console or a script often have a stray slash or are whole parameter ARNs.  GetParametersByPath
+// rejects both, so they are fixed here and the user is told what changed.  Anything that cannot
+// be fixed is an error naming the input.  An ARN's region is checked later, against the region
+// the selection actually runs in.
+
+/// A path ready for GetParametersByPath and the fixes made to get there, for the warning.  `arn`
+/// is the input when the path came from a parameter ARN.
+#[derive(Debug)]
+pub(crate) struct Normalized {
+    pub(crate) path: String,
+    pub(crate) changes: Vec<&'static str>,
+    pub(crate) arn: Option<String>,
+}
+
+// Besides the slashes between segments, SSM allows letters, digits, and these in parameter names.
+fn is_allowed(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/')
+}
+
+/// `arn:aws:ssm:us-east-1:123456789012:parameter/aws/service/...` becomes `/aws/service/...`.
+fn from_arn(input: &str) -> Result<&str, String> {
+    let fields: Vec<&str> = input.splitn(6, ':').collect();
+    let (service, resource) = match fields[..] {
+        [_, _, service, _, _, resource] => (service, resource),
+        _ => return Err(format!("{} is not a complete ARN", input)),
+    };
+    if service != "ssm" {
+        return Err(format!("{} is not an SSM parameter ARN", input));
+    }
+    resource
+        .strip_prefix("parameter")
+        .filter(|path| path.starts_with('/'))
+        .ok_or_else(|| format!("{} is not an SSM parameter ARN", input))
+}
+
+/// GetParametersByPath only reads the region the client is in, so an ARN from another one would
+/// quietly read a different parameter.  An ARN without a region fits any.
+pub(crate) fn check_region(arn: &str, region: &str) -> Result<(), String> {
+    match arn.split(':').nth(3) {
+        Some(arn_region) if !arn_region.is_empty() && arn_region != region => Err(format!(
+            "{} is in {} but the AMIs are being selected in {}",
+            arn, arn_region, region
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Adds a missing leading `/`, removes a trailing one, and collapses runs of `/`.  A parameter
+/// ARN is reduced to its path.  `wildcards` allows the `*` that --path accepts.
+pub(crate) fn normalize(input: &str, wildcards: bool) -> Result<Normalized, String> {
+    let mut changes = Vec::new();
+    let mut path = input.trim();
+    if path.len() != input.len() {
+        changes.push("removed the surrounding spaces");
+    }
+    let arn = path.starts_with("arn:").then(|| path.to_string());
+    if let Some(arn) = &arn {
+        path = from_arn(arn)?;
+        changes.push("took the path from the ARN");
+    }
+    if let Some(c) = path
+        .chars()
+        .find(|c| !(is_allowed(*c) || wildcards && *c == '*'))
+    {
+        return Err(format!(
+            "{} contains {:?}, which SSM does not allow in a parameter path",
+            input, c
+        ));
+    }
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        return Err(format!("{} does not name a path", input));
+    }
+    if !path.starts_with('/') {
+        changes.push("added the leading /");
+    }
+    if path.ends_with('/') {
+        changes.push("removed the trailing /");
+    }
+    if path.contains("//") {
+        changes.push("collapsed repeated /");
+    }
+    Ok(Normalized {
+        path: format!("/{}", segments.join("/")),
+        changes,
+        arn,
+    })
+}
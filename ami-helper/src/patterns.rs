@@ -0,0 +1,254 @@
+// Every regular expression used to pick apart parameter names.  The preferred filters and
+// `parse_version` share the version extractors so what is reported as a version is always what
+// the filters choose between.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// `al2023-ami-kernel-default-x86_64`, `amzn2-ami-hvm-x86_64-gp2`, `amzn-ami-hvm-x86_64-gp2`.
+/// Captures the label (`al2023`), its prefix (`al`) and the possibly empty number (`2023`).
+pub(crate) static AMAZON_VERSION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^((al|amzn)([0-9]*))-").unwrap());
+
+/// `12/latest/amd64`.  Captures the release number.
+pub(crate) static DEBIAN_VERSION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([1-9][0-9]*)/").unwrap());
+
+/// `24.04/stable/current/amd64/hvm/ebs-gp3/ami-id`.  Captures the year and the month.
+pub(crate) static UBUNTU_VERSION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([1-9][0-9]*)[.]([0-9][0-9])/").unwrap());
+
+/// `Windows_Server-2022-English-Full-Base`.  Captures the year.
+pub(crate) static WINDOWS_VERSION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\-(20[0-9][0-9])\-").unwrap());
+
+/// Debian build segments like `20230601-1402`.
+pub(crate) static DATE_SERIAL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{8}-\d+$").unwrap());
+
+/// Ubuntu build segments like `20230601` or `20230601.1`.
+pub(crate) static DATE_REVISION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\d{8}(?:[.]\d+)?$").unwrap());
+
+/// Calendar dates like `2024-01-01`.
+pub(crate) static CALENDAR_DATE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap());
+
+/// Kubernetes minor versions like `1.30`.
+pub(crate) static KUBERNETES_VERSION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^1[.][1-9][0-9]*$").unwrap());
+
+/// AMI ids like `ami-0a699202e5027c10d` or the older eight digit `ami-1a2b3c4d`.
+pub(crate) static AMI_ID: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^ami-[0-9a-f]{8}(?:[0-9a-f]{9})?$").unwrap());
+
+#[cfg(test)]
+mod tests {
+    use once_cell::sync::Lazy;
+    use regex::Regex;
+
+    use crate::patterns;
+
+    fn check(name: &str, regex: &Lazy<Regex>, matching: &[&str], other: &[&str]) {
+        for text in matching.iter() {
+            assert!(regex.is_match(text), "{} should match {}", name, text);
+        }
+        for text in other.iter() {
+            assert!(!regex.is_match(text), "{} should not match {}", name, text);
+        }
+    }
+
+    fn captures<'t>(regex: &Lazy<Regex>, text: &'t str) -> Vec<&'t str> {
+        let captures = regex.captures(text).unwrap();
+        captures
+            .iter()
+            .skip(1)
+            .map(|c| c.map(|c| c.as_str()).unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn amazon_version() {
+        check(
+            "AMAZON_VERSION",
+            &patterns::AMAZON_VERSION,
+            &[
+                "al2023-ami-kernel-default-x86_64",
+                "al2023-ami-minimal-kernel-6.1-arm64",
+                "amzn2-ami-hvm-x86_64-gp2",
+                "amzn-ami-hvm-x86_64-gp2",
+                "al2025-ami-kernel-default-x86_64",
+            ],
+            &[
+                "ami-amazon-linux-latest",
+                "al2023",
+                "xal2023-ami-kernel-default-x86_64",
+                "amazon-linux-2",
+                "bottlerocket-aws-k8s-1.30",
+            ],
+        );
+        let regex = &patterns::AMAZON_VERSION;
+        assert_eq!(
+            captures(regex, "al2023-ami-kernel-default-x86_64"),
+            ["al2023", "al", "2023"]
+        );
+        assert_eq!(
+            captures(regex, "amzn2-ami-hvm-x86_64-gp2"),
+            ["amzn2", "amzn", "2"]
+        );
+        assert_eq!(
+            captures(regex, "amzn-ami-hvm-x86_64-gp2"),
+            ["amzn", "amzn", ""]
+        );
+    }
+
+    #[test]
+    fn debian_version() {
+        check(
+            "DEBIAN_VERSION",
+            &patterns::DEBIAN_VERSION,
+            &[
+                "12/latest/amd64",
+                "11/20240507-1740/arm64",
+                "100/latest/amd64",
+            ],
+            &[
+                "012/latest/amd64",
+                "latest/amd64",
+                "12",
+                "bookworm/latest/amd64",
+            ],
+        );
+        assert_eq!(
+            captures(&patterns::DEBIAN_VERSION, "12/latest/amd64"),
+            ["12"]
+        );
+    }
+
+    #[test]
+    fn ubuntu_version() {
+        check(
+            "UBUNTU_VERSION",
+            &patterns::UBUNTU_VERSION,
+            &[
+                "24.04/stable/current/amd64/hvm/ebs-gp3/ami-id",
+                "20.04/stable/current/arm64/hvm/ebs-gp2/ami-id",
+                "24.10/stable/current/amd64/hvm/ebs-gp3/ami-id",
+            ],
+            &[
+                "jammy/stable/current/amd64/hvm/ebs-gp2/ami-id",
+                "24.4/stable/current/amd64",
+                "24.04",
+                "04.04/stable",
+                "24-04/stable",
+            ],
+        );
+        assert_eq!(
+            captures(&patterns::UBUNTU_VERSION, "22.04/stable/current"),
+            ["22", "04"]
+        );
+    }
+
+    #[test]
+    fn windows_version() {
+        check(
+            "WINDOWS_VERSION",
+            &patterns::WINDOWS_VERSION,
+            &[
+                "Windows_Server-2022-English-Full-Base",
+                "Windows_Server-2016-English-Core-Base",
+                "Windows_Server-2019-Japanese-Full-SQL_2019_Standard",
+            ],
+            &[
+                "Windows_Server-2022",
+                "Windows_Server-1909-English-Core-Base",
+                "Windows_Server-22-English-Full-Base",
+                "EC2LaunchV2-Windows_Server-English-Full-Base",
+            ],
+        );
+        assert_eq!(
+            captures(
+                &patterns::WINDOWS_VERSION,
+                "Windows_Server-2019-English-Full-Base"
+            ),
+            ["2019"]
+        );
+    }
+
+    #[test]
+    fn date_serial() {
+        check(
+            "DATE_SERIAL",
+            &patterns::DATE_SERIAL,
+            &["20230601-1402", "20240507-1740", "20240507-1"],
+            &[
+                "20230601",
+                "20230601-",
+                "2023061-1402",
+                "20230601-1402a",
+                "x20230601-1402",
+            ],
+        );
+    }
+
+    #[test]
+    fn date_revision() {
+        check(
+            "DATE_REVISION",
+            &patterns::DATE_REVISION,
+            &["20230601", "20230601.1", "20240423.12"],
+            &[
+                "20230601.",
+                "2023060",
+                "20230601-1402",
+                "current",
+                "20230601.1a",
+            ],
+        );
+    }
+
+    #[test]
+    fn calendar_date() {
+        check(
+            "CALENDAR_DATE",
+            &patterns::CALENDAR_DATE,
+            &["2024-01-01", "1999-12-31", "2024-13-45"],
+            &[
+                "2024-1-01",
+                "24-01-01",
+                "2024/01/01",
+                "20240101",
+                "2024-01-01T00:00:00Z",
+                " 2024-01-01",
+            ],
+        );
+    }
+
+    #[test]
+    fn kubernetes_version() {
+        check(
+            "KUBERNETES_VERSION",
+            &patterns::KUBERNETES_VERSION,
+            &["1.30", "1.29", "1.100", "1.1"],
+            &["1.0", "1.05", "2.1", "1.30.1", "v1.30", "1.", "130"],
+        );
+    }
+
+    #[test]
+    fn ami_id() {
+        check(
+            "AMI_ID",
+            &patterns::AMI_ID,
+            &["ami-0a699202e5027c10d", "ami-1a2b3c4d"],
+            &[
+                "ami-0A699202E5027C10D",
+                "ami-0a699202e5027c10",
+                "ami-0a699202e5027c10dd",
+                "ami-1a2b3c4",
+                "ami-1a2b3c4g",
+                "snap-0a699202e5027c10d",
+                " ami-1a2b3c4d",
+                "ami-",
+            ],
+        );
+    }
+}
@@ -0,0 +1,29 @@
+// Hand-written prost mirror of proto/ami_helper.proto.  Keep the field tags in sync with the
+// schema.  Deriving the messages directly avoids needing protoc at build time.
+
+use std::io::Write;
+
+use prost::Message;
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct AmiDetail {
+    #[prost(string, tag = "1")]
+    pub(crate) operating_system: String,
+    #[prost(string, tag = "2")]
+    pub(crate) name: String,
+    #[prost(string, tag = "3")]
+    pub(crate) ami: String,
+}
+
+pub(crate) fn output_protobuf<I>(out: &mut dyn Write, messages: I) -> std::io::Result<()>
+where
+    I: IntoIterator<Item = AmiDetail>,
+{
+    let mut buffer = Vec::new();
+    for message in messages.into_iter() {
+        message
+            .encode_length_delimited(&mut buffer)
+            .map_err(std::io::Error::other)?;
+    }
+    out.write_all(&buffer)
+}
@@ -0,0 +1,102 @@
+// AMI_HELPER_RENDER replaces what was detected with a comma separated list of the capabilities to
+// assume, `unicode` and `ansi`, or an empty value for neither.  Either rendering can then be
+// reproduced on any console.
+
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, Debug)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// What the console receiving the output is able to render.  Legacy Windows consoles do not
+/// necessarily use UTF-8 and only interpret ANSI escape sequences when virtual terminal
+/// processing is enabled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct RenderCapabilities {
+    pub(crate) unicode: bool,
+    pub(crate) ansi: bool,
+}
+
+impl RenderCapabilities {
+    pub(crate) fn stdout() -> Self {
+        assumed().unwrap_or_else(|| detect(Stream::Stdout))
+    }
+    pub(crate) fn stderr() -> Self {
+        assumed().unwrap_or_else(|| detect(Stream::Stderr))
+    }
+    pub(crate) fn ellipsis(&self) -> &'static str {
+        if self.unicode {
+            "\u{2026}"
+        } else {
+            "..."
+        }
+    }
+    pub(crate) fn render<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        if self.unicode || text.is_ascii() {
+            Cow::Borrowed(text)
+        } else {
+            let mut rv = String::with_capacity(text.len());
+            for c in text.chars() {
+                match c {
+                    '\u{2026}' => rv.push_str(self.ellipsis()),
+                    c if c.is_ascii() => rv.push(c),
+                    _ => rv.push('?'),
+                }
+            }
+            Cow::Owned(rv)
+        }
+    }
+}
+
+fn assumed() -> Option<RenderCapabilities> {
+    let text = std::env::var("AMI_HELPER_RENDER").ok()?;
+    let capabilities: Vec<&str> = text.split(',').map(str::trim).collect();
+    Some(RenderCapabilities {
+        unicode: capabilities.contains(&"unicode"),
+        ansi: capabilities.contains(&"ansi"),
+    })
+}
+
+#[cfg(not(windows))]
+fn detect(_stream: Stream) -> RenderCapabilities {
+    RenderCapabilities {
+        unicode: true,
+        ansi: true,
+    }
+}
+
+#[cfg(windows)]
+fn detect(stream: Stream) -> RenderCapabilities {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetConsoleOutputCP, GetStdHandle, SetConsoleMode,
+        ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+    };
+    const CP_UTF8: u32 = 65001;
+
+    let which = match stream {
+        Stream::Stdout => STD_OUTPUT_HANDLE,
+        Stream::Stderr => STD_ERROR_HANDLE,
+    };
+    // SAFETY: The console functions only read and write the local `mode` and tolerate invalid
+    // or redirected handles by returning zero.
+    unsafe {
+        let handle = GetStdHandle(which);
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            // Not a console.  The output is going to a file or a pipe which receives the UTF-8
+            // bytes as is but has no use for escape sequences.
+            return RenderCapabilities {
+                unicode: true,
+                ansi: false,
+            };
+        }
+        let ansi = (mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+            || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0;
+        RenderCapabilities {
+            unicode: GetConsoleOutputCP() == CP_UTF8,
+            ansi,
+        }
+    }
+}
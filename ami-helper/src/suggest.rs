@@ -0,0 +1,64 @@
+// "did you mean" suggestions for values typed on the command line.  A transposed pair of
+// characters or a dropped dot counts as a single edit so `24.40` and `2404` both find `24.04`.
+
+/// The most edits a candidate can be away from the input and still be suggested.
+fn max_distance(input: &str) -> usize {
+    (input.chars().count() / 3).max(1)
+}
+
+// Optimal string alignment distance: Levenshtein plus swapping two neighboring characters.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Up to three candidates close to `input`, nearest first.  Empty when nothing is close enough
+/// to be worth suggesting.
+pub(crate) fn closest<'c, I>(input: &str, candidates: I) -> Vec<&'c str>
+where
+    I: IntoIterator<Item = &'c str>,
+{
+    let limit = max_distance(input);
+    let mut scored: Vec<(usize, &str)> = Vec::new();
+    for candidate in candidates {
+        if candidate == input || scored.iter().any(|(_, c)| *c == candidate) {
+            continue;
+        }
+        let d = distance(input, candidate);
+        if d <= limit {
+            scored.push((d, candidate));
+        }
+    }
+    scored.sort();
+    scored.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+/// `; did you mean '24.04'?` ready to be appended to a message, or an empty string when there
+/// is nothing to suggest.
+pub(crate) fn did_you_mean<S: AsRef<str>>(close: &[S]) -> String {
+    if close.is_empty() {
+        return String::new();
+    }
+    let quoted: Vec<String> = close.iter().map(|c| format!("'{}'", c.as_ref())).collect();
+    format!("; did you mean {}?", quoted.join(" or "))
+}
@@ -0,0 +1,104 @@
+// Where the configuration directory ends up, checked through the alias subcommand which is the
+// one command that writes to it and needs no AWS credentials.
+
+use std::path::{Path, PathBuf};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("dirs")
+        .join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn add_alias(root: &Path, env: &[(&str, &Path)], args: &[&str]) {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_ami-helper"));
+    command
+        .env("HOME", root.join("home"))
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("AMI_HELPER_CONFIG_DIR")
+        .env_remove("APPDATA");
+    for (name, value) in env {
+        command.env(name, value);
+    }
+    let output = command
+        .args(args)
+        .args(["alias", "add", "x86_64", "amd64"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+// Only the most specific location is written; each less specific one stays empty.
+#[test]
+fn config_dir_precedence() {
+    let root = scratch("precedence");
+    let flag = root.join("flag");
+    let env = root.join("env");
+    let xdg = root.join("xdg");
+    let flag_arg = flag.to_str().unwrap();
+
+    add_alias(
+        &root,
+        &[("AMI_HELPER_CONFIG_DIR", &env), ("XDG_CONFIG_HOME", &xdg)],
+        &["--config-dir", flag_arg],
+    );
+    assert!(flag.join("aliases.toml").exists());
+    assert!(!env.exists());
+    assert!(!xdg.exists());
+
+    add_alias(
+        &root,
+        &[("AMI_HELPER_CONFIG_DIR", &env), ("XDG_CONFIG_HOME", &xdg)],
+        &[],
+    );
+    assert!(env.join("aliases.toml").exists());
+    assert!(!xdg.exists());
+
+    add_alias(&root, &[("XDG_CONFIG_HOME", &xdg)], &[]);
+    assert!(xdg.join("ami-helper").join("aliases.toml").exists());
+
+    // A relative XDG_CONFIG_HOME is ignored as the specification requires.
+    add_alias(&root, &[("XDG_CONFIG_HOME", Path::new("relative"))], &[]);
+    #[cfg(not(any(windows, target_os = "macos")))]
+    assert!(root
+        .join("home")
+        .join(".config")
+        .join("ami-helper")
+        .join("aliases.toml")
+        .exists());
+}
+
+#[test]
+fn config_dir_after_the_subcommand() {
+    let root = scratch("after");
+    let flag = root.join("flag");
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_ami-helper"));
+    let output = command
+        .env("HOME", root.join("home"))
+        .args(["alias", "add", "x86_64", "amd64"])
+        .args(["--config-dir", flag.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(flag.join("aliases.toml").exists());
+    assert!(!root.join("home").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn created_directories_are_private() {
+    use std::os::unix::fs::PermissionsExt;
+    let root = scratch("private");
+    let flag = root.join("a").join("b");
+    add_alias(&root, &[], &["--config-dir", flag.to_str().unwrap()]);
+    for dir in [root.join("a"), flag] {
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700, "{}", dir.display());
+    }
+}
@@ -0,0 +1,92 @@
+// Run the select subcommand against the real SSM endpoints in us-east-2.  These tests need AWS
+// credentials so they only run when AMI_HELPER_LIVE_TESTS=1; otherwise each one returns at once.
+// The catalog changes over time so they check invariants rather than exact AMIs.
+
+use regex::Regex;
+use serde_json::Value;
+
+fn live() -> bool {
+    std::env::var("AMI_HELPER_LIVE_TESTS").as_deref() == Ok("1")
+}
+
+// The (name, ami) pairs selected for one operating system.
+fn select(operating_system: &str) -> Vec<(String, String)> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_ami-helper"))
+        .arg("select")
+        .args([
+            "--region",
+            "us-east-2",
+            "--no-stale-check",
+            "--format",
+            "json",
+        ])
+        .args([
+            "--operating-system",
+            operating_system,
+            "--architecture",
+            "all",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let selected: Vec<Value> = serde_json::from_slice(&output.stdout).unwrap();
+    selected
+        .iter()
+        .map(|detail| {
+            (
+                detail["name"].as_str().unwrap_or_default().to_string(),
+                detail["ami"].as_str().unwrap_or_default().to_string(),
+            )
+        })
+        .collect()
+}
+
+fn check(operating_system: &str, expected_count: Option<usize>) {
+    if !live() {
+        return;
+    }
+    let selected = select(operating_system);
+    let names: Vec<&str> = selected.iter().map(|(name, _)| name.as_str()).collect();
+    assert!(
+        !selected.is_empty(),
+        "nothing selected for {}",
+        operating_system
+    );
+    if let Some(expected_count) = expected_count {
+        assert_eq!(selected.len(), expected_count, "{:?}", names);
+        let amd64 = names
+            .iter()
+            .any(|n| n.contains("amd64") || n.contains("x86_64"));
+        let arm64 = names.iter().any(|n| n.contains("arm64"));
+        assert!(amd64 && arm64, "both architectures expected in {:?}", names);
+    }
+    let ami = Regex::new(r"^ami-[0-9a-f]{8}([0-9a-f]{9})?$").unwrap();
+    for (name, id) in selected.iter() {
+        assert!(ami.is_match(id), "{} has the AMI id {:?}", name, id);
+    }
+}
+
+#[test]
+fn amazon() {
+    check("amazon", Some(2));
+}
+
+#[test]
+fn debian() {
+    check("debian", Some(2));
+}
+
+#[test]
+fn ubuntu() {
+    check("ubuntu", Some(2));
+}
+
+// Windows Server is only published for amd64.
+#[test]
+fn windows() {
+    check("windows", None);
+}